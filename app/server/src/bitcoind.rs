@@ -0,0 +1,109 @@
+//! Minimal bitcoind JSON-RPC client, used as a broadcast fallback
+//!
+//! Only `sendrawtransaction` is needed, so instead of pulling in an HTTP
+//! client this speaks just enough HTTP/1.1 to POST one JSON-RPC call over
+//! plain TCP (bitcoind's RPC has no TLS). See `config::bitcoind_rpc`.
+
+use anyhow::{anyhow, Context, Result};
+use bitcoin::base64::engine::general_purpose::STANDARD as BASE64;
+use bitcoin::base64::Engine;
+use serde_json::Value;
+use std::fs;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::config::{BitcoindAuth, BitcoindRpcConfig};
+
+/// Upper bound for one RPC round trip
+const RPC_TIMEOUT: Duration = Duration::from_secs(20);
+
+pub struct BitcoindRpc {
+    config: BitcoindRpcConfig,
+}
+
+impl BitcoindRpc {
+    pub fn new(config: BitcoindRpcConfig) -> Self {
+        Self { config }
+    }
+
+    /// `host:port` the RPC is reached at
+    pub fn addr(&self) -> &str {
+        &self.config.addr
+    }
+
+    /// Broadcast a raw transaction; returns its txid
+    pub async fn send_raw_transaction(&self, tx_hex: &str) -> Result<String> {
+        let result = self.call("sendrawtransaction", serde_json::json!([tx_hex])).await?;
+
+        result
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Unexpected sendrawtransaction result: {}", result))
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let body = serde_json::json!({
+            "jsonrpc": "1.0",
+            "id": "nomad",
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nAuthorization: Basic {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.config.addr,
+            BASE64.encode(self.credentials()?),
+            body.len(),
+            body
+        );
+
+        let response = timeout(RPC_TIMEOUT, async {
+            let mut stream = TcpStream::connect(&self.config.addr).await?;
+            stream.write_all(request.as_bytes()).await?;
+
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await?;
+            Ok::<_, std::io::Error>(response)
+        })
+        .await
+        .map_err(|_| anyhow!("bitcoind RPC timeout"))?
+        .context("bitcoind RPC connection failed")?;
+
+        let response = String::from_utf8_lossy(&response);
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| anyhow!("Malformed bitcoind RPC response"))?;
+
+        let status = head.split_whitespace().nth(1).unwrap_or("");
+        if status == "401" {
+            return Err(anyhow!("bitcoind RPC authentication failed"));
+        }
+
+        // Errors (e.g. a rejected tx) come back as HTTP 500 with a JSON body
+        let reply: Value = serde_json::from_str(body.trim())
+            .map_err(|_| anyhow!("bitcoind RPC returned HTTP {}", status))?;
+
+        match reply.get("error") {
+            Some(error) if !error.is_null() => Err(anyhow!(
+                "{}",
+                error.get("message").and_then(Value::as_str).unwrap_or("unknown bitcoind error")
+            )),
+            _ => Ok(reply.get("result").cloned().unwrap_or(Value::Null)),
+        }
+    }
+
+    /// `user:password`; the cookie is re-read since bitcoind rewrites it on
+    /// every start
+    fn credentials(&self) -> Result<String> {
+        match &self.config.auth {
+            BitcoindAuth::Cookie(path) => Ok(fs::read_to_string(path)
+                .with_context(|| format!("Failed to read bitcoind cookie {}", path.display()))?
+                .trim()
+                .to_string()),
+            BitcoindAuth::UserPass(user, password) => Ok(format!("{}:{}", user, password)),
+        }
+    }
+}
@@ -2,8 +2,18 @@
 //! 
 //! Handles Umbrel-specific configuration and environment variables.
 
+use anyhow::{anyhow, Context, Result};
+use bitcoin::Network;
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::nostr_handler::{
+    NOMAD_SERVER_REQUEST_KIND, NOMAD_SERVER_RESPONSE_KIND, SUPPORTED_REQUEST_TYPES,
+};
 
 /// Get the Umbrel app data directory
 /// 
@@ -18,6 +28,52 @@ pub fn get_data_dir() -> PathBuf {
         })
 }
 
+/// Make sure the data directory exists, is private and writable
+///
+/// Missing directories are created with mode 0700. Fails if the path is not
+/// a directory, is accessible by other users, or can't be written to.
+pub fn ensure_data_dir(path: &Path) -> Result<()> {
+    if !path.exists() {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder
+            .create(path)
+            .with_context(|| format!("Failed to create data dir {}", path.display()))?;
+    }
+
+    let meta = fs::metadata(path)
+        .with_context(|| format!("Failed to stat data dir {}", path.display()))?;
+
+    if !meta.is_dir() {
+        return Err(anyhow!("Data dir {} is not a directory", path.display()));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = meta.permissions().mode();
+        if mode & 0o007 != 0 {
+            return Err(anyhow!(
+                "Data dir {} is accessible by other users (mode {:o}); run chmod 700",
+                path.display(),
+                mode & 0o777
+            ));
+        }
+    }
+
+    let probe = path.join(".write_test");
+    fs::write(&probe, b"ok")
+        .with_context(|| format!("Data dir {} is not writable", path.display()))?;
+    let _ = fs::remove_file(&probe);
+
+    Ok(())
+}
+
 /// Get the Umbrel app ID
 /// 
 /// Umbrel sets UMBREL_APP_ID to identify the app instance.
@@ -25,3 +81,602 @@ pub fn get_app_id() -> Option<String> {
     env::var("UMBREL_APP_ID").ok()
 }
 
+
+/// Get the Bitcoin network the server operates on
+///
+/// Reads NOMAD_NETWORK (mainnet, testnet, signet or regtest); defaults to mainnet.
+/// Address validation and xpub derivation follow this network.
+pub fn get_network() -> Network {
+    let value = match env::var("NOMAD_NETWORK") {
+        Ok(v) => v,
+        Err(_) => return Network::Bitcoin,
+    };
+
+    match value.trim().to_lowercase().as_str() {
+        "" | "mainnet" | "bitcoin" => Network::Bitcoin,
+        "testnet" => Network::Testnet,
+        "signet" => Network::Signet,
+        "regtest" => Network::Regtest,
+        other => {
+            warn!("Unknown NOMAD_NETWORK '{}', defaulting to mainnet", other);
+            Network::Bitcoin
+        }
+    }
+}
+
+/// Whether logs should be emitted as JSON lines
+///
+/// Set NOMAD_LOG_FORMAT=json for log aggregators; anything else keeps the
+/// default human-readable text format.
+pub fn log_format_json() -> bool {
+    env::var("NOMAD_LOG_FORMAT")
+        .map(|v| v.eq_ignore_ascii_case("json"))
+        .unwrap_or(false)
+}
+
+/// How many of an address's transactions get their amount resolved
+///
+/// Resolving an amount costs Electrs roundtrips per tx, so busy addresses
+/// only get the newest NOMAD_MAX_RESOLVED_TXS (default 100) resolved; older
+/// ones are returned with `amount: null`.
+pub fn max_resolved_txs() -> usize {
+    env_parse("NOMAD_MAX_RESOLVED_TXS").unwrap_or(100)
+}
+
+/// Responses larger than this (bytes of JSON) are sent gzip-compressed
+///
+/// NOMAD_COMPRESS_THRESHOLD, default 32768. 0 disables compression.
+pub fn compress_threshold() -> usize {
+    env_parse("NOMAD_COMPRESS_THRESHOLD").unwrap_or(32 * 1024)
+}
+
+/// Largest event content (bytes) sent as one response event
+///
+/// Bigger responses (after compression) are split across several events;
+/// NOMAD_CHUNK_SIZE, default 49152, stays under common 64KB relay limits.
+pub fn chunk_size() -> usize {
+    env_parse("NOMAD_CHUNK_SIZE").unwrap_or(48 * 1024).max(1024)
+}
+
+/// Largest serialized response event (bytes) relays are expected to accept
+///
+/// NOMAD_MAX_EVENT_BYTES, default 61440. Response chunks are shrunk until
+/// every signed event, id, signature and tags included, fits.
+pub fn max_event_bytes() -> usize {
+    env_parse("NOMAD_MAX_EVENT_BYTES").unwrap_or(60 * 1024).max(4096)
+}
+
+/// Most relays the Nostr client connects to at once
+///
+/// NOMAD_MAX_RELAYS; unset or 0 means no limit. Relays past the first N of
+/// the configured list are kept on standby and replace active relays that
+/// stay down (see `nostr::run_relay_monitor`).
+pub fn max_relays() -> Option<usize> {
+    env_parse("NOMAD_MAX_RELAYS").filter(|n: &usize| *n > 0)
+}
+
+/// Most addresses accepted in one `get_utxos` or multi-address lookup
+///
+/// NOMAD_MAX_REQUEST_ADDRESSES, default 200. Larger lists are refused with
+/// `too_many_addresses` before any Electrs call.
+pub fn max_request_addresses() -> usize {
+    env_parse("NOMAD_MAX_REQUEST_ADDRESSES").unwrap_or(200)
+}
+
+/// Whether every request and response is written to the debug dump
+///
+/// NOMAD_DEBUG_DUMP, default false. See `debugdump`; the file holds
+/// wallet addresses and xpubs in the clear, so only enable it to debug.
+pub fn debug_dump() -> bool {
+    env::var("NOMAD_DEBUG_DUMP").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
+/// Size (bytes) at which the debug dump is rotated
+///
+/// NOMAD_DEBUG_DUMP_MAX_BYTES, default 10 MiB.
+pub fn debug_dump_max_bytes() -> u64 {
+    env_parse("NOMAD_DEBUG_DUMP_MAX_BYTES").unwrap_or(10 * 1024 * 1024).max(4096)
+}
+
+/// Whether responses carry `seq` and `ts` fields
+///
+/// NOMAD_RESPONSE_SEQ, default true. Advertised as `response_seq` in
+/// `get_capabilities`; wallets that don't know it ignore the extra fields.
+pub fn response_seq() -> bool {
+    env_parse("NOMAD_RESPONSE_SEQ").unwrap_or(true)
+}
+
+/// Requests processed concurrently by the Nostr handler
+///
+/// NOMAD_MAX_CONCURRENT_REQUESTS, default 4. Keep it low on small nodes;
+/// further requests queue until a slot frees up.
+pub fn max_concurrent_requests() -> usize {
+    env_parse("NOMAD_MAX_CONCURRENT_REQUESTS").unwrap_or(4).max(1)
+}
+
+/// How far back the request subscription reaches when the server starts
+///
+/// NOMAD_REQUEST_LOOKBACK_SECS, default 60. Older requests were most likely
+/// abandoned by the wallet and are not answered. 0 disables the window, so
+/// only the processed-request checkpoint limits replay.
+pub fn request_lookback() -> Option<Duration> {
+    let secs: u64 = env_parse("NOMAD_REQUEST_LOOKBACK_SECS").unwrap_or(60);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// How long startup keeps retrying to reach and warm up Electrs
+///
+/// NOMAD_WARMUP_MAX_WAIT_SECS, default 60. Electrs is often still starting
+/// when the server boots (e.g. on Umbrel); after this the server starts
+/// anyway and keeps retrying the warm-up in the background. 0 tries once.
+pub fn warmup_max_wait() -> Duration {
+    Duration::from_secs(env_parse("NOMAD_WARMUP_MAX_WAIT_SECS").unwrap_or(60))
+}
+
+/// Request types the Nostr handler will serve
+///
+/// NOMAD_ENABLED_REQUESTS is a comma-separated list, e.g.
+/// `bitcoin_lookup,get_fees` for a read-only server that never broadcasts.
+/// Unset or empty enables everything. Other types are answered with a
+/// `disabled` error; `get_capabilities` is always served so wallets can
+/// discover what is enabled.
+pub fn enabled_requests() -> Vec<String> {
+    let raw = env::var("NOMAD_ENABLED_REQUESTS").unwrap_or_default();
+
+    let mut enabled: Vec<String> = Vec::new();
+    for name in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if !SUPPORTED_REQUEST_TYPES.contains(&name) {
+            warn!("Ignoring unknown request type '{}' in NOMAD_ENABLED_REQUESTS", name);
+            continue;
+        }
+        if !enabled.iter().any(|e| e == name) {
+            enabled.push(name.to_string());
+        }
+    }
+
+    if enabled.is_empty() {
+        return SUPPORTED_REQUEST_TYPES.iter().map(|t| t.to_string()).collect();
+    }
+
+    if !enabled.iter().any(|e| e == "get_capabilities") {
+        enabled.push("get_capabilities".to_string());
+    }
+
+    enabled
+}
+
+/// Browser origins allowed to fetch the read-only HTTP routes
+///
+/// NOMAD_CORS_ORIGINS is a comma-separated list, e.g.
+/// `https://dash.example.com,http://localhost:5173`; `*` allows any origin.
+/// Unset means no CORS headers (same-origin only). Admin routes never get
+/// CORS headers.
+pub fn cors_origins() -> Vec<String> {
+    env::var("NOMAD_CORS_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|o| o.trim().trim_end_matches('/').to_string())
+        .filter(|o| !o.is_empty())
+        .collect()
+}
+
+/// Names of the extra identities (tenants) hosted by this process
+///
+/// NOMAD_TENANTS is a comma-separated list, e.g. `alice,bob`. Each tenant
+/// keeps its key and pairings under `<data dir>/tenants/<name>/`. Names may
+/// only use ASCII letters, digits, `-` and `_`; others are ignored.
+pub fn tenants() -> Vec<String> {
+    let raw = env::var("NOMAD_TENANTS").unwrap_or_default();
+
+    let mut tenants: Vec<String> = Vec::new();
+    for name in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let valid = name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            warn!("Ignoring invalid tenant name '{}' in NOMAD_TENANTS", name);
+            continue;
+        }
+        if !tenants.iter().any(|t| t == name) {
+            tenants.push(name.to_string());
+        }
+    }
+
+    tenants
+}
+
+/// Oldest Electrs tip considered synced
+///
+/// Electrum does not expose bitcoind's height, so `/health/electrs` judges
+/// sync by the age of Electrs's tip header. NOMAD_ELECTRS_MAX_TIP_AGE is in
+/// seconds (default 7200, i.e. roughly 12 blocks).
+pub fn electrs_max_tip_age() -> Duration {
+    Duration::from_secs(env_parse("NOMAD_ELECTRS_MAX_TIP_AGE").unwrap_or(7200))
+}
+
+/// Per-requester rate limit for Nostr requests
+///
+/// Each requester pubkey may send `max_requests` requests per `window`.
+/// Override with NOMAD_RATE_LIMIT_REQUESTS and NOMAD_RATE_LIMIT_WINDOW_SECS.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    pub max_requests: u32,
+    pub window: Duration,
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_requests: env_parse("NOMAD_RATE_LIMIT_REQUESTS").unwrap_or(10),
+            window: Duration::from_secs(env_parse("NOMAD_RATE_LIMIT_WINDOW_SECS").unwrap_or(10)),
+        }
+    }
+}
+
+/// How long a response publish waits for relay acknowledgements
+///
+/// Every strategy sends to all write relays; they differ in when the
+/// publish returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublishStrategy {
+    /// Wait for every write relay; one acceptance is enough (`all`)
+    All,
+    /// Return on the first acceptance (`first-ack`)
+    FirstAck,
+    /// Return once N relays accepted; fewer is an error (`quorum-N`)
+    Quorum(usize),
+}
+
+impl PublishStrategy {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "all" => Some(Self::All),
+            "first-ack" => Some(Self::FirstAck),
+            other => other
+                .strip_prefix("quorum-")
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .map(Self::Quorum),
+        }
+    }
+}
+
+/// How responses are published to relays
+///
+/// Each send waits at most `timeout` (NOMAD_PUBLISH_TIMEOUT_SECS, default
+/// 10). `strategy` comes from NOMAD_PUBLISH_STRATEGY (`all`, `first-ack`
+/// or `quorum-N`, default `all`); the older NOMAD_PUBLISH_QUORUM=N still
+/// means `quorum-N` when no strategy is set. Relays that did not accept a
+/// response are retried once while the strategy is unmet.
+#[derive(Debug, Clone, Copy)]
+pub struct PublishConfig {
+    pub timeout: Duration,
+    pub strategy: PublishStrategy,
+}
+
+impl PublishConfig {
+    pub fn from_env() -> Self {
+        let strategy = match env::var("NOMAD_PUBLISH_STRATEGY") {
+            Ok(value) => PublishStrategy::parse(&value).unwrap_or_else(|| {
+                warn!("Unknown NOMAD_PUBLISH_STRATEGY '{}', using all", value);
+                PublishStrategy::All
+            }),
+            Err(_) => env_parse("NOMAD_PUBLISH_QUORUM")
+                .filter(|n: &usize| *n > 0)
+                .map_or(PublishStrategy::All, PublishStrategy::Quorum),
+        };
+
+        Self {
+            timeout: Duration::from_secs(env_parse("NOMAD_PUBLISH_TIMEOUT_SECS").unwrap_or(10)),
+            strategy,
+        }
+    }
+}
+
+/// Per-request-type Electrs timeouts for the Nostr handler
+///
+/// Each is read from NOMAD_TIMEOUT_<NAME> in seconds, e.g.
+/// NOMAD_TIMEOUT_BALANCE=60 or NOMAD_TIMEOUT_BROADCAST=10.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeoutConfig {
+    pub balance: Duration,
+    /// Address history and per-tx amount lookups
+    pub txs: Duration,
+    pub utxos: Duration,
+    pub broadcast: Duration,
+    pub fees: Duration,
+    pub mempool: Duration,
+    pub history: Duration,
+    pub proxy: Duration,
+    pub tx_details: Duration,
+    /// Per chain (receive / change) of a `scan_xpub`
+    pub xpub_scan: Duration,
+}
+
+impl TimeoutConfig {
+    pub fn from_env() -> Self {
+        let secs = |name: &str, default: u64| {
+            Duration::from_secs(env_parse(name).unwrap_or(default).max(1))
+        };
+
+        Self {
+            balance: secs("NOMAD_TIMEOUT_BALANCE", 30),
+            txs: secs("NOMAD_TIMEOUT_TXS", 20),
+            utxos: secs("NOMAD_TIMEOUT_UTXOS", 45),
+            broadcast: secs("NOMAD_TIMEOUT_BROADCAST", 30),
+            fees: secs("NOMAD_TIMEOUT_FEES", 30),
+            mempool: secs("NOMAD_TIMEOUT_MEMPOOL", 30),
+            history: secs("NOMAD_TIMEOUT_HISTORY", 45),
+            proxy: secs("NOMAD_TIMEOUT_PROXY", 30),
+            tx_details: secs("NOMAD_TIMEOUT_TX_DETAILS", 30),
+            xpub_scan: secs("NOMAD_TIMEOUT_XPUB_SCAN", 120),
+        }
+    }
+}
+
+/// Transport for the Electrum protocol connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElectrsProtocol {
+    /// Plain TCP (electrs default, port 50001)
+    Tcp,
+    /// TLS (Fulcrum / ElectrumX SSL ports, usually 50002)
+    Ssl,
+}
+
+impl ElectrsProtocol {
+    /// URL scheme understood by electrum-client
+    pub fn scheme(self) -> &'static str {
+        match self {
+            ElectrsProtocol::Tcp => "tcp",
+            ElectrsProtocol::Ssl => "ssl",
+        }
+    }
+}
+
+/// Read NOMAD_ELECTRS_PROTOCOL (tcp or ssl); defaults to tcp
+///
+/// Any Electrum server works (electrs, Fulcrum, ElectrumX). The esplora
+/// HTTP REST API is not supported; point ELECTRS_ADDR at the Electrum port.
+fn electrs_protocol() -> ElectrsProtocol {
+    let value = env::var("NOMAD_ELECTRS_PROTOCOL").unwrap_or_default();
+
+    match value.trim().to_lowercase().as_str() {
+        "" | "tcp" | "electrum" => ElectrsProtocol::Tcp,
+        "ssl" | "tls" => ElectrsProtocol::Ssl,
+        "rest" | "http" | "https" | "esplora" => {
+            warn!("NOMAD_ELECTRS_PROTOCOL '{}' is not supported (Electrum protocol only), using tcp", value);
+            ElectrsProtocol::Tcp
+        }
+        other => {
+            warn!("Unknown NOMAD_ELECTRS_PROTOCOL '{}', defaulting to tcp", other);
+            ElectrsProtocol::Tcp
+        }
+    }
+}
+
+/// Electrs (Electrum protocol) connection settings
+///
+/// The server keeps one persistent Electrum connection, so there is no pool
+/// to size; instead this controls how fast a dead backend is detected:
+/// - `protocol`: tcp or ssl (NOMAD_ELECTRS_PROTOCOL)
+/// - `connect_timeout`: TCP connect budget (NOMAD_ELECTRS_CONNECT_TIMEOUT_SECS)
+/// - `socket_timeout`: per-RPC read/write timeout, unset = none
+///   (NOMAD_ELECTRS_SOCKET_TIMEOUT_SECS)
+/// - `retry`: reconnect attempts after a broken connection (NOMAD_ELECTRS_RETRY)
+/// - `proxy`: SOCKS5 proxy, e.g. Tor for a `.onion` ELECTRS_ADDR (see `socks_proxy`)
+/// - `cert_fingerprint`: SHA-256 of the server's TLS certificate, hex with
+///   optional colons (NOMAD_ELECTRS_CERT_FINGERPRINT); ssl only
+#[derive(Debug, Clone)]
+pub struct ElectrsConnectionConfig {
+    pub protocol: ElectrsProtocol,
+    pub connect_timeout: Duration,
+    pub socket_timeout: Option<u8>,
+    pub retry: u8,
+    pub proxy: Option<SocksProxy>,
+    pub cert_fingerprint: Option<String>,
+}
+
+impl ElectrsConnectionConfig {
+    pub fn from_env() -> Self {
+        Self {
+            protocol: electrs_protocol(),
+            connect_timeout: Duration::from_secs(
+                env_parse("NOMAD_ELECTRS_CONNECT_TIMEOUT_SECS").unwrap_or(3),
+            ),
+            socket_timeout: env_parse("NOMAD_ELECTRS_SOCKET_TIMEOUT_SECS"),
+            retry: env_parse("NOMAD_ELECTRS_RETRY").unwrap_or(1),
+            proxy: socks_proxy(),
+            cert_fingerprint: env::var("NOMAD_ELECTRS_CERT_FINGERPRINT")
+                .ok()
+                .filter(|v| !v.trim().is_empty()),
+        }
+    }
+}
+
+/// SOCKS5 proxy (usually Tor) for Electrs and Nostr relay connections
+#[derive(Debug, Clone, Copy)]
+pub struct SocksProxy {
+    pub addr: SocketAddr,
+    /// Only `.onion` hosts go through the proxy
+    pub onion_only: bool,
+}
+
+impl SocksProxy {
+    /// Whether connections to `host` should use the proxy
+    pub fn applies_to(&self, host: &str) -> bool {
+        !self.onion_only || host.trim_end_matches('.').ends_with(".onion")
+    }
+}
+
+/// Read NOMAD_SOCKS_PROXY, e.g. `127.0.0.1:9050` or `socks5://tor:9050`
+///
+/// The value is `host:port` with an optional `socks5://` prefix; hostnames
+/// are resolved once at startup. By default only `.onion` Electrs / relay
+/// hosts use the proxy; set NOMAD_SOCKS_ONION_ONLY=false to route everything
+/// through it.
+pub fn socks_proxy() -> Option<SocksProxy> {
+    let raw = env::var("NOMAD_SOCKS_PROXY").ok()?;
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return None;
+    }
+
+    let host_port = raw.strip_prefix("socks5://").unwrap_or(raw).trim_end_matches('/');
+
+    let addr = match host_port.to_socket_addrs().map(|mut a| a.next()) {
+        Ok(Some(addr)) => addr,
+        Ok(None) | Err(_) => {
+            warn!("Ignoring NOMAD_SOCKS_PROXY '{}': expected host:port", raw);
+            return None;
+        }
+    };
+
+    Some(SocksProxy {
+        addr,
+        onion_only: env_parse("NOMAD_SOCKS_ONION_ONLY").unwrap_or(true),
+    })
+}
+
+/// Credentials for bitcoind's RPC
+#[derive(Debug, Clone)]
+pub enum BitcoindAuth {
+    /// Path to bitcoind's `.cookie` file
+    Cookie(PathBuf),
+    UserPass(String, String),
+}
+
+/// bitcoind RPC used when broadcasting through Electrs fails
+#[derive(Debug, Clone)]
+pub struct BitcoindRpcConfig {
+    /// `host:port`
+    pub addr: String,
+    pub auth: BitcoindAuth,
+}
+
+/// Read BITCOIND_RPC_URL, e.g. `http://bitcoind:8332`
+///
+/// Authenticates with the cookie at BITCOIND_RPC_COOKIE or with
+/// BITCOIND_RPC_USER / BITCOIND_RPC_PASSWORD. Unset (or without
+/// credentials) disables the broadcast fallback.
+pub fn bitcoind_rpc() -> Option<BitcoindRpcConfig> {
+    let raw = env::var("BITCOIND_RPC_URL").ok()?;
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return None;
+    }
+
+    if raw.starts_with("https://") {
+        warn!("Ignoring BITCOIND_RPC_URL '{}': bitcoind RPC is plain http", raw);
+        return None;
+    }
+    let addr = raw.strip_prefix("http://").unwrap_or(raw).trim_end_matches('/');
+    if addr.rsplit_once(':').is_none_or(|(_, port)| port.parse::<u16>().is_err()) {
+        warn!("Ignoring BITCOIND_RPC_URL '{}': expected http://host:port", raw);
+        return None;
+    }
+
+    let cookie = env::var("BITCOIND_RPC_COOKIE").ok().filter(|v| !v.trim().is_empty());
+    let user = env::var("BITCOIND_RPC_USER").ok().filter(|v| !v.is_empty());
+    let password = env::var("BITCOIND_RPC_PASSWORD").ok();
+
+    let auth = match (cookie, user, password) {
+        (Some(cookie), _, _) => BitcoindAuth::Cookie(PathBuf::from(cookie.trim())),
+        (None, Some(user), Some(password)) => BitcoindAuth::UserPass(user, password),
+        _ => {
+            warn!("Ignoring BITCOIND_RPC_URL: set BITCOIND_RPC_COOKIE or BITCOIND_RPC_USER/PASSWORD");
+            return None;
+        }
+    };
+
+    Some(BitcoindRpcConfig {
+        addr: addr.to_string(),
+        auth,
+    })
+}
+
+/// `get_fees` fallback and sanity limits (sat/vB)
+///
+/// `fallback` is returned when Electrs can't estimate
+/// (NOMAD_FALLBACK_FEE_FAST / _MEDIUM / _SLOW, default 10/5/1); estimates
+/// above `ceiling` (NOMAD_FEE_CEILING, default 1000) are clamped to it.
+#[derive(Debug, Clone, Copy)]
+pub struct FeeConfig {
+    pub fallback: (u64, u64, u64),
+    pub ceiling: u64,
+}
+
+impl FeeConfig {
+    pub fn from_env() -> Self {
+        Self {
+            fallback: (
+                env_parse("NOMAD_FALLBACK_FEE_FAST").unwrap_or(10),
+                env_parse("NOMAD_FALLBACK_FEE_MEDIUM").unwrap_or(5),
+                env_parse("NOMAD_FALLBACK_FEE_SLOW").unwrap_or(1),
+            ),
+            ceiling: env_parse("NOMAD_FEE_CEILING").unwrap_or(1000).max(1),
+        }
+    }
+}
+
+/// Address subscription (`subscribe_address`) settings
+///
+/// Subscriptions expire after `idle_timeout` without renewal
+/// (NOMAD_SUBSCRIPTION_IDLE_SECS); watched addresses are polled every
+/// `poll_interval` (NOMAD_SUBSCRIPTION_POLL_SECS).
+#[derive(Debug, Clone, Copy)]
+pub struct SubscriptionConfig {
+    pub idle_timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+impl SubscriptionConfig {
+    pub fn from_env() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(
+                env_parse("NOMAD_SUBSCRIPTION_IDLE_SECS").unwrap_or(3600),
+            ),
+            poll_interval: Duration::from_secs(
+                env_parse("NOMAD_SUBSCRIPTION_POLL_SECS").unwrap_or(60).max(1),
+            ),
+        }
+    }
+}
+
+/// Nostr event kinds used for requests and responses
+///
+/// Override with NOMAD_REQUEST_KIND / NOMAD_RESPONSE_KIND, e.g. to avoid
+/// cross-talk with other NIP-78 app-data users on shared relays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventKinds {
+    pub request: u16,
+    pub response: u16,
+}
+
+impl EventKinds {
+    pub fn from_env() -> Self {
+        let kinds = Self {
+            request: env_parse("NOMAD_REQUEST_KIND").unwrap_or(NOMAD_SERVER_REQUEST_KIND),
+            response: env_parse("NOMAD_RESPONSE_KIND").unwrap_or(NOMAD_SERVER_RESPONSE_KIND),
+        };
+
+        for (name, kind) in [("request", kinds.request), ("response", kinds.response)] {
+            if !(30000..40000).contains(&kind) {
+                warn!(
+                    "Nostr {} kind {} is outside the parameterized-replaceable range (30000-39999)",
+                    name, kind
+                );
+            }
+        }
+
+        if kinds.request == kinds.response {
+            warn!("Nostr request and response kinds are both {}", kinds.request);
+        }
+
+        kinds
+    }
+}
+
+/// Parse an environment variable, ignoring it if unset or malformed
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|v| v.trim().parse().ok())
+}
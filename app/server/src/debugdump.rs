@@ -0,0 +1,130 @@
+//! Request/response dump for protocol debugging (NOMAD_DEBUG_DUMP=1)
+//!
+//! Every received request event and every response sent is appended as
+//! one JSON line to `<data dir>/debug_dump.jsonl`, unredacted. Past
+//! NOMAD_DEBUG_DUMP_MAX_BYTES the file is rotated to `debug_dump.jsonl.1`,
+//! replacing the previous one.
+
+use nostr_sdk::{Event, JsonUtil, PublicKey, Timestamp};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const DUMP_FILENAME: &str = "debug_dump.jsonl";
+
+/// Requests unanswered for this long are no longer timed
+const PENDING_TTL: Duration = Duration::from_secs(600);
+
+struct DumpFile {
+    file: Option<File>,
+    len: u64,
+    /// (requester, req) -> when the request arrived
+    pending: HashMap<(PublicKey, String), Instant>,
+}
+
+pub struct DebugDump {
+    path: PathBuf,
+    max_bytes: u64,
+    inner: Mutex<DumpFile>,
+}
+
+impl DebugDump {
+    /// The dump configured by NOMAD_DEBUG_DUMP, or None when it is off
+    pub fn from_env(data_dir: &Path) -> Option<Self> {
+        if !crate::config::debug_dump() {
+            return None;
+        }
+
+        let path = data_dir.join(DUMP_FILENAME);
+        let max_bytes = crate::config::debug_dump_max_bytes();
+        warn!(
+            path = %path.display(),
+            max_bytes,
+            "Debug dump enabled; requests and responses are written unredacted"
+        );
+
+        Some(Self {
+            path,
+            max_bytes,
+            inner: Mutex::new(DumpFile {
+                file: None,
+                len: 0,
+                pending: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Record a received request event, before any validation
+    pub fn request(&self, event: &Event, req_id: Option<&str>) {
+        let line = json!({
+            "ts": Timestamp::now().as_secs(),
+            "dir": "request",
+            "req": req_id,
+            "event": serde_json::from_str::<serde_json::Value>(&event.as_json()).ok(),
+        });
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(req_id) = req_id {
+            inner.pending.retain(|_, at| at.elapsed() < PENDING_TTL);
+            inner.pending.insert((event.pubkey, req_id.to_string()), Instant::now());
+        }
+        self.append(&mut inner, &line);
+    }
+
+    /// Record a response sent to `to_pubkey`, with the time since its request
+    pub fn response(&self, to_pubkey: PublicKey, req_id: &str, content: &serde_json::Value, events: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        let elapsed_ms = inner
+            .pending
+            .remove(&(to_pubkey, req_id.to_string()))
+            .map(|at| at.elapsed().as_millis() as u64);
+
+        let line = json!({
+            "ts": Timestamp::now().as_secs(),
+            "dir": "response",
+            "req": req_id,
+            "to": to_pubkey.to_hex(),
+            "elapsed_ms": elapsed_ms,
+            "events": events,
+            "content": content,
+        });
+        self.append(&mut inner, &line);
+    }
+
+    fn append(&self, inner: &mut DumpFile, line: &serde_json::Value) {
+        let mut bytes = line.to_string().into_bytes();
+        bytes.push(b'\n');
+
+        if let Err(e) = self.write(inner, &bytes) {
+            // Debugging aid only; never fail a request over it
+            warn!(err = %e, "Failed to write debug dump");
+            inner.file = None;
+        }
+    }
+
+    fn write(&self, inner: &mut DumpFile, bytes: &[u8]) -> std::io::Result<()> {
+        if inner.file.is_some() && inner.len + bytes.len() as u64 > self.max_bytes {
+            inner.file = None;
+            fs::rename(&self.path, self.path.with_extension("jsonl.1"))?;
+            info!(path = %self.path.display(), "Rotated debug dump");
+        }
+
+        if inner.file.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            inner.len = file.metadata()?.len();
+            inner.file = Some(file);
+        }
+
+        if let Some(file) = inner.file.as_mut() {
+            file.write_all(bytes)?;
+            inner.len += bytes.len() as u64;
+        }
+
+        Ok(())
+    }
+}
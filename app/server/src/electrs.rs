@@ -1,47 +1,498 @@
 use anyhow::{anyhow, Result};
-use electrum_client::bitcoin::{Address, Network, ScriptBuf};
-use electrum_client::{Client, ElectrumApi};
+use electrum_client::bitcoin::{Address, Network, Script, ScriptBuf, Txid};
+use electrum_client::raw_client::RawClient;
+use electrum_client::{
+    Batch, Client, ConfigBuilder, ElectrumApi, GetBalanceRes, GetHeadersRes, GetHistoryRes,
+    GetMerkleRes, ListUnspentRes, Param, RawHeaderNotification, ScriptStatus, ServerFeaturesRes,
+    Socks5Config,
+};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::net::ToSocketAddrs;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 use tokio::sync::Semaphore;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
+
+use crate::bitcoind::BitcoindRpc;
+use crate::error::ErrorCode;
+
+/// How long a fee estimate is served from cache
+const FEE_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Scripts per `blockchain.scripthash.listunspent` batch
+const UTXO_BATCH_SIZE: usize = 50;
+
+/// How long a broadcast waits on Electrs before also trying bitcoind
+const BITCOIND_FALLBACK_AFTER: Duration = Duration::from_secs(10);
+
+/// Read-only Electrum methods `electrs_proxy` may call
+///
+/// Anything that mutates server state (broadcast) or opens subscriptions is
+/// deliberately left out; those go through the dedicated request types.
+const PROXY_METHODS: &[&str] = &[
+    "blockchain.block.header",
+    "blockchain.block.headers",
+    "blockchain.estimatefee",
+    "blockchain.relayfee",
+    "blockchain.scripthash.get_balance",
+    "blockchain.scripthash.get_history",
+    "blockchain.scripthash.get_mempool",
+    "blockchain.scripthash.listunspent",
+    "blockchain.transaction.get",
+    "blockchain.transaction.get_merkle",
+    "blockchain.transaction.id_from_pos",
+    "mempool.get_fee_histogram",
+    "server.banner",
+    "server.features",
+    "server.version",
+];
+
+/// Longest string parameter accepted by `electrs_proxy` (a 32-byte hex hash)
+const PROXY_MAX_STRING_PARAM: usize = 64;
+
+/// Transactions per `blockchain.transaction.get` batch
+const TX_BATCH_SIZE: usize = 50;
+
+/// Confirmations before a coinbase output can be spent
+const COINBASE_MATURITY: u32 = 100;
+
+/// (fast, medium, slow) fee rates in sat/vB
+type FeeEstimate = (u64, u64, u64);
+
+/// `[fee_rate (sat/vB), vsize]` buckets, highest fee rate first
+type FeeHistogram = Vec<(f64, u64)>;
+
+/// Size and fee details of a single transaction
+#[derive(Debug, Clone)]
+pub struct TxDetails {
+    pub txid: String,
+    /// Fee in sats; None for coinbase or when prevouts can't be fetched
+    pub fee: Option<u64>,
+    pub vsize: u64,
+    pub weight: u64,
+}
+
+/// bitcoind's default limit on in-mempool ancestors (including the tx)
+const MAX_MEMPOOL_ANCESTORS: u32 = 25;
+
+/// Client name sent with `server.version`
+const CLIENT_NAME: &str = concat!("NomadServer/", env!("CARGO_PKG_VERSION"));
+
+/// Electrum protocol version requested with `server.version`
+const ELECTRUM_PROTOCOL_VERSION: &str = "1.4";
+
+/// Unconfirmed ancestor package of a transaction, like bitcoind's
+/// `ancestorcount` / `ancestorfees` / `ancestorsize` (the tx itself included)
+///
+/// The totals are None once the transaction is confirmed.
+#[derive(Debug, Clone)]
+pub struct TxAncestry {
+    pub txid: String,
+    pub confirmed: bool,
+    pub ancestor_count: Option<u32>,
+    /// Sats
+    pub ancestor_fees: Option<u64>,
+    pub ancestor_vsize: Option<u64>,
+}
+
+/// One entry of an address's history
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub txid: String,
+    /// None while in the mempool
+    pub height: Option<u32>,
+}
+
+/// Balance and history of an Electrum scripthash
+#[derive(Debug, Clone)]
+pub struct ScripthashSummary {
+    pub confirmed: u64,
+    /// Negative while unconfirmed txs spend confirmed coins
+    pub unconfirmed: i64,
+    /// Oldest first
+    pub history: Vec<HistoryEntry>,
+}
+
+/// A successful broadcast and the backend that accepted it
+#[derive(Debug, Clone)]
+pub struct Broadcast {
+    pub txid: String,
+    /// `electrs`, or `bitcoind` for the BITCOIND_RPC_URL fallback
+    pub via: &'static str,
+}
+
+/// Hash and header timestamp of a block
+#[derive(Debug, Clone)]
+pub struct BlockInfo {
+    pub hash: String,
+    pub time: u32,
+}
+
+/// Confirmation state of a transaction
+#[derive(Debug, Clone)]
+pub struct TxStatus {
+    pub txid: String,
+    pub confirmed: bool,
+    pub block_height: Option<u32>,
+    /// 0 while in the mempool
+    pub confirmations: u32,
+}
+
+/// Mempool summary built from Electrum's `mempool.get_fee_histogram`
+///
+/// The Electrum protocol does not expose a mempool transaction count, so
+/// totals are derived from the histogram buckets.
+#[derive(Debug, Clone)]
+pub struct MempoolInfo {
+    pub total_vsize: u64,
+    /// Approximate total fees in sats (bucket fee rate * bucket vsize)
+    pub total_fee: u64,
+    /// `[fee_rate (sat/vB), vsize]` buckets, highest fee rate first
+    pub fee_histogram: Vec<(f64, u64)>,
+}
+
+/// Electrs chain tip and how far behind it appears to be
+///
+/// Lag is estimated from the tip header's timestamp (one block per ten
+/// minutes), since Electrum offers no way to ask bitcoind for its height.
+#[derive(Debug, Clone)]
+pub struct SyncStatus {
+    pub tip_height: u32,
+    /// Tip header timestamp (unix seconds)
+    pub tip_time: u32,
+    pub tip_age_secs: u64,
+    /// Estimated blocks behind the network tip
+    pub lag_blocks: u64,
+    /// Tip is younger than NOMAD_ELECTRS_MAX_TIP_AGE (always true on regtest)
+    pub synced: bool,
+}
+
+/// UTXOs of several addresses; addresses that could not be queried are
+/// listed instead of failing the whole lookup
+#[derive(Debug, Clone, Default)]
+pub struct UtxoSet {
+    pub utxos: Vec<crate::nostr_handler::UtxoInfo>,
+    pub failed_addresses: Vec<String>,
+}
+
+/// One page of an address's transaction history, newest first
+#[derive(Debug, Clone)]
+pub struct HistoryPage {
+    pub txids: Vec<String>,
+    /// Older transactions exist beyond this page
+    pub has_more: bool,
+}
+
+/// Outcome of a `test_mempool_accept` dry run
+#[derive(Debug, Clone)]
+pub struct MempoolAcceptResult {
+    pub txid: String,
+    pub allowed: bool,
+    /// bitcoind-style reject reason (e.g. "missing-inputs") when not allowed
+    pub reject_reason: Option<String>,
+    /// Fee in sats, when all prevouts could be resolved
+    pub fee: Option<u64>,
+    pub vsize: u64,
+}
+
+/// Lookup future shared between concurrent callers (errors as code and
+/// message so it's Clone)
+type SharedLookup<T> = Shared<BoxFuture<'static, Result<T, (ErrorCode, String)>>>;
+
+/// Deduplicates concurrent identical lookups
+///
+/// The first caller for a key runs the lookup; callers arriving while it is
+/// in flight await the same shared future instead of issuing their own.
+struct SingleFlight<T: Clone> {
+    inflight: Mutex<HashMap<String, SharedLookup<T>>>,
+}
+
+impl<T: Clone + Send + Sync + 'static> SingleFlight<T> {
+    fn new() -> Self {
+        Self {
+            inflight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn run<F>(self: &Arc<Self>, key: String, fut: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>> + Send + 'static,
+    {
+        let shared = {
+            let mut inflight = self.inflight.lock().unwrap();
+
+            match inflight.get(&key) {
+                Some(existing) => existing.clone(),
+                None => {
+                    let this = Arc::clone(self);
+                    let k = key.clone();
+                    let shared = async move {
+                        let res = fut.await.map_err(|e| (ErrorCode::of(&e), e.to_string()));
+                        this.inflight.lock().unwrap().remove(&k);
+                        res
+                    }
+                    .boxed()
+                    .shared();
+
+                    inflight.insert(key, shared.clone());
+                    shared
+                }
+            }
+        };
+
+        shared.await.map_err(|(code, message)| code.error(message))
+    }
+}
+
+/// How long a cached balance / UTXO set is served without a new block
+const ADDRESS_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// Addresses kept per cache; the least recently used are evicted
+const ADDRESS_CACHE_CAPACITY: usize = 1000;
+
+/// Per-address results, dropped on a new block or after ADDRESS_CACHE_TTL
+struct AddressCache<T: Clone> {
+    entries: Mutex<HashMap<String, (T, Instant, Instant)>>, // value, fetched, last used
+    tip: Mutex<Option<u32>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<T: Clone> AddressCache<T> {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            tip: Mutex::new(None),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn get(&self, address: &str) -> Option<T> {
+        let mut entries = self.entries.lock().unwrap();
+
+        let hit = match entries.get_mut(address) {
+            Some((value, fetched, used)) if fetched.elapsed() < ADDRESS_CACHE_TTL => {
+                *used = Instant::now();
+                Some(value.clone())
+            }
+            _ => None,
+        };
+
+        let counter = if hit.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        hit
+    }
+
+    fn insert(&self, address: &str, value: T) {
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= ADDRESS_CACHE_CAPACITY && !entries.contains_key(address) {
+            entries.retain(|_, (_, fetched, _)| fetched.elapsed() < ADDRESS_CACHE_TTL);
+
+            if entries.len() >= ADDRESS_CACHE_CAPACITY {
+                if let Some(lru) = entries
+                    .iter()
+                    .min_by_key(|(_, (_, _, used))| *used)
+                    .map(|(k, _)| k.clone())
+                {
+                    entries.remove(&lru);
+                }
+            }
+        }
+
+        let now = Instant::now();
+        entries.insert(address.to_string(), (value, now, now));
+    }
+
+    /// Clear everything when the chain tip moves
+    fn observe_tip(&self, height: u32) {
+        let mut tip = self.tip.lock().unwrap();
+
+        if tip.is_some_and(|t| t != height) {
+            self.entries.lock().unwrap().clear();
+            debug!(height, "New block; address cache cleared");
+        }
+        *tip = Some(height);
+    }
+}
+
+/// Address cache hit/miss counters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
 
 #[derive(Clone)]
 pub struct ElectrsClient {
-    client: Arc<Client>,
+    client: Arc<Backend>,
     addr: String,
 
     // Soft rate limit between individual RPC calls
     last_call: Arc<Mutex<Instant>>,
 
+    // RPCs sent over the persistent connection (debug tracing of reuse)
+    rpc_calls: Arc<AtomicU64>,
+
     // Hard global gate: only one in-flight Electrs request at a time
     gate: Arc<Semaphore>,
 
     // Cooldown until this time (set when a timeout happens)
     cooldown_until: Arc<Mutex<Option<Instant>>>,
+
+    // Network addresses are validated against (NOMAD_NETWORK)
+    network: Network,
+
+    // Oldest tip header still considered synced
+    max_tip_age: Duration,
+
+    // Last fee estimate (fast, medium, slow) and when it was fetched
+    fee_cache: Arc<Mutex<Option<(Instant, FeeEstimate)>>>,
+
+    // Last mempool fee histogram, cached like the fee estimate
+    histogram_cache: Arc<Mutex<Option<(Instant, FeeHistogram)>>>,
+
+    // Recent balances / UTXO sets per address (see AddressCache)
+    balance_cache: Arc<AddressCache<(u64, i64)>>,
+    utxo_cache: Arc<AddressCache<Vec<crate::nostr_handler::UtxoInfo>>>,
+
+    // Concurrent identical lookups share one Electrs call
+    balance_flight: Arc<SingleFlight<(u64, i64)>>,
+    txs_flight: Arc<SingleFlight<Vec<HistoryEntry>>>,
+
+    // bitcoind RPC tried when a broadcast via Electrs fails (BITCOIND_RPC_URL)
+    bitcoind: Option<Arc<BitcoindRpc>>,
 }
 
 impl ElectrsClient {
+    /// Client for ELECTRS_ADDR (default `electrs:50001`) on NOMAD_NETWORK
     pub fn new() -> Result<Self> {
         let addr = std::env::var("ELECTRS_ADDR").unwrap_or_else(|_| "electrs:50001".to_string());
-        info!("ElectrsClient using ELECTRS_ADDR={}", addr);
+        let network = crate::config::get_network();
+        let conn = crate::config::ElectrsConnectionConfig::from_env();
+
+        Self::connect(&addr, network, conn)
+    }
+
+    /// Client for an explicit Electrum server, e.g. a local mock or a
+    /// regtest electrs in integration setups
+    pub fn connect(
+        addr: &str,
+        network: Network,
+        conn: crate::config::ElectrsConnectionConfig,
+    ) -> Result<Self> {
+        let addr = addr.to_string();
+        info!("ElectrsClient using ELECTRS_ADDR={} network={}", addr, network);
+        info!(
+            "Electrs connection: protocol={} connect_timeout={:?} socket_timeout={:?} retry={}",
+            conn.protocol.scheme(), conn.connect_timeout, conn.socket_timeout, conn.retry
+        );
+
+        let host = addr.rsplit_once(':').map_or(addr.as_str(), |(host, _)| host);
+        let proxy = conn.proxy.filter(|p| p.applies_to(host));
+
+        let pin = conn
+            .cert_fingerprint
+            .as_deref()
+            .map(parse_cert_fingerprint)
+            .transpose()?;
+        if pin.is_some() && conn.protocol != crate::config::ElectrsProtocol::Ssl {
+            return Err(anyhow!("NOMAD_ELECTRS_CERT_FINGERPRINT requires NOMAD_ELECTRS_PROTOCOL=ssl"));
+        }
+        if pin.is_some() && proxy.is_some() {
+            return Err(anyhow!("NOMAD_ELECTRS_CERT_FINGERPRINT is not supported through a SOCKS proxy"));
+        }
 
-        preflight_tcp(&addr)?;
+        // A .onion address only resolves inside Tor; check the proxy instead
+        match proxy {
+            Some(p) => {
+                info!("Electrs connection via SOCKS5 proxy {}", p.addr);
+                preflight_tcp(&p.addr.to_string(), conn.connect_timeout)?;
+            }
+            None => preflight_tcp(&addr, conn.connect_timeout)?,
+        }
+
+        // The pin replaces CA validation, so self-signed certificates work
+        let client = match pin {
+            Some(pin) => {
+                let target = PinnedTarget {
+                    addr: addr.clone(),
+                    pin,
+                    connect_timeout: conn.connect_timeout,
+                    socket_timeout: conn.socket_timeout.map(|s| Duration::from_secs(s.into())),
+                };
+                let raw = target.connect()?;
+                info!("Electrs TLS certificate matches the pinned fingerprint");
+
+                Backend::Pinned(PinnedClient {
+                    raw: RwLock::new(raw),
+                    target,
+                    retry: conn.retry,
+                })
+            }
+            None => {
+                let config = ConfigBuilder::new()
+                    .timeout(conn.socket_timeout)
+                    .retry(conn.retry)
+                    .socks5(proxy.map(|p| Socks5Config::new(p.addr)))
+                    .build();
+
+                let url = format!("{}://{}", conn.protocol.scheme(), addr);
+                let client = Client::from_config(&url, config)
+                    .map_err(|e| anyhow!("Failed to create electrum client for {}: {}", addr, e))?;
+                Backend::Client(client)
+            }
+        };
 
-        let client = Client::new(&addr)
-            .map_err(|e| anyhow!("Failed to create electrum client for {}: {}", addr, e))?;
+        identify(&client);
 
         Ok(Self {
             client: Arc::new(client),
             addr,
             last_call: Arc::new(Mutex::new(Instant::now())),
+            rpc_calls: Arc::new(AtomicU64::new(0)),
             gate: Arc::new(Semaphore::new(1)),
             cooldown_until: Arc::new(Mutex::new(None)),
+            network,
+            max_tip_age: crate::config::electrs_max_tip_age(),
+            fee_cache: Arc::new(Mutex::new(None)),
+            histogram_cache: Arc::new(Mutex::new(None)),
+            balance_cache: Arc::new(AddressCache::new()),
+            utxo_cache: Arc::new(AddressCache::new()),
+            balance_flight: Arc::new(SingleFlight::new()),
+            txs_flight: Arc::new(SingleFlight::new()),
+            bitcoind: crate::config::bitcoind_rpc().map(|config| {
+                info!("Broadcast fallback via bitcoind RPC at {}", config.addr);
+                Arc::new(BitcoindRpc::new(config))
+            }),
         })
     }
 
+    /// Bitcoin network this client validates addresses against
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Parse an address and check it belongs to the configured network
+    fn parse_address(&self, address: &str) -> Result<Address> {
+        Address::from_str(address)
+            .map_err(|e| ErrorCode::InvalidAddress.error(format!("Invalid address: {}", e)))?
+            .require_network(self.network)
+            .map_err(|e| ErrorCode::InvalidAddress.error(format!("Invalid address: {}", e)))
+    }
+
+    /// Whether `address` parses and belongs to the configured network
+    pub fn is_valid_address(&self, address: &str) -> bool {
+        self.parse_address(address).is_ok()
+    }
+
     pub fn test_connectivity(&self) -> Result<()> {
         self.client
             .ping()
@@ -68,6 +519,39 @@ impl ElectrsClient {
         }
 
         *last = Instant::now();
+
+        let calls = self.rpc_calls.fetch_add(1, Ordering::Relaxed) + 1;
+        debug!(calls, "Electrs RPC over persistent connection");
+    }
+
+    /// Drain queued header notifications and invalidate caches on a new tip
+    ///
+    /// Notifications arrive once any call has subscribed to headers (UTXO
+    /// and sync-status lookups do), so this costs no roundtrip.
+    fn poll_tip(&self) {
+        let mut latest = None;
+        while let Ok(Some(header)) = self.client.block_headers_pop() {
+            latest = Some(header.height as u32);
+        }
+
+        if let Some(height) = latest {
+            self.observe_tip(height);
+        }
+    }
+
+    fn observe_tip(&self, height: u32) {
+        self.balance_cache.observe_tip(height);
+        self.utxo_cache.observe_tip(height);
+    }
+
+    /// Combined hit/miss counts of the balance and UTXO caches
+    pub fn cache_stats(&self) -> CacheStats {
+        let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+
+        CacheStats {
+            hits: load(&self.balance_cache.hits) + load(&self.utxo_cache.hits),
+            misses: load(&self.balance_cache.misses) + load(&self.utxo_cache.misses),
+        }
     }
 
     fn check_cooldown(&self) -> Result<()> {
@@ -93,24 +577,86 @@ impl ElectrsClient {
     }
 
     /// BLOCKING tx history lookup
-    fn get_address_txs_blocking(&self, address: &str) -> Result<Vec<String>> {
+    fn get_address_txs_blocking(&self, address: &str) -> Result<Vec<HistoryEntry>> {
+        self.rate_limit();
+
+        let addr = self.parse_address(address)?;
+        let script: ScriptBuf = addr.script_pubkey();
+
+        let history = self.client.script_get_history(&script)?;
+        Ok(history
+            .into_iter()
+            .map(|h| HistoryEntry {
+                txid: h.tx_hash.to_string(),
+                height: (h.height > 0).then_some(h.height as u32),
+            })
+            .collect())
+    }
+
+    /// BLOCKING balance and history of an Electrum scripthash
+    ///
+    /// electrum-client's script_* calls hash a script themselves, so the
+    /// scripthash methods are called raw. Like the address balance, the
+    /// balance call is skipped when there is no history.
+    fn get_scripthash_summary_blocking(&self, scripthash: &str) -> Result<ScripthashSummary> {
+        let param = || vec![Param::String(scripthash.to_string())];
+
+        self.rate_limit();
+        let history: Vec<GetHistoryRes> = serde_json::from_value(
+            self.client.raw_call("blockchain.scripthash.get_history", param())?,
+        )?;
+
+        let history: Vec<HistoryEntry> = history
+            .into_iter()
+            .map(|h| HistoryEntry {
+                txid: h.tx_hash.to_string(),
+                height: (h.height > 0).then_some(h.height as u32),
+            })
+            .collect();
+
+        if history.is_empty() {
+            return Ok(ScripthashSummary { confirmed: 0, unconfirmed: 0, history });
+        }
+
+        self.rate_limit();
+        let balance: GetBalanceRes = serde_json::from_value(
+            self.client.raw_call("blockchain.scripthash.get_balance", param())?,
+        )?;
+
+        Ok(ScripthashSummary {
+            confirmed: balance.confirmed,
+            unconfirmed: balance.unconfirmed,
+            history,
+        })
+    }
+
+    /// BLOCKING unconfirmed txids touching `address`
+    ///
+    /// Electrum reports mempool history entries with height 0 (or -1 when
+    /// they spend unconfirmed outputs).
+    fn get_address_mempool_blocking(&self, address: &str) -> Result<Vec<String>> {
         self.rate_limit();
 
-        let addr = Address::from_str(address)?.require_network(Network::Bitcoin)?;
+        let addr = self.parse_address(address)?;
         let script: ScriptBuf = addr.script_pubkey();
 
         let history = self.client.script_get_history(&script)?;
-        Ok(history.into_iter().map(|h| h.tx_hash.to_string()).collect())
+        Ok(history
+            .into_iter()
+            .filter(|h| h.height <= 0)
+            .map(|h| h.tx_hash.to_string())
+            .collect())
     }
 
     /// BLOCKING balance lookup with history fast-path:
     /// 1) Call script_get_history first
-    ///    - if empty => immediately return (0,0) (avoids listunspent cost/blocking)
-    /// 2) If non-empty => call script_list_unspent and sum values
+    ///    - if empty => immediately return (0,0) (avoids the balance call for unused addresses)
+    /// 2) If non-empty => call script_get_balance
     ///
-    /// This keeps the service stateless while avoiding listunspent calls for unused addresses.
-    fn get_address_balance_blocking(&self, address: &str) -> Result<(u64, u64)> {
-        let addr = Address::from_str(address)?.require_network(Network::Bitcoin)?;
+    /// Unconfirmed is signed: it goes negative when mempool txs spend more
+    /// of the address's confirmed coins than they pay back to it.
+    fn get_address_balance_blocking(&self, address: &str) -> Result<(u64, i64)> {
+        let addr = self.parse_address(address)?;
         let script: ScriptBuf = addr.script_pubkey();
 
         // ---- Fast-path: check history first ----
@@ -120,31 +666,41 @@ impl ElectrsClient {
             return Ok((0, 0));
         }
 
-        // ---- Only if there is history, compute balance from UTXOs ----
+        // ---- Only if there is history, ask for the balance ----
         self.rate_limit();
-        let utxos = self.client.script_list_unspent(&script)?;
+        let balance = self.client.script_get_balance(&script)?;
 
-        let mut confirmed: u64 = 0;
-        let mut unconfirmed: u64 = 0;
+        Ok((balance.confirmed, balance.unconfirmed))
+    }
 
-        for u in utxos {
-            // Convention: height == 0 => mempool/unconfirmed
-            if u.height > 0 {
-                confirmed = confirmed.saturating_add(u.value);
-            } else {
-                unconfirmed = unconfirmed.saturating_add(u.value);
-            }
+    /// Balance lookup, deduplicated: concurrent lookups for the same address
+    /// share a single Electrs call.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_address_balance(&self, address: &str) -> Result<(u64, i64)> {
+        self.poll_tip();
+        if let Some(balance) = self.balance_cache.get(address) {
+            return Ok(balance);
         }
 
-        Ok((confirmed, unconfirmed))
+        let this = self.clone();
+        let addr = address.to_string();
+
+        let balance = self
+            .balance_flight
+            .run(address.to_string(), async move {
+                this.fetch_address_balance(&addr).await
+            })
+            .await?;
+
+        self.balance_cache.insert(address, balance);
+        Ok(balance)
     }
 
     /// Balance lookup:
     /// - single-flight gate (global)
     /// - cooldown after timeout
     /// - 90s timeout + 1 retry
-    pub async fn get_address_balance(&self, address: &str) -> Result<(u64, u64)> {
-        use tokio::task::spawn_blocking;
+    async fn fetch_address_balance(&self, address: &str) -> Result<(u64, i64)> {
         use tokio::time::{timeout, Duration};
 
         // Respect cooldown (fast-fail instead of wedging Electrs)
@@ -168,7 +724,7 @@ impl ElectrsClient {
 
         match first {
             Ok(Ok(Ok(v))) => return Ok(v),
-            Ok(Ok(Err(e))) => return Err(anyhow!("Electrs balance error: {}", e)),
+            Ok(Ok(Err(e))) => return Err(prefixed("Electrs balance error", e)),
             Ok(Err(e)) => return Err(anyhow!("Electrs join error: {}", e)),
             Err(_) => {
                 warn!("Electrs balance timed out, setting cooldown + retrying once...");
@@ -189,22 +745,60 @@ impl ElectrsClient {
 
         match second {
             Ok(Ok(Ok(v))) => Ok(v),
-            Ok(Ok(Err(e))) => Err(anyhow!("Electrs balance error (retry): {}", e)),
+            Ok(Ok(Err(e))) => Err(prefixed("Electrs balance error (retry)", e)),
             Ok(Err(e)) => Err(anyhow!("Electrs join error (retry): {}", e)),
             Err(_) => {
                 warn!("Electrs balance timed out after retry; setting longer cooldown");
                 self.set_cooldown(20);
-                Err(anyhow!("Electrs balance timeout (after retry)"))
+                Err(ErrorCode::Timeout.error("Electrs balance timeout (after retry)"))
             }
         }
     }
 
+    /// Unconfirmed txids for an address (async wrapper)
+    #[tracing::instrument(skip(self))]
+    pub async fn get_address_mempool(&self, address: &str) -> Result<Vec<String>> {
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let addr = address.to_string();
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.get_address_mempool_blocking(&addr)).await;
+
+        match res {
+            Ok(Ok(txids)) => Ok(txids),
+            Ok(Err(e)) => Err(prefixed("Address mempool error", e)),
+            Err(e) => Err(anyhow!("Address mempool join error: {}", e)),
+        }
+    }
+
+    /// History lookup, deduplicated like `get_address_balance`
+    #[tracing::instrument(skip(self))]
+    pub async fn get_address_txs(&self, address: &str) -> Result<Vec<String>> {
+        let history = self.get_address_history_entries(address).await?;
+
+        Ok(history.into_iter().map(|h| h.txid).collect())
+    }
+
+    /// History with confirmation heights, oldest first
+    pub async fn get_address_history_entries(&self, address: &str) -> Result<Vec<HistoryEntry>> {
+        let this = self.clone();
+        let addr = address.to_string();
+
+        self.txs_flight
+            .run(address.to_string(), async move {
+                this.fetch_address_txs(&addr).await
+            })
+            .await
+    }
+
     /// History lookup (used only for xpub path):
     /// - single-flight gate (global)
     /// - cooldown after timeout
     /// - 45s timeout (no retries here by default)
-    pub async fn get_address_txs(&self, address: &str) -> Result<Vec<String>> {
-        use tokio::task::spawn_blocking;
+    async fn fetch_address_txs(&self, address: &str) -> Result<Vec<HistoryEntry>> {
         use tokio::time::{timeout, Duration};
 
         self.check_cooldown()?;
@@ -222,32 +816,153 @@ impl ElectrsClient {
 
         match res {
             Ok(Ok(Ok(v))) => Ok(v),
-            Ok(Ok(Err(e))) => Err(anyhow!("Electrs tx error: {}", e)),
+            Ok(Ok(Err(e))) => Err(prefixed("Electrs tx error", e)),
             Ok(Err(e)) => Err(anyhow!("Electrs join error: {}", e)),
             Err(_) => {
                 warn!("Electrs history timed out; setting cooldown");
                 self.set_cooldown(10);
-                Err(anyhow!("Electrs history timeout"))
+                Err(ErrorCode::Timeout.error("Electrs history timeout"))
             }
         }
     }
 
+    /// Paginated history, newest first
+    ///
+    /// Electrum returns an address's full history in one call, so paging is
+    /// done here: the page starts after `last_seen_txid` (or at the newest tx)
+    /// and holds at most `limit` txids. An unknown `last_seen_txid` is an error
+    /// rather than silently restarting from the top.
+    pub async fn get_address_history(
+        &self,
+        address: &str,
+        last_seen_txid: Option<&str>,
+        limit: usize,
+    ) -> Result<HistoryPage> {
+        let mut history = self.get_address_txs(address).await?;
+        history.reverse();
+
+        let start = match last_seen_txid {
+            Some(txid) => history
+                .iter()
+                .position(|t| t == txid)
+                .map(|i| i + 1)
+                .ok_or_else(|| ErrorCode::InvalidTxid.error(format!("Unknown last_seen_txid: {}", txid)))?,
+            None => 0,
+        };
+
+        let end = start.saturating_add(limit).min(history.len());
+
+        Ok(HistoryPage {
+            txids: history[start..end].to_vec(),
+            has_more: end < history.len(),
+        })
+    }
+
     /// Broadcast raw transaction (BLOCKING)
     fn broadcast_transaction_blocking(&self, tx_hex: &str) -> Result<String> {
         self.rate_limit();
 
         let raw_tx = hex::decode(tx_hex)
-            .map_err(|e| anyhow!("Invalid hex: {}", e))?;
+            .map_err(|e| ErrorCode::InvalidTx.error(format!("Invalid hex: {}", e)))?;
 
         // Validate transaction format (deserialize to check it's valid)
         let _tx: electrum_client::bitcoin::Transaction = electrum_client::bitcoin::consensus::encode::deserialize(&raw_tx)
-            .map_err(|e| anyhow!("Invalid transaction: {}", e))?;
+            .map_err(|e| ErrorCode::InvalidTx.error(format!("Invalid transaction: {}", e)))?;
 
         let txid = self.client.transaction_broadcast_raw(&raw_tx)?;
 
         Ok(txid.to_string())
     }
 
+    /// Dry-run policy check of a raw transaction (BLOCKING)
+    ///
+    /// Electrum has no `testmempoolaccept`, so this approximates it with the
+    /// checks Electrs can answer: the tx is not already known, every input
+    /// spends an existing unspent output, outputs don't exceed inputs and the
+    /// fee meets the relay fee. Script and standardness checks are not covered.
+    fn test_mempool_accept_blocking(&self, tx_hex: &str) -> Result<MempoolAcceptResult> {
+        use electrum_client::bitcoin::{OutPoint, Transaction};
+
+        let raw_tx = hex::decode(tx_hex)
+            .map_err(|e| ErrorCode::InvalidTx.error(format!("Invalid hex: {}", e)))?;
+        let tx: Transaction = electrum_client::bitcoin::consensus::encode::deserialize(&raw_tx)
+            .map_err(|e| ErrorCode::InvalidTx.error(format!("Invalid transaction: {}", e)))?;
+
+        let txid = tx.compute_txid();
+        let vsize = tx.vsize() as u64;
+        let reject = |reason: &str, fee: Option<u64>| MempoolAcceptResult {
+            txid: txid.to_string(),
+            allowed: false,
+            reject_reason: Some(reason.to_string()),
+            fee,
+            vsize,
+        };
+
+        if tx.is_coinbase() {
+            return Ok(reject("coinbase", None));
+        }
+
+        self.rate_limit();
+        if self.client.transaction_get(&txid).is_ok() {
+            return Ok(reject("txn-already-known", None));
+        }
+
+        // Resolve prevouts; a missing parent means missing inputs
+        self.rate_limit();
+        let prev_ids: Vec<_> = tx.input.iter().map(|i| i.previous_output.txid).collect();
+        let prev_txs = match self.client.batch_transaction_get(&prev_ids) {
+            Ok(p) => p,
+            Err(_) => return Ok(reject("missing-inputs", None)),
+        };
+
+        let mut prevouts = Vec::with_capacity(tx.input.len());
+        for (input, prev) in tx.input.iter().zip(prev_txs.iter()) {
+            match prev.output.get(input.previous_output.vout as usize) {
+                Some(out) => prevouts.push((input.previous_output, out.clone())),
+                None => return Ok(reject("missing-inputs", None)),
+            }
+        }
+
+        // Every prevout must still be unspent (listunspent excludes outputs
+        // already spent in the mempool)
+        self.rate_limit();
+        let unspent = self
+            .client
+            .batch_script_list_unspent(prevouts.iter().map(|(_, out)| out.script_pubkey.as_script()))?;
+
+        for ((outpoint, _), utxos) in prevouts.iter().zip(unspent.iter()) {
+            let found = utxos.iter().any(|u| {
+                OutPoint::new(u.tx_hash, u.tx_pos as u32) == *outpoint
+            });
+            if !found {
+                return Ok(reject("bad-txns-inputs-missingorspent", None));
+            }
+        }
+
+        let inputs: u64 = prevouts.iter().map(|(_, out)| out.value.to_sat()).sum();
+        let outputs: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+        if outputs > inputs {
+            return Ok(reject("bad-txns-in-belowout", None));
+        }
+        let fee = inputs - outputs;
+
+        // relay_fee is BTC/kvB
+        self.rate_limit();
+        let relay_fee = self.client.relay_fee()?;
+        let min_fee = (relay_fee * 100_000.0 * vsize as f64).ceil() as u64;
+        if fee < min_fee {
+            return Ok(reject("min relay fee not met", Some(fee)));
+        }
+
+        Ok(MempoolAcceptResult {
+            txid: txid.to_string(),
+            allowed: true,
+            reject_reason: None,
+            fee: Some(fee),
+            vsize,
+        })
+    }
+
     /// Estimate fees for fast/medium/slow (BLOCKING)
     /// Returns (fast, medium, slow) in sat/vB
     fn estimate_fees_blocking(&self) -> Result<(u64, u64, u64)> {
@@ -274,116 +989,1352 @@ impl ElectrsClient {
         Ok((fast, medium, slow))
     }
 
-    /// Get UTXOs for multiple addresses (BLOCKING)
-    fn get_utxos_blocking(&self, addresses: &[String]) -> Result<Vec<crate::nostr_handler::UtxoInfo>> {
-        use crate::nostr_handler::UtxoInfo;
+    /// Transaction size + fee lookup (BLOCKING)
+    ///
+    /// The fee is computed from the prevouts: sum(inputs) - sum(outputs).
+    fn get_transaction_details_blocking(&self, txid: &str) -> Result<TxDetails> {
+        use electrum_client::bitcoin::Txid;
 
-        let mut all_utxos = Vec::new();
+        self.rate_limit();
 
-        for address in addresses {
+        let id = Txid::from_str(txid).map_err(|e| ErrorCode::InvalidTxid.error(format!("Invalid txid: {}", e)))?;
+        let tx = self.client.transaction_get(&id)?;
+
+        let fee = if tx.is_coinbase() {
+            None
+        } else {
             self.rate_limit();
 
-            let addr = Address::from_str(address)?.require_network(Network::Bitcoin)?;
-            let script: ScriptBuf = addr.script_pubkey();
+            let prev_ids: Vec<Txid> = tx.input.iter().map(|i| i.previous_output.txid).collect();
+            match self.client.batch_transaction_get(&prev_ids) {
+                Ok(prev_txs) => {
+                    let inputs: Option<u64> = tx
+                        .input
+                        .iter()
+                        .zip(prev_txs.iter())
+                        .map(|(input, prev)| {
+                            prev.output
+                                .get(input.previous_output.vout as usize)
+                                .map(|o| o.value.to_sat())
+                        })
+                        .sum();
+                    let outputs: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+
+                    inputs.map(|i| i.saturating_sub(outputs))
+                }
+                Err(e) => {
+                    warn!("Failed to fetch prevouts for {}: {}", txid, e);
+                    None
+                }
+            }
+        };
+
+        Ok(TxDetails {
+            txid: tx.compute_txid().to_string(),
+            fee,
+            vsize: tx.vsize() as u64,
+            weight: tx.weight().to_wu(),
+        })
+    }
 
-            let utxos = self.client.script_list_unspent(&script)?;
+    /// Unconfirmed ancestor totals of a transaction (BLOCKING)
+    ///
+    /// Electrum has no mempool-entry call, so the package is walked here:
+    /// each unconfirmed tx is fetched with its parents, its fee computed from
+    /// the prevouts, and parents still in the mempool are followed.
+    fn get_tx_ancestry_blocking(&self, txid: &str) -> Result<TxAncestry> {
+        use electrum_client::bitcoin::Txid;
 
-            // Get current blockchain height for confirmation calculation
-            let current_height = match self.client.block_headers_subscribe() {
-                Ok(header) => header.height as u32,
-                Err(_) => 0, // Fallback if subscription fails
-            };
+        let id = Txid::from_str(txid).map_err(|e| ErrorCode::InvalidTxid.error(format!("Invalid txid: {}", e)))?;
 
-            for utxo in utxos {
-                let confirmations = if utxo.height > 0 {
-                    current_height.saturating_sub(utxo.height as u32)
-                } else {
-                    0 // Unconfirmed (mempool)
-                };
+        self.rate_limit();
+        let tx = self.client.transaction_get(&id)?;
+
+        if self.tx_height_blocking(&tx)? > 0 {
+            return Ok(TxAncestry {
+                txid: id.to_string(),
+                confirmed: true,
+                ancestor_count: None,
+                ancestor_fees: None,
+                ancestor_vsize: None,
+            });
+        }
+
+        let mut seen: HashSet<Txid> = HashSet::from([id]);
+        let mut queue = vec![tx];
+        let mut count: u32 = 0;
+        let mut fees: u64 = 0;
+        let mut vsize: u64 = 0;
+
+        while let Some(tx) = queue.pop() {
+            count += 1;
+            if count > MAX_MEMPOOL_ANCESTORS {
+                return Err(anyhow!("more than {} unconfirmed ancestors", MAX_MEMPOOL_ANCESTORS));
+            }
 
-                all_utxos.push(UtxoInfo {
-                    txid: utxo.tx_hash.to_string(),
-                    vout: utxo.tx_pos as u32,
-                    value: utxo.value,
-                    address: address.clone(),
-                    confirmations,
-                });
+            let prev_ids: Vec<Txid> = tx.input.iter().map(|i| i.previous_output.txid).collect();
+            let prev_txs = self.batch_get_transactions(&prev_ids)?;
+
+            let inputs: u64 = tx
+                .input
+                .iter()
+                .zip(prev_txs.iter())
+                .filter_map(|(input, prev)| prev.output.get(input.previous_output.vout as usize))
+                .map(|o| o.value.to_sat())
+                .sum();
+            let outputs: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+
+            fees = fees.saturating_add(inputs.saturating_sub(outputs));
+            vsize = vsize.saturating_add(tx.vsize() as u64);
+
+            for prev in prev_txs {
+                if seen.insert(prev.compute_txid()) && self.tx_height_blocking(&prev)? <= 0 {
+                    queue.push(prev);
+                }
             }
         }
 
-        Ok(all_utxos)
+        Ok(TxAncestry {
+            txid: id.to_string(),
+            confirmed: false,
+            ancestor_count: Some(count),
+            ancestor_fees: Some(fees),
+            ancestor_vsize: Some(vsize),
+        })
     }
 
-    /// Broadcast transaction (async wrapper)
-    pub async fn broadcast_transaction(&self, tx_hex: &str) -> Result<String> {
-        use tokio::task::spawn_blocking;
+    /// Hash and time of the blocks at `heights` (BLOCKING)
+    ///
+    /// Heights are deduplicated; headers are fetched in batches of
+    /// TX_BATCH_SIZE.
+    fn get_block_infos_blocking(&self, heights: &[u32]) -> Result<HashMap<u32, BlockInfo>> {
+        let mut heights = heights.to_vec();
+        heights.sort_unstable();
+        heights.dedup();
+
+        let mut infos = HashMap::with_capacity(heights.len());
+        for chunk in heights.chunks(TX_BATCH_SIZE) {
+            self.rate_limit();
+            let headers = self.client.batch_block_header(chunk.iter().copied())?;
+
+            for (height, header) in chunk.iter().zip(headers) {
+                infos.insert(
+                    *height,
+                    BlockInfo {
+                        hash: header.block_hash().to_string(),
+                        time: header.time,
+                    },
+                );
+            }
+        }
 
-        self.check_cooldown()?;
-        let _permit = self.gate.acquire().await.unwrap();
-        self.check_cooldown()?;
+        Ok(infos)
+    }
 
-        let hex = tx_hex.to_string();
-        let this = self.clone();
+    /// Confirmation state of a transaction (BLOCKING)
+    ///
+    /// Confirmations are counted against the tip known from header
+    /// notifications, subscribing only if none arrived yet.
+    fn get_tx_status_blocking(&self, txid: &str) -> Result<TxStatus> {
+        use electrum_client::bitcoin::Txid;
 
-        let res = spawn_blocking(move || this.broadcast_transaction_blocking(&hex)).await;
+        let id = Txid::from_str(txid).map_err(|e| ErrorCode::InvalidTxid.error(format!("Invalid txid: {}", e)))?;
 
-        match res {
-            Ok(Ok(txid)) => Ok(txid),
-            Ok(Err(e)) => Err(anyhow!("Broadcast error: {}", e)),
-            Err(e) => Err(anyhow!("Broadcast join error: {}", e)),
+        self.rate_limit();
+        let tx = self.client.transaction_get(&id)?;
+        let height = self.tx_height_blocking(&tx)?;
+
+        if height <= 0 {
+            return Ok(TxStatus {
+                txid: id.to_string(),
+                confirmed: false,
+                block_height: None,
+                confirmations: 0,
+            });
         }
-    }
 
-    /// Estimate fees (async wrapper)
-    pub async fn estimate_fees(&self) -> Result<(u64, u64, u64)> {
-        use tokio::task::spawn_blocking;
+        self.poll_tip();
+        let known = *self.balance_cache.tip.lock().unwrap();
+        let tip = match known {
+            Some(tip) => tip,
+            None => {
+                self.rate_limit();
+                let tip = self.client.block_headers_subscribe()?.height as u32;
+                self.observe_tip(tip);
+                tip
+            }
+        };
 
-        self.check_cooldown()?;
-        let _permit = self.gate.acquire().await.unwrap();
-        self.check_cooldown()?;
+        let height = height as u32;
+        Ok(TxStatus {
+            txid: id.to_string(),
+            confirmed: true,
+            block_height: Some(height),
+            confirmations: tip.saturating_sub(height) + 1,
+        })
+    }
 
-        let this = self.clone();
+    /// Height of a transaction as Electrs sees it; 0 or -1 means mempool
+    ///
+    /// Found through the history of one of its output scripts.
+    fn tx_height_blocking(&self, tx: &electrum_client::bitcoin::Transaction) -> Result<i32> {
+        let txid = tx.compute_txid();
 
-        let res = spawn_blocking(move || this.estimate_fees_blocking()).await;
+        for output in tx.output.iter().filter(|o| !o.script_pubkey.is_op_return()) {
+            self.rate_limit();
+            let history = self.client.script_get_history(&output.script_pubkey)?;
 
-        match res {
-            Ok(Ok(fees)) => Ok(fees),
-            Ok(Err(e)) => Err(anyhow!("Fee estimation error: {}", e)),
-            Err(e) => Err(anyhow!("Fee estimation join error: {}", e)),
+            if let Some(entry) = history.iter().find(|h| h.tx_hash == txid) {
+                return Ok(entry.height);
+            }
         }
+
+        Err(anyhow!("transaction {} not found in Electrs history", txid))
     }
 
-    /// Get UTXOs (async wrapper)
-    pub async fn get_utxos(&self, addresses: &[String]) -> Result<Vec<crate::nostr_handler::UtxoInfo>> {
-        use tokio::task::spawn_blocking;
+    /// Net effect of a transaction on an address, in sats (BLOCKING)
+    ///
+    /// Outputs paying the address count positive, inputs spending from it
+    /// (resolved via their prevouts) count negative.
+    fn get_transaction_amount_blocking(&self, address: &str, txid: &str) -> Result<i64> {
+        use electrum_client::bitcoin::Txid;
 
-        self.check_cooldown()?;
-        let _permit = self.gate.acquire().await.unwrap();
-        self.check_cooldown()?;
+        let addr = self.parse_address(address)?;
+        let script: ScriptBuf = addr.script_pubkey();
 
-        let addrs = addresses.to_vec();
-        let this = self.clone();
+        self.rate_limit();
 
-        let res = spawn_blocking(move || this.get_utxos_blocking(&addrs)).await;
+        let id = Txid::from_str(txid).map_err(|e| ErrorCode::InvalidTxid.error(format!("Invalid txid: {}", e)))?;
+        let tx = self.client.transaction_get(&id)?;
 
-        match res {
-            Ok(Ok(utxos)) => Ok(utxos),
-            Ok(Err(e)) => Err(anyhow!("UTXO fetch error: {}", e)),
-            Err(e) => Err(anyhow!("UTXO join error: {}", e)),
-        }
-    }
-}
+        let prevouts = self.resolve_prevouts(std::slice::from_ref(&tx))?;
 
-fn preflight_tcp(addr: &str) -> Result<()> {
-    let mut addrs = addr
-        .to_socket_addrs()
-        .map_err(|e| anyhow!("Invalid ELECTRS_ADDR '{}': {}", addr, e))?;
+        Ok(net_amount(&tx, &script, &prevouts))
+    }
+
+    /// Outputs spent by the inputs of `txs` (BLOCKING)
+    ///
+    /// Each parent transaction is fetched once, however many inputs spend
+    /// from it, in pipelined batches of TX_BATCH_SIZE.
+    fn resolve_prevouts(
+        &self,
+        txs: &[electrum_client::bitcoin::Transaction],
+    ) -> Result<HashMap<electrum_client::bitcoin::OutPoint, electrum_client::bitcoin::TxOut>> {
+        use electrum_client::bitcoin::Txid;
+
+        let mut prev_ids: Vec<Txid> = txs
+            .iter()
+            .filter(|tx| !tx.is_coinbase())
+            .flat_map(|tx| tx.input.iter().map(|i| i.previous_output.txid))
+            .collect();
+        prev_ids.sort();
+        prev_ids.dedup();
+
+        let prev_txs: HashMap<Txid, _> = prev_ids
+            .iter()
+            .copied()
+            .zip(self.batch_get_transactions(&prev_ids)?)
+            .collect();
+
+        Ok(txs
+            .iter()
+            .filter(|tx| !tx.is_coinbase())
+            .flat_map(|tx| tx.input.iter().map(|i| i.previous_output))
+            .filter_map(|outpoint| {
+                prev_txs
+                    .get(&outpoint.txid)
+                    .and_then(|prev| prev.output.get(outpoint.vout as usize))
+                    .map(|out| (outpoint, out.clone()))
+            })
+            .collect())
+    }
+
+    /// Fetch transactions in pipelined batches of TX_BATCH_SIZE (BLOCKING)
+    fn batch_get_transactions(
+        &self,
+        ids: &[electrum_client::bitcoin::Txid],
+    ) -> Result<Vec<electrum_client::bitcoin::Transaction>> {
+        let mut txs = Vec::with_capacity(ids.len());
+
+        for chunk in ids.chunks(TX_BATCH_SIZE) {
+            self.rate_limit();
+            txs.extend(self.client.batch_transaction_get(chunk)?);
+        }
+
+        Ok(txs)
+    }
+
+    /// Net effect of several transactions on an address (BLOCKING)
+    ///
+    /// Same as `get_transaction_amount_blocking` but the transactions and all
+    /// their prevouts are fetched in batches instead of one call per tx.
+    fn get_transaction_amounts_blocking(&self, address: &str, txids: &[String]) -> Result<Vec<i64>> {
+        use electrum_client::bitcoin::Txid;
+
+        let addr = self.parse_address(address)?;
+        let script: ScriptBuf = addr.script_pubkey();
+
+        let ids = txids
+            .iter()
+            .map(|t| Txid::from_str(t).map_err(|e| ErrorCode::InvalidTxid.error(format!("Invalid txid: {}", e))))
+            .collect::<Result<Vec<Txid>>>()?;
+
+        let txs = self.batch_get_transactions(&ids)?;
+        let prevouts = self.resolve_prevouts(&txs)?;
+
+        Ok(txs.iter().map(|tx| net_amount(tx, &script, &prevouts)).collect())
+    }
+
+    /// Mempool fee histogram + totals (BLOCKING)
+    fn get_mempool_blocking(&self) -> Result<MempoolInfo> {
+        let fee_histogram = self.get_fee_histogram_blocking()?;
+
+        let total_vsize = fee_histogram.iter().map(|(_, vsize)| *vsize).sum();
+        let total_fee = fee_histogram
+            .iter()
+            .map(|(rate, vsize)| (rate * *vsize as f64) as u64)
+            .sum();
+
+        Ok(MempoolInfo {
+            total_vsize,
+            total_fee,
+            fee_histogram,
+        })
+    }
+
+    /// Tip header and its age (BLOCKING)
+    fn get_sync_status_blocking(&self) -> Result<SyncStatus> {
+        self.rate_limit();
+
+        let tip = self.client.block_headers_subscribe()?;
+        let tip_time = tip.header.time;
+        self.observe_tip(tip.height as u32);
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let tip_age_secs = now.saturating_sub(u64::from(tip_time));
+
+        // Regtest blocks are mined on demand, so an old tip means nothing
+        let synced = self.network == Network::Regtest
+            || tip_age_secs <= self.max_tip_age.as_secs();
+
+        Ok(SyncStatus {
+            tip_height: tip.height as u32,
+            tip_time,
+            tip_age_secs,
+            lag_blocks: tip_age_secs / 600,
+            synced,
+        })
+    }
+
+    /// Mempool fee-rate histogram (BLOCKING)
+    fn get_fee_histogram_blocking(&self) -> Result<FeeHistogram> {
+        self.rate_limit();
+
+        let raw = self
+            .client
+            .raw_call("mempool.get_fee_histogram", std::iter::empty())?;
+
+        serde_json::from_value(raw).map_err(|e| anyhow!("Invalid fee histogram: {}", e))
+    }
+
+    /// Allowlisted raw Electrum call (BLOCKING)
+    fn proxy_call_blocking(&self, method: &str, params: Vec<Param>) -> Result<serde_json::Value> {
+        self.rate_limit();
+        Ok(self.client.raw_call(method, params)?)
+    }
+
+    /// Get UTXOs for multiple addresses (BLOCKING)
+    ///
+    /// The Electrum connection is a single synchronous socket, so instead of
+    /// one roundtrip per address the scripts are sent as pipelined batches of
+    /// UTXO_BATCH_SIZE. The tip height is fetched once for all confirmations.
+    ///
+    /// `spendable` needs 1 confirmation, or COINBASE_MATURITY for coinbase
+    /// outputs; only UTXOs that could still be immature have their tx fetched.
+    fn get_utxos_blocking(&self, addresses: &[String]) -> Result<UtxoSet> {
+        use crate::nostr_handler::UtxoInfo;
+
+        let mut failed_addresses = Vec::new();
+        let mut last_error = None;
+
+        // Invalid addresses are reported, not fatal
+        let mut valid = Vec::with_capacity(addresses.len());
+        let mut scripts = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            match self.parse_address(address) {
+                Ok(addr) => {
+                    valid.push(address.clone());
+                    scripts.push(addr.script_pubkey());
+                }
+                Err(e) => {
+                    warn!(address = %address, err = %e, "Skipping address in UTXO lookup");
+                    failed_addresses.push(address.clone());
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        // Confirmations are counted from the tip; without it they'd be wrong
+        self.rate_limit();
+        let tip = self
+            .client
+            .block_headers_subscribe()
+            .map_err(|e| anyhow!("Tip height unavailable: {}", e))?
+            .height as u32;
+        self.observe_tip(tip);
+
+        let mut all_utxos = Vec::new();
+
+        for (chunk_addrs, chunk_scripts) in valid
+            .chunks(UTXO_BATCH_SIZE)
+            .zip(scripts.chunks(UTXO_BATCH_SIZE))
+        {
+            self.rate_limit();
+
+            // On a batch failure, retry one by one so one bad script only
+            // costs its own address
+            let results: Vec<Option<_>> =
+                match self.client.batch_script_list_unspent(chunk_scripts.iter().map(|s| s.as_script())) {
+                    Ok(results) => results.into_iter().map(Some).collect(),
+                    Err(e) => {
+                        warn!(err = %e, addresses = chunk_addrs.len(), "UTXO batch failed; retrying per address");
+                        chunk_scripts
+                            .iter()
+                            .map(|script| {
+                                self.rate_limit();
+                                self.client.script_list_unspent(script).ok()
+                            })
+                            .collect()
+                    }
+                };
+
+            for ((address, script), utxos) in chunk_addrs.iter().zip(chunk_scripts).zip(results) {
+                let Some(utxos) = utxos else {
+                    warn!(address = %address, "Skipping address in UTXO lookup");
+                    failed_addresses.push(address.clone());
+                    last_error = Some(anyhow!("script_list_unspent failed for {}", address));
+                    continue;
+                };
+
+                for utxo in utxos {
+                    let confirmations = if utxo.height > 0 {
+                        // Mined in the tip block = 1 confirmation
+                        tip.saturating_sub(utxo.height as u32) + 1
+                    } else {
+                        0 // Unconfirmed (mempool)
+                    };
+
+                    all_utxos.push(UtxoInfo {
+                        txid: utxo.tx_hash.to_string(),
+                        vout: utxo.tx_pos as u32,
+                        value: utxo.value,
+                        address: address.clone(),
+                        label: None,
+                        script_type: script_type(script),
+                        confirmations,
+                        spendable: confirmations >= 1,
+                    });
+                }
+            }
+        }
+
+        // Coinbase outputs need COINBASE_MATURITY confirmations
+        let mut maybe_immature: Vec<electrum_client::bitcoin::Txid> = all_utxos
+            .iter()
+            .filter(|u| u.confirmations >= 1 && u.confirmations < COINBASE_MATURITY)
+            .filter_map(|u| electrum_client::bitcoin::Txid::from_str(&u.txid).ok())
+            .collect();
+        maybe_immature.sort();
+        maybe_immature.dedup();
+
+        if !maybe_immature.is_empty() {
+            let coinbase: Vec<String> = self
+                .batch_get_transactions(&maybe_immature)?
+                .iter()
+                .filter(|tx| tx.is_coinbase())
+                .map(|tx| tx.compute_txid().to_string())
+                .collect();
+
+            for utxo in all_utxos.iter_mut() {
+                if coinbase.contains(&utxo.txid) {
+                    utxo.spendable = utxo.confirmations >= COINBASE_MATURITY;
+                }
+            }
+        }
+
+        // Nothing could be queried: a plain error says more than a list
+        if !addresses.is_empty() && failed_addresses.len() == addresses.len() {
+            if let Some(e) = last_error {
+                return Err(e);
+            }
+        }
+
+        Ok(UtxoSet {
+            utxos: all_utxos,
+            failed_addresses,
+        })
+    }
+
+    /// Broadcast a transaction via Electrs, falling back to bitcoind
+    ///
+    /// With BITCOIND_RPC_URL set, a broadcast Electrs fails, rejects or
+    /// doesn't answer within BITCOIND_FALLBACK_AFTER is sent to bitcoind's
+    /// `sendrawtransaction` as well. Resending a tx already in the mempool
+    /// is harmless.
+    #[tracing::instrument(skip_all)]
+    pub async fn broadcast_transaction(&self, tx_hex: &str) -> Result<Broadcast> {
+        let Some(bitcoind) = &self.bitcoind else {
+            let txid = self.broadcast_via_electrs(tx_hex).await?;
+            return Ok(Broadcast { txid, via: "electrs" });
+        };
+
+        let electrs_err = match tokio::time::timeout(
+            BITCOIND_FALLBACK_AFTER,
+            self.broadcast_via_electrs(tx_hex),
+        )
+        .await
+        {
+            Ok(Ok(txid)) => return Ok(Broadcast { txid, via: "electrs" }),
+            Ok(Err(e)) => e,
+            Err(_) => ErrorCode::Timeout.error("Broadcast timeout via Electrs"),
+        };
+
+        warn!(err = %electrs_err, "Broadcast via Electrs failed; trying bitcoind at {}", bitcoind.addr());
+
+        match bitcoind.send_raw_transaction(tx_hex).await {
+            Ok(txid) => {
+                info!(txid = %txid, "Broadcast via bitcoind fallback OK");
+                Ok(Broadcast { txid, via: "bitcoind" })
+            }
+            Err(e) => Err(anyhow!("{}; bitcoind fallback: {}", electrs_err, e)),
+        }
+    }
+
+    async fn broadcast_via_electrs(&self, tx_hex: &str) -> Result<String> {
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let hex = tx_hex.to_string();
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.broadcast_transaction_blocking(&hex)).await;
+
+        match res {
+            Ok(Ok(txid)) => Ok(txid),
+            Ok(Err(e)) => Err(prefixed("Broadcast error", e)),
+            Err(e) => Err(anyhow!("Broadcast join error: {}", e)),
+        }
+    }
+
+    /// Dry-run broadcast check (async wrapper); never broadcasts
+    #[tracing::instrument(skip_all)]
+    pub async fn test_mempool_accept(&self, tx_hex: &str) -> Result<MempoolAcceptResult> {
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let hex = tx_hex.to_string();
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.test_mempool_accept_blocking(&hex)).await;
+
+        match res {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(e)) => Err(prefixed("Test mempool accept error", e)),
+            Err(e) => Err(anyhow!("Test mempool accept join error: {}", e)),
+        }
+    }
+
+    /// Estimate fees (async wrapper)
+    ///
+    /// Results are cached for FEE_CACHE_TTL so polling wallets don't each
+    /// trigger an Electrs roundtrip.
+    #[tracing::instrument(skip(self))]
+    pub async fn estimate_fees(&self) -> Result<(u64, u64, u64)> {
+        if let Some((fetched_at, fees)) = *self.fee_cache.lock().unwrap() {
+            if fetched_at.elapsed() < FEE_CACHE_TTL {
+                return Ok(fees);
+            }
+        }
+
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.estimate_fees_blocking()).await;
+
+        match res {
+            Ok(Ok(fees)) => {
+                *self.fee_cache.lock().unwrap() = Some((Instant::now(), fees));
+                Ok(fees)
+            }
+            Ok(Err(e)) => Err(prefixed("Fee estimation error", e)),
+            Err(e) => Err(anyhow!("Fee estimation join error: {}", e)),
+        }
+    }
+
+    /// Mempool fee-rate histogram (async wrapper)
+    ///
+    /// Cached for FEE_CACHE_TTL, same as `estimate_fees`.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_fee_histogram(&self) -> Result<FeeHistogram> {
+        if let Some((fetched_at, histogram)) = self.histogram_cache.lock().unwrap().as_ref() {
+            if fetched_at.elapsed() < FEE_CACHE_TTL {
+                return Ok(histogram.clone());
+            }
+        }
+
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.get_fee_histogram_blocking()).await;
+
+        match res {
+            Ok(Ok(histogram)) => {
+                *self.histogram_cache.lock().unwrap() = Some((Instant::now(), histogram.clone()));
+                Ok(histogram)
+            }
+            Ok(Err(e)) => Err(prefixed("Fee histogram error", e)),
+            Err(e) => Err(anyhow!("Fee histogram join error: {}", e)),
+        }
+    }
+
+    /// Transaction size + fee lookup (async wrapper)
+    #[tracing::instrument(skip(self))]
+    pub async fn get_transaction_details(&self, txid: &str) -> Result<TxDetails> {
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let txid = txid.to_string();
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.get_transaction_details_blocking(&txid)).await;
+
+        match res {
+            Ok(Ok(details)) => Ok(details),
+            Ok(Err(e)) => Err(prefixed("Tx details error", e)),
+            Err(e) => Err(anyhow!("Tx details join error: {}", e)),
+        }
+    }
+
+    /// Block hash and time per height (async wrapper)
+    #[tracing::instrument(skip_all, fields(heights = heights.len()))]
+    pub async fn get_block_infos(&self, heights: &[u32]) -> Result<HashMap<u32, BlockInfo>> {
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let heights = heights.to_vec();
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.get_block_infos_blocking(&heights)).await;
+
+        match res {
+            Ok(Ok(infos)) => Ok(infos),
+            Ok(Err(e)) => Err(prefixed("Block header error", e)),
+            Err(e) => Err(anyhow!("Block header join error: {}", e)),
+        }
+    }
+
+    /// Confirmation state lookup (async wrapper)
+    #[tracing::instrument(skip(self))]
+    pub async fn get_tx_status(&self, txid: &str) -> Result<TxStatus> {
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let txid = txid.to_string();
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.get_tx_status_blocking(&txid)).await;
+
+        match res {
+            Ok(Ok(status)) => Ok(status),
+            Ok(Err(e)) => Err(prefixed("Tx status error", e)),
+            Err(e) => Err(anyhow!("Tx status join error: {}", e)),
+        }
+    }
+
+    /// Scripthash balance and history (async wrapper)
+    #[tracing::instrument(skip(self))]
+    pub async fn get_scripthash_summary(&self, scripthash: &str) -> Result<ScripthashSummary> {
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let scripthash = scripthash.to_string();
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.get_scripthash_summary_blocking(&scripthash)).await;
+
+        match res {
+            Ok(Ok(summary)) => Ok(summary),
+            Ok(Err(e)) => Err(prefixed("Scripthash lookup error", e)),
+            Err(e) => Err(anyhow!("Scripthash lookup join error: {}", e)),
+        }
+    }
+
+    /// Unconfirmed ancestor totals (async wrapper)
+    #[tracing::instrument(skip(self))]
+    pub async fn get_tx_ancestry(&self, txid: &str) -> Result<TxAncestry> {
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let txid = txid.to_string();
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.get_tx_ancestry_blocking(&txid)).await;
+
+        match res {
+            Ok(Ok(ancestry)) => Ok(ancestry),
+            Ok(Err(e)) => Err(prefixed("Tx ancestry error", e)),
+            Err(e) => Err(anyhow!("Tx ancestry join error: {}", e)),
+        }
+    }
+
+    /// Net amounts of several transactions for an address (async wrapper)
+    #[tracing::instrument(skip(self, txids), fields(txs = txids.len()))]
+    pub async fn get_transaction_amounts(&self, address: &str, txids: &[String]) -> Result<Vec<i64>> {
+        if txids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let addr = address.to_string();
+        let txids = txids.to_vec();
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.get_transaction_amounts_blocking(&addr, &txids)).await;
+
+        match res {
+            Ok(Ok(amounts)) => Ok(amounts),
+            Ok(Err(e)) => Err(prefixed("Tx amounts error", e)),
+            Err(e) => Err(anyhow!("Tx amounts join error: {}", e)),
+        }
+    }
+
+    /// Net transaction amount for an address (async wrapper)
+    #[tracing::instrument(skip(self))]
+    pub async fn get_transaction_amount(&self, address: &str, txid: &str) -> Result<i64> {
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let addr = address.to_string();
+        let txid = txid.to_string();
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.get_transaction_amount_blocking(&addr, &txid)).await;
+
+        match res {
+            Ok(Ok(amount)) => Ok(amount),
+            Ok(Err(e)) => Err(prefixed("Tx amount error", e)),
+            Err(e) => Err(anyhow!("Tx amount join error: {}", e)),
+        }
+    }
+
+    /// Mempool info (async wrapper)
+    #[tracing::instrument(skip(self))]
+    pub async fn get_mempool(&self) -> Result<MempoolInfo> {
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.get_mempool_blocking()).await;
+
+        match res {
+            Ok(Ok(info)) => Ok(info),
+            Ok(Err(e)) => Err(prefixed("Mempool info error", e)),
+            Err(e) => Err(anyhow!("Mempool info join error: {}", e)),
+        }
+    }
+
+    /// Current tip height, from header notifications when subscribed
+    ///
+    /// Notifications are only read off the socket during a call, so a ping
+    /// goes first to pick up blocks found while the connection sat idle.
+    pub async fn tip_height(&self) -> Result<u32> {
+        let this = self.clone();
+        let known = spawn_blocking(move || {
+            this.rate_limit();
+            this.client.ping()?;
+            this.poll_tip();
+            Ok::<_, anyhow::Error>(*this.balance_cache.tip.lock().unwrap())
+        })
+        .await
+        .map_err(|e| anyhow!("Tip height join error: {}", e))??;
+
+        match known {
+            Some(height) => Ok(height),
+            None => Ok(self.get_sync_status().await?.tip_height),
+        }
+    }
+
+    /// Electrs sync status (async wrapper)
+    #[tracing::instrument(skip(self))]
+    pub async fn get_sync_status(&self) -> Result<SyncStatus> {
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.get_sync_status_blocking()).await;
+
+        match res {
+            Ok(Ok(status)) => Ok(status),
+            Ok(Err(e)) => Err(prefixed("Sync status error", e)),
+            Err(e) => Err(anyhow!("Sync status join error: {}", e)),
+        }
+    }
+
+    /// Raw passthrough for allowlisted read-only Electrum methods (async wrapper)
+    ///
+    /// Parameters must be booleans, non-negative integers or short hex
+    /// strings (scripthashes, txids); anything else is rejected before the
+    /// call reaches Electrs.
+    #[tracing::instrument(skip(self, params))]
+    pub async fn proxy_call(
+        &self,
+        method: &str,
+        params: &[serde_json::Value],
+    ) -> Result<serde_json::Value> {
+
+        let params = validate_proxy_call(method, params)?;
+
+        self.check_cooldown()?;
+        let _permit = self.gate.acquire().await.unwrap();
+        self.check_cooldown()?;
+
+        let method = method.to_string();
+        let this = self.clone();
+
+        let res = spawn_blocking(move || this.proxy_call_blocking(&method, params)).await;
+
+        match res {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => Err(prefixed("Proxy call error", e)),
+            Err(e) => Err(anyhow!("Proxy call join error: {}", e)),
+        }
+    }
+
+    /// Get UTXOs (async wrapper)
+    #[tracing::instrument(skip_all, fields(addresses = addresses.len()))]
+    pub async fn get_utxos(&self, addresses: &[String]) -> Result<UtxoSet> {
+        // Only addresses without a fresh cached UTXO set go to Electrs
+        self.poll_tip();
+        let mut cached = HashMap::new();
+        let mut addrs = Vec::new();
+        let mut failed_addresses = Vec::new();
+        for address in addresses {
+            match self.utxo_cache.get(address) {
+                Some(utxos) => {
+                    cached.insert(address.clone(), utxos);
+                }
+                None if !addrs.contains(address) => addrs.push(address.clone()),
+                None => {}
+            }
+        }
+
+        if !addrs.is_empty() {
+            self.check_cooldown()?;
+            let _permit = self.gate.acquire().await.unwrap();
+            self.check_cooldown()?;
+
+            let this = self.clone();
+            let fetch = addrs.clone();
+
+            let res = spawn_blocking(move || this.get_utxos_blocking(&fetch)).await;
+
+            let fetched = match res {
+                Ok(Ok(set)) => set,
+                // Cached addresses can still be answered
+                Ok(Err(e)) if !cached.is_empty() => {
+                    warn!(err = %e, "UTXO fetch failed; returning cached addresses only");
+                    UtxoSet { utxos: vec![], failed_addresses: addrs.clone() }
+                }
+                Ok(Err(e)) => return Err(prefixed("UTXO fetch error", e)),
+                Err(e) => return Err(anyhow!("UTXO join error: {}", e)),
+            };
+
+            for address in addrs {
+                if fetched.failed_addresses.contains(&address) {
+                    failed_addresses.push(address);
+                    continue;
+                }
+
+                let utxos: Vec<_> = fetched.utxos.iter().filter(|u| u.address == address).cloned().collect();
+                self.utxo_cache.insert(&address, utxos.clone());
+                cached.insert(address, utxos);
+            }
+        }
+
+        // Keep the caller's address order
+        Ok(UtxoSet {
+            utxos: addresses
+                .iter()
+                .filter_map(|a| cached.remove(a))
+                .flatten()
+                .collect(),
+            failed_addresses,
+        })
+    }
+}
+
+/// Received minus spent by `script` in `tx`, in sats
+///
+/// Inputs whose prevout is missing from `prevouts` count as not ours.
+fn net_amount(
+    tx: &electrum_client::bitcoin::Transaction,
+    script: &ScriptBuf,
+    prevouts: &HashMap<electrum_client::bitcoin::OutPoint, electrum_client::bitcoin::TxOut>,
+) -> i64 {
+    let received: u64 = tx
+        .output
+        .iter()
+        .filter(|o| &o.script_pubkey == script)
+        .map(|o| o.value.to_sat())
+        .sum();
+
+    let sent: u64 = tx
+        .input
+        .iter()
+        .filter_map(|i| prevouts.get(&i.previous_output))
+        .filter(|o| &o.script_pubkey == script)
+        .map(|o| o.value.to_sat())
+        .sum();
+
+    received as i64 - sent as i64
+}
+
+/// Short name of an output script's type, for spending-size estimates
+fn script_type(script: &electrum_client::bitcoin::Script) -> &'static str {
+    if script.is_p2wpkh() {
+        "p2wpkh"
+    } else if script.is_p2tr() {
+        "p2tr"
+    } else if script.is_p2sh() {
+        "p2sh"
+    } else if script.is_p2wsh() {
+        "p2wsh"
+    } else if script.is_p2pkh() {
+        "p2pkh"
+    } else {
+        "unknown"
+    }
+}
+
+/// Check `method` against the allowlist and convert JSON params to Electrum params
+fn validate_proxy_call(method: &str, params: &[serde_json::Value]) -> Result<Vec<Param>> {
+    if !PROXY_METHODS.contains(&method) {
+        return Err(anyhow!("Method not allowed: {}", method));
+    }
+
+    params
+        .iter()
+        .map(|p| match p {
+            serde_json::Value::Bool(b) => Ok(Param::Bool(*b)),
+            serde_json::Value::Number(n) => n
+                .as_u64()
+                .and_then(|n| usize::try_from(n).ok())
+                .map(Param::Usize)
+                .ok_or_else(|| anyhow!("Invalid numeric param: {}", n)),
+            serde_json::Value::String(s)
+                if !s.is_empty()
+                    && s.len() <= PROXY_MAX_STRING_PARAM
+                    && s.chars().all(|c| c.is_ascii_hexdigit()) =>
+            {
+                Ok(Param::String(s.clone()))
+            }
+            other => Err(anyhow!("Invalid param: {}", other)),
+        })
+        .collect()
+}
+
+/// Parse a SHA-256 certificate fingerprint (hex, `:` separators allowed)
+fn parse_cert_fingerprint(raw: &str) -> Result<[u8; 32]> {
+    let hex_str: String = raw.trim().chars().filter(|c| *c != ':').collect();
+
+    let bytes = hex::decode(&hex_str)
+        .map_err(|e| anyhow!("Invalid NOMAD_ELECTRS_CERT_FINGERPRINT: {}", e))?;
+
+    bytes
+        .try_into()
+        .map_err(|_| anyhow!("NOMAD_ELECTRS_CERT_FINGERPRINT must be a SHA-256 hash (32 bytes)"))
+}
+
+/// The Electrum connection behind ElectrsClient
+///
+/// electrum-client's `Client` builds its own rustls config with no hook for
+/// a custom verifier, so a pinned certificate needs a `RawClient` over a
+/// TLS stream we set up ourselves.
+enum Backend {
+    Client(Client),
+    Pinned(PinnedClient),
+}
+
+/// TLS stream whose certificate was checked by PinnedCertVerifier
+type PinnedStream = rustls::StreamOwned<rustls::ClientConnection, std::net::TcpStream>;
+
+/// Where and how to (re)open the pinned connection
+struct PinnedTarget {
+    addr: String,
+    pin: [u8; 32],
+    connect_timeout: Duration,
+    socket_timeout: Option<Duration>,
+}
+
+impl PinnedTarget {
+    /// TLS handshake that only accepts a leaf certificate with the pinned
+    /// SHA-256 fingerprint; the same stream then carries the RPCs
+    fn connect(&self) -> Result<RawClient<PinnedStream>> {
+        use rustls::pki_types::ServerName;
+
+        let addr = self.addr.as_str();
+        let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|e| anyhow!("Invalid Electrs host '{}': {}", host, e))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier { pin: self.pin, provider }))
+            .with_no_client_auth();
+
+        let sock = addr
+            .to_socket_addrs()
+            .map_err(|e| anyhow!("Invalid ELECTRS_ADDR '{}': {}", addr, e))?
+            .next()
+            .ok_or_else(|| anyhow!("ELECTRS_ADDR '{}' did not resolve", addr))?;
+
+        let mut stream = std::net::TcpStream::connect_timeout(&sock, self.connect_timeout)
+            .map_err(|e| anyhow!("Electrs TLS failed to connect to {}: {}", addr, e))?;
+        let _ = stream.set_read_timeout(Some(self.connect_timeout));
+        let _ = stream.set_write_timeout(Some(self.connect_timeout));
+
+        let mut conn = rustls::ClientConnection::new(Arc::new(config), server_name)?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut stream)
+                .map_err(|e| anyhow!("Electrs TLS pin check failed: {}", e))?;
+        }
+
+        stream.set_read_timeout(self.socket_timeout)?;
+        stream.set_write_timeout(self.socket_timeout)?;
+
+        Ok(RawClient::from(rustls::StreamOwned::new(conn, stream)))
+    }
+}
+
+/// RawClient on a pinned TLS stream, reopened (and re-pinned) after a
+/// broken connection up to `retry` times per call, like electrum-client's
+/// own Client does
+struct PinnedClient {
+    raw: RwLock<RawClient<PinnedStream>>,
+    target: PinnedTarget,
+    retry: u8,
+}
+
+impl PinnedClient {
+    fn call<T>(
+        &self,
+        f: impl Fn(&RawClient<PinnedStream>) -> Result<T, electrum_client::Error>,
+    ) -> Result<T, electrum_client::Error> {
+        let mut attempts = 0;
+
+        loop {
+            let res = f(&self.raw.read().unwrap());
+
+            match res {
+                Err(electrum_client::Error::Protocol(_) | electrum_client::Error::AlreadySubscribed(_)) => {
+                    return res;
+                }
+                Err(e) if attempts < self.retry => {
+                    attempts += 1;
+                    warn!(err = %e, attempt = attempts, "Pinned Electrs connection failed; reconnecting");
+
+                    match self.target.connect() {
+                        Ok(raw) => *self.raw.write().unwrap() = raw,
+                        Err(e) => warn!(err = %e, "Pinned Electrs reconnect failed"),
+                    }
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+macro_rules! backend_call {
+    ($self:expr, $name:ident $(, $args:expr)*) => {
+        match $self {
+            Backend::Client(client) => client.$name($($args),*),
+            Backend::Pinned(pinned) => pinned.call(|raw| raw.$name($($args),*)),
+        }
+    };
+}
+
+impl ElectrumApi for Backend {
+    fn raw_call(
+        &self,
+        method_name: &str,
+        params: impl IntoIterator<Item = Param>,
+    ) -> Result<serde_json::Value, electrum_client::Error> {
+        let params: Vec<Param> = params.into_iter().collect();
+        backend_call!(self, raw_call, method_name, params.clone())
+    }
+
+    fn batch_call(&self, batch: &Batch) -> Result<Vec<serde_json::Value>, electrum_client::Error> {
+        backend_call!(self, batch_call, batch)
+    }
+
+    fn block_headers_subscribe_raw(&self) -> Result<RawHeaderNotification, electrum_client::Error> {
+        backend_call!(self, block_headers_subscribe_raw)
+    }
+
+    fn block_headers_pop_raw(&self) -> Result<Option<RawHeaderNotification>, electrum_client::Error> {
+        backend_call!(self, block_headers_pop_raw)
+    }
+
+    fn block_header_raw(&self, height: usize) -> Result<Vec<u8>, electrum_client::Error> {
+        backend_call!(self, block_header_raw, height)
+    }
+
+    fn block_headers(&self, start_height: usize, count: usize) -> Result<GetHeadersRes, electrum_client::Error> {
+        backend_call!(self, block_headers, start_height, count)
+    }
+
+    fn estimate_fee(&self, number: usize) -> Result<f64, electrum_client::Error> {
+        backend_call!(self, estimate_fee, number)
+    }
+
+    fn relay_fee(&self) -> Result<f64, electrum_client::Error> {
+        backend_call!(self, relay_fee)
+    }
+
+    fn script_subscribe(&self, script: &Script) -> Result<Option<ScriptStatus>, electrum_client::Error> {
+        backend_call!(self, script_subscribe, script)
+    }
+
+    fn batch_script_subscribe<'s, I>(&self, scripts: I) -> Result<Vec<Option<ScriptStatus>>, electrum_client::Error>
+    where
+        I: IntoIterator + Clone,
+        I::Item: Borrow<&'s Script>,
+    {
+        backend_call!(self, batch_script_subscribe, scripts.clone())
+    }
+
+    fn script_unsubscribe(&self, script: &Script) -> Result<bool, electrum_client::Error> {
+        backend_call!(self, script_unsubscribe, script)
+    }
+
+    fn script_pop(&self, script: &Script) -> Result<Option<ScriptStatus>, electrum_client::Error> {
+        backend_call!(self, script_pop, script)
+    }
+
+    fn script_get_balance(&self, script: &Script) -> Result<GetBalanceRes, electrum_client::Error> {
+        backend_call!(self, script_get_balance, script)
+    }
+
+    fn batch_script_get_balance<'s, I>(&self, scripts: I) -> Result<Vec<GetBalanceRes>, electrum_client::Error>
+    where
+        I: IntoIterator + Clone,
+        I::Item: Borrow<&'s Script>,
+    {
+        backend_call!(self, batch_script_get_balance, scripts.clone())
+    }
+
+    fn script_get_history(&self, script: &Script) -> Result<Vec<GetHistoryRes>, electrum_client::Error> {
+        backend_call!(self, script_get_history, script)
+    }
+
+    fn batch_script_get_history<'s, I>(&self, scripts: I) -> Result<Vec<Vec<GetHistoryRes>>, electrum_client::Error>
+    where
+        I: IntoIterator + Clone,
+        I::Item: Borrow<&'s Script>,
+    {
+        backend_call!(self, batch_script_get_history, scripts.clone())
+    }
+
+    fn script_list_unspent(&self, script: &Script) -> Result<Vec<ListUnspentRes>, electrum_client::Error> {
+        backend_call!(self, script_list_unspent, script)
+    }
+
+    fn batch_script_list_unspent<'s, I>(&self, scripts: I) -> Result<Vec<Vec<ListUnspentRes>>, electrum_client::Error>
+    where
+        I: IntoIterator + Clone,
+        I::Item: Borrow<&'s Script>,
+    {
+        backend_call!(self, batch_script_list_unspent, scripts.clone())
+    }
+
+    fn transaction_get_raw(&self, txid: &Txid) -> Result<Vec<u8>, electrum_client::Error> {
+        backend_call!(self, transaction_get_raw, txid)
+    }
+
+    fn batch_transaction_get_raw<'t, I>(&self, txids: I) -> Result<Vec<Vec<u8>>, electrum_client::Error>
+    where
+        I: IntoIterator + Clone,
+        I::Item: Borrow<&'t Txid>,
+    {
+        backend_call!(self, batch_transaction_get_raw, txids.clone())
+    }
+
+    fn batch_block_header_raw<I>(&self, heights: I) -> Result<Vec<Vec<u8>>, electrum_client::Error>
+    where
+        I: IntoIterator + Clone,
+        I::Item: Borrow<u32>,
+    {
+        backend_call!(self, batch_block_header_raw, heights.clone())
+    }
+
+    fn batch_estimate_fee<I>(&self, numbers: I) -> Result<Vec<f64>, electrum_client::Error>
+    where
+        I: IntoIterator + Clone,
+        I::Item: Borrow<usize>,
+    {
+        backend_call!(self, batch_estimate_fee, numbers.clone())
+    }
+
+    fn transaction_broadcast_raw(&self, raw_tx: &[u8]) -> Result<Txid, electrum_client::Error> {
+        backend_call!(self, transaction_broadcast_raw, raw_tx)
+    }
+
+    fn transaction_get_merkle(&self, txid: &Txid, height: usize) -> Result<GetMerkleRes, electrum_client::Error> {
+        backend_call!(self, transaction_get_merkle, txid, height)
+    }
+
+    fn server_features(&self) -> Result<ServerFeaturesRes, electrum_client::Error> {
+        backend_call!(self, server_features)
+    }
+
+    fn ping(&self) -> Result<(), electrum_client::Error> {
+        backend_call!(self, ping)
+    }
+}
+
+/// Accepts exactly one certificate, identified by its SHA-256 fingerprint
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    pin: [u8; 32],
+    provider: Arc<rustls::crypto::CryptoProvider>,
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> std::result::Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        use bitcoin::hashes::{sha256, Hash};
+
+        let fingerprint = sha256::Hash::hash(end_entity.as_ref()).to_byte_array();
+        if fingerprint == self.pin {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!(
+                "certificate fingerprint {} does not match the pinned one",
+                hex::encode(fingerprint)
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// `e` prefixed with `context`, keeping its ErrorCode
+fn prefixed(context: &str, e: anyhow::Error) -> anyhow::Error {
+    ErrorCode::of(&e).error(format!("{}: {}", context, e))
+}
+
+/// Name this client to Electrs with `server.version`
+///
+/// The Electrum protocol's equivalent of a User-Agent: the name shows up
+/// in Electrs's connection logs. Failure is only logged; servers that
+/// don't implement it still answer the other calls.
+fn identify(client: &impl ElectrumApi) {
+    let params = vec![
+        Param::String(CLIENT_NAME.to_string()),
+        Param::String(ELECTRUM_PROTOCOL_VERSION.to_string()),
+    ];
+
+    match client.raw_call("server.version", params) {
+        Ok(reply) => info!(client = CLIENT_NAME, server = %reply, "Electrs identified"),
+        Err(e) => warn!(client = CLIENT_NAME, err = %e, "Electrs server.version failed"),
+    }
+}
+
+/// `tokio::task::spawn_blocking` that keeps the caller's tracing span
+///
+/// Electrs calls run on the blocking pool; carrying the span over tags their
+/// log lines with the Nostr `req` of the request being served.
+fn spawn_blocking<F, R>(f: F) -> tokio::task::JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(f))
+}
+
+fn preflight_tcp(addr: &str, connect_timeout: Duration) -> Result<()> {
+    let mut addrs = addr
+        .to_socket_addrs()
+        .map_err(|e| anyhow!("Invalid ELECTRS_ADDR '{}': {}", addr, e))?;
 
     let sock = addrs
         .next()
         .ok_or_else(|| anyhow!("ELECTRS_ADDR '{}' did not resolve", addr))?;
 
-    let stream = std::net::TcpStream::connect_timeout(&sock, Duration::from_secs(3))
+    let stream = std::net::TcpStream::connect_timeout(&sock, connect_timeout)
         .map_err(|e| anyhow!("Electrs TCP preflight failed to {}: {}", addr, e))?;
 
     let _ = stream.set_read_timeout(Some(Duration::from_secs(3)));
@@ -391,3 +2342,622 @@ fn preflight_tcp(addr: &str) -> Result<()> {
 
     Ok(())
 }
+
+/// Mock Electrum server for tests here and in other modules
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    /// What the mock answers to one request; `Hangup` closes the connection
+    pub(crate) enum Reply {
+        Result(Value),
+        Error(&'static str),
+        Hangup,
+        /// Header notification at this height, then `Result`
+        NewTip(u32, Value),
+    }
+
+    type Handler = Arc<dyn Fn(&str, &Value) -> Reply + Send + Sync>;
+
+    /// Methods received in each round trip, i.e. each write of the client
+    type RoundTrips = Arc<Mutex<Vec<Vec<String>>>>;
+
+    /// Electrum server speaking line-delimited JSON-RPC on a local port
+    pub(crate) struct MockElectrum {
+        pub(crate) addr: String,
+        pub(crate) round_trips: RoundTrips,
+    }
+
+    impl MockElectrum {
+        pub(crate) fn start(handler: impl Fn(&str, &Value) -> Reply + Send + Sync + 'static) -> Self {
+            Self::with_latency(Duration::ZERO, handler)
+        }
+
+        /// Server that waits `latency` before answering each round trip,
+        /// like a remote Electrs would
+        pub(crate) fn with_latency(
+            latency: Duration,
+            handler: impl Fn(&str, &Value) -> Reply + Send + Sync + 'static,
+        ) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            let handler: Handler = Arc::new(handler);
+            let round_trips = RoundTrips::default();
+
+            let recorded = round_trips.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let (handler, recorded) = (handler.clone(), recorded.clone());
+                    std::thread::spawn(move || serve(stream, handler, latency, recorded));
+                }
+            });
+
+            Self { addr, round_trips }
+        }
+
+        pub(crate) fn client(&self) -> Result<ElectrsClient> {
+            ElectrsClient::connect(&self.addr, Network::Bitcoin, tcp_config())
+        }
+    }
+
+    fn serve(stream: TcpStream, handler: Handler, latency: Duration, round_trips: RoundTrips) {
+        let _ = stream.set_nodelay(true);
+        let mut writer = stream.try_clone().unwrap();
+        // Large enough that a pipelined batch is read in one go
+        let mut reader = BufReader::with_capacity(1 << 16, stream);
+
+        loop {
+            // Nothing left of the last write: this line starts a round trip
+            let new_round_trip = reader.buffer().is_empty();
+            let mut line = String::new();
+            if !matches!(reader.read_line(&mut line), Ok(n) if n > 0) {
+                return;
+            }
+            let Ok(req) = serde_json::from_str::<Value>(&line) else { return };
+
+            if new_round_trip {
+                std::thread::sleep(latency);
+                round_trips.lock().unwrap().push(Vec::new());
+            }
+            let method = req["method"].as_str().unwrap_or_default();
+            round_trips.lock().unwrap().last_mut().unwrap().push(method.to_string());
+
+            let id = req["id"].clone();
+            let reply = match handler(method, &req["params"]) {
+                Reply::Result(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Reply::NewTip(height, result) => {
+                    let notification = json!({
+                        "jsonrpc": "2.0",
+                        "method": "blockchain.headers.subscribe",
+                        "params": [{ "height": height, "hex": "00".repeat(80) }],
+                    });
+                    if writeln!(writer, "{}", notification).is_err() {
+                        return;
+                    }
+                    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+                }
+                Reply::Error(message) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": 1, "message": message },
+                }),
+                Reply::Hangup => return,
+            };
+
+            if writeln!(writer, "{}", reply).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// No reconnect attempts, so a broken connection fails the call at once
+    pub(crate) fn tcp_config() -> crate::config::ElectrsConnectionConfig {
+        crate::config::ElectrsConnectionConfig {
+            protocol: crate::config::ElectrsProtocol::Tcp,
+            connect_timeout: Duration::from_secs(2),
+            socket_timeout: Some(2),
+            retry: 0,
+            proxy: None,
+            cert_fingerprint: None,
+        }
+    }
+
+    /// Electrum scripthash of an address, as clients send it
+    pub(crate) fn scripthash(address: &str) -> String {
+        use electrum_client::ToElectrumScriptHash;
+
+        let script = Address::from_str(address).unwrap().assume_checked().script_pubkey();
+        hex::encode(*script.to_electrum_scripthash())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{tcp_config, MockElectrum, Reply};
+    use super::*;
+    use electrum_client::bitcoin::consensus::encode::serialize_hex;
+    use electrum_client::bitcoin::{absolute, transaction, Amount, OutPoint, Transaction, TxIn, TxOut};
+    use serde_json::{json, Value};
+    use std::net::TcpListener;
+
+    const ADDRESS: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+    const TXID: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+    fn test_tx() -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut { value: Amount::from_sat(1_000), script_pubkey: ScriptBuf::new() }],
+        }
+    }
+
+    /// Non-coinbase tx, so a confirmed output of it is spendable at once
+    fn spending_tx() -> Transaction {
+        let mut tx = test_tx();
+        tx.input[0].previous_output = OutPoint::new(TXID.parse().unwrap(), 0);
+        tx
+    }
+
+    /// Tip at `tip` and one UTXO of spending_tx mined at `height` (0 = mempool)
+    fn utxo_server(tip: u32, height: u32) -> MockElectrum {
+        MockElectrum::start(move |method, _| match method {
+            "blockchain.headers.subscribe" => {
+                Reply::Result(json!({ "height": tip, "hex": "00".repeat(80) }))
+            }
+            "blockchain.scripthash.listunspent" => Reply::Result(json!([{
+                "tx_hash": spending_tx().compute_txid().to_string(),
+                "tx_pos": 0,
+                "height": height,
+                "value": 1_000,
+            }])),
+            "blockchain.transaction.get" => Reply::Result(json!(serialize_hex(&spending_tx()))),
+            _ => Reply::Error("unsupported"),
+        })
+    }
+
+    #[tokio::test]
+    async fn mempool_utxo_has_zero_confirmations() {
+        let electrs = utxo_server(800_000, 0).client().unwrap();
+        let set = electrs.get_utxos(&[ADDRESS.to_string()]).await.unwrap();
+
+        assert_eq!(set.utxos.len(), 1);
+        assert_eq!(set.utxos[0].confirmations, 0);
+        assert!(!set.utxos[0].spendable);
+    }
+
+    #[tokio::test]
+    async fn utxo_in_tip_block_has_one_confirmation() {
+        let electrs = utxo_server(800_000, 800_000).client().unwrap();
+        let set = electrs.get_utxos(&[ADDRESS.to_string()]).await.unwrap();
+
+        assert_eq!(set.utxos[0].confirmations, 1);
+        assert!(set.utxos[0].spendable);
+    }
+
+    #[tokio::test]
+    async fn utxos_fail_without_tip() {
+        let mock = MockElectrum::start(|method, _| match method {
+            "blockchain.scripthash.listunspent" => Reply::Result(json!([])),
+            _ => Reply::Error("unsupported"),
+        });
+
+        let electrs = mock.client().unwrap();
+        let err = electrs.get_utxos(&[ADDRESS.to_string()]).await.unwrap_err();
+
+        assert!(err.to_string().contains("Tip height unavailable"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn utxos_take_one_round_trip_per_batch() {
+        let scripts: Vec<ScriptBuf> = (0..2 * UTXO_BATCH_SIZE as u16)
+            .map(|i| Address::p2wsh(&ScriptBuf::from_bytes(i.to_be_bytes().to_vec()), Network::Bitcoin).script_pubkey())
+            .collect();
+        let addresses: Vec<String> = scripts
+            .iter()
+            .map(|s| Address::from_script(s, Network::Bitcoin).unwrap().to_string())
+            .collect();
+        let mock = MockElectrum::with_latency(Duration::from_millis(20), |method, _| match method {
+            "blockchain.headers.subscribe" => {
+                Reply::Result(json!({ "height": 800_000, "hex": "00".repeat(80) }))
+            }
+            "blockchain.scripthash.listunspent" => Reply::Result(json!([])),
+            _ => Reply::Error("unsupported"),
+        });
+        let electrs = mock.client().unwrap();
+        let list_unspent_round_trips = || -> Vec<usize> {
+            let round_trips = mock.round_trips.lock().unwrap();
+            round_trips
+                .iter()
+                .filter(|methods| methods.iter().any(|m| m == "blockchain.scripthash.listunspent"))
+                .map(Vec::len)
+                .collect()
+        };
+
+        let started = Instant::now();
+        let set = electrs.get_utxos(&addresses).await.unwrap();
+        let batched = started.elapsed();
+
+        assert!(set.failed_addresses.is_empty());
+        assert_eq!(list_unspent_round_trips(), [UTXO_BATCH_SIZE, UTXO_BATCH_SIZE]);
+
+        // The same lookups one address at a time
+        let this = electrs.clone();
+        let started = Instant::now();
+        spawn_blocking(move || {
+            for script in &scripts {
+                this.client.script_list_unspent(script).unwrap();
+            }
+        })
+        .await
+        .unwrap();
+        let sequential = started.elapsed();
+
+        // Still far slower, though it skips the 100ms spacing get_utxos keeps
+        // between its calls
+        assert_eq!(list_unspent_round_trips().len(), 2 + addresses.len());
+        assert!(batched * 4 < sequential, "batched {:?}, sequential {:?}", batched, sequential);
+    }
+
+    fn script_of(address: &str) -> ScriptBuf {
+        Address::from_str(address).unwrap().assume_checked().script_pubkey()
+    }
+
+    fn out(script: &ScriptBuf, sats: u64) -> TxOut {
+        TxOut { value: Amount::from_sat(sats), script_pubkey: script.clone() }
+    }
+
+    /// Tx spending `inputs` (prevout, funded output) into `outputs`
+    fn tx_with(inputs: &[(OutPoint, TxOut)], outputs: Vec<TxOut>) -> (Transaction, HashMap<OutPoint, TxOut>) {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|(prevout, _)| TxIn { previous_output: *prevout, ..TxIn::default() })
+                .collect(),
+            output: outputs,
+        };
+        (tx, inputs.iter().cloned().collect())
+    }
+
+    #[test]
+    fn net_amount_nets_spent_inputs_against_change() {
+        let ours = script_of(ADDRESS);
+        let theirs = script_of("bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g");
+        let prevout = OutPoint::new(TXID.parse().unwrap(), 0);
+
+        // Spend 5000 of ours: 3500 to someone else, 1200 change back, 300 fee
+        let (tx, prevouts) = tx_with(&[(prevout, out(&ours, 5_000))], vec![out(&theirs, 3_500), out(&ours, 1_200)]);
+        assert_eq!(net_amount(&tx, &ours, &prevouts), -3_800);
+        assert_eq!(net_amount(&tx, &theirs, &prevouts), 3_500);
+
+        // Sending to ourselves only costs the fee
+        let (tx, prevouts) = tx_with(&[(prevout, out(&ours, 2_000))], vec![out(&ours, 1_900)]);
+        assert_eq!(net_amount(&tx, &ours, &prevouts), -100);
+    }
+
+    #[test]
+    fn net_amount_counts_every_matching_input_and_output() {
+        let ours = script_of(ADDRESS);
+        let theirs = script_of("bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g");
+        let a = OutPoint::new(TXID.parse().unwrap(), 0);
+        let b = OutPoint::new(TXID.parse().unwrap(), 1);
+        let c = OutPoint::new(TXID.parse().unwrap(), 2);
+
+        let (tx, prevouts) = tx_with(
+            &[(a, out(&ours, 1_000)), (b, out(&theirs, 4_000)), (c, out(&ours, 2_000))],
+            vec![out(&ours, 500), out(&theirs, 5_800), out(&ours, 250)],
+        );
+        assert_eq!(net_amount(&tx, &ours, &prevouts), 750 - 3_000);
+    }
+
+    #[test]
+    fn net_amount_treats_unknown_prevouts_as_not_ours() {
+        let ours = script_of(ADDRESS);
+        let prevout = OutPoint::new(TXID.parse().unwrap(), 0);
+
+        let (tx, _) = tx_with(&[(prevout, out(&ours, 5_000))], vec![out(&ours, 4_000)]);
+        assert_eq!(net_amount(&tx, &ours, &HashMap::new()), 4_000);
+    }
+
+    #[tokio::test]
+    async fn single_flight_runs_one_lookup_for_concurrent_callers() {
+        use std::sync::atomic::AtomicUsize;
+
+        let flight = Arc::new(SingleFlight::new());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let callers = (0..10).map(|_| {
+            let runs = runs.clone();
+            flight.run("k".to_string(), async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(7u32)
+            })
+        });
+        let results = futures::future::join_all(callers).await;
+
+        assert!(results.iter().all(|r| *r.as_ref().unwrap() == 7));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(flight.inflight.lock().unwrap().is_empty());
+
+        // Done lookups are not reused: a later caller runs its own
+        let later = flight.run("k".to_string(), async { Ok(8u32) }).await.unwrap();
+        assert_eq!(later, 8);
+    }
+
+    #[tokio::test]
+    async fn single_flight_shares_errors_with_their_code() {
+        let flight: Arc<SingleFlight<u32>> = Arc::new(SingleFlight::new());
+
+        let fail = || {
+            flight.run("k".to_string(), async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Err(ErrorCode::Timeout.error("lookup timeout"))
+            })
+        };
+        let (a, b) = tokio::join!(fail(), fail());
+
+        for err in [a.unwrap_err(), b.unwrap_err()] {
+            assert_eq!(ErrorCode::of(&err), ErrorCode::Timeout);
+            assert_eq!(err.to_string(), "lookup timeout");
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_balance_lookups_share_one_electrs_call() {
+        use std::sync::atomic::AtomicUsize;
+
+        let history_calls = Arc::new(AtomicUsize::new(0));
+        let calls = history_calls.clone();
+        let mock = MockElectrum::start(move |method, _| match method {
+            "blockchain.scripthash.get_history" => {
+                calls.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(200));
+                Reply::Result(json!([{ "tx_hash": TXID, "height": 800_000 }]))
+            }
+            "blockchain.scripthash.get_balance" => {
+                Reply::Result(json!({ "confirmed": 1_500, "unconfirmed": 0 }))
+            }
+            _ => Reply::Error("unsupported"),
+        });
+
+        let electrs = mock.client().unwrap();
+        let lookups = (0..10).map(|_| electrs.get_address_balance(ADDRESS));
+        let results = futures::future::join_all(lookups).await;
+
+        assert!(results.iter().all(|r| *r.as_ref().unwrap() == (1_500, 0)));
+        assert_eq!(history_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn new_tip_clears_balance_and_utxo_caches() {
+        use std::sync::atomic::AtomicUsize;
+
+        let history_calls = Arc::new(AtomicUsize::new(0));
+        let unspent_calls = Arc::new(AtomicUsize::new(0));
+        let (history, unspent) = (history_calls.clone(), unspent_calls.clone());
+        // A block is found by the time of the first ping
+        let tip = Arc::new(AtomicU64::new(800_000));
+        let mock = MockElectrum::start(move |method, _| match method {
+            "blockchain.headers.subscribe" => {
+                Reply::Result(json!({ "height": tip.load(Ordering::SeqCst), "hex": "00".repeat(80) }))
+            }
+            "server.ping" => {
+                tip.store(800_001, Ordering::SeqCst);
+                Reply::NewTip(800_001, Value::Null)
+            }
+            "blockchain.scripthash.get_history" => {
+                history.fetch_add(1, Ordering::SeqCst);
+                Reply::Result(json!([{ "tx_hash": TXID, "height": 800_000 }]))
+            }
+            "blockchain.scripthash.get_balance" => {
+                Reply::Result(json!({ "confirmed": 1_000, "unconfirmed": 0 }))
+            }
+            "blockchain.scripthash.listunspent" => {
+                unspent.fetch_add(1, Ordering::SeqCst);
+                Reply::Result(json!([{
+                    "tx_hash": spending_tx().compute_txid().to_string(),
+                    "tx_pos": 0,
+                    "height": 800_000,
+                    "value": 1_000,
+                }]))
+            }
+            "blockchain.transaction.get" => Reply::Result(json!(serialize_hex(&spending_tx()))),
+            _ => Reply::Error("unsupported"),
+        });
+        let electrs = mock.client().unwrap();
+        let addresses = [ADDRESS.to_string()];
+
+        // Second lookups at the same tip come from the caches
+        for _ in 0..2 {
+            electrs.get_address_balance(ADDRESS).await.unwrap();
+            assert_eq!(electrs.get_utxos(&addresses).await.unwrap().utxos[0].confirmations, 1);
+        }
+        assert_eq!((history_calls.load(Ordering::SeqCst), unspent_calls.load(Ordering::SeqCst)), (1, 1));
+
+        assert_eq!(electrs.tip_height().await.unwrap(), 800_001);
+
+        electrs.get_address_balance(ADDRESS).await.unwrap();
+        let set = electrs.get_utxos(&addresses).await.unwrap();
+        assert_eq!((history_calls.load(Ordering::SeqCst), unspent_calls.load(Ordering::SeqCst)), (2, 2));
+        assert_eq!(set.utxos[0].confirmations, 2);
+    }
+
+    #[tokio::test]
+    async fn concurrent_history_lookups_share_one_electrs_call() {
+        use std::sync::atomic::AtomicUsize;
+
+        let history_calls = Arc::new(AtomicUsize::new(0));
+        let calls = history_calls.clone();
+        let mock = MockElectrum::start(move |method, _| match method {
+            "blockchain.scripthash.get_history" => {
+                calls.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(200));
+                Reply::Result(json!([{ "tx_hash": TXID, "height": 800_000 }]))
+            }
+            _ => Reply::Error("unsupported"),
+        });
+
+        let electrs = mock.client().unwrap();
+        let lookups = (0..5).map(|_| electrs.get_address_txs(ADDRESS));
+        let results = futures::future::join_all(lookups).await;
+
+        assert!(results.iter().all(|r| r.as_ref().unwrap() == &[TXID.to_string()]));
+        assert_eq!(history_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn balance_from_history_and_balance_calls() {
+        let mock = MockElectrum::start(|method, _| match method {
+            "blockchain.scripthash.get_history" => {
+                Reply::Result(json!([{ "tx_hash": TXID, "height": 800_000 }]))
+            }
+            "blockchain.scripthash.get_balance" => {
+                Reply::Result(json!({ "confirmed": 1_500, "unconfirmed": -200 }))
+            }
+            _ => Reply::Error("unsupported"),
+        });
+
+        let electrs = mock.client().unwrap();
+        assert_eq!(electrs.get_address_balance(ADDRESS).await.unwrap(), (1_500, -200));
+    }
+
+    #[tokio::test]
+    async fn unused_address_skips_balance_call() {
+        let mock = MockElectrum::start(|method, _| match method {
+            "blockchain.scripthash.get_history" => Reply::Result(json!([])),
+            _ => Reply::Error("unexpected call"),
+        });
+
+        let electrs = mock.client().unwrap();
+        assert_eq!(electrs.get_address_balance(ADDRESS).await.unwrap(), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn history_marks_mempool_entries() {
+        let mock = MockElectrum::start(|method, _| match method {
+            "blockchain.scripthash.get_history" => Reply::Result(json!([
+                { "tx_hash": TXID, "height": 800_000 },
+                { "tx_hash": TXID.replace('4', "5"), "height": 0 },
+            ])),
+            _ => Reply::Error("unsupported"),
+        });
+
+        let electrs = mock.client().unwrap();
+        let history = electrs.get_address_history_entries(ADDRESS).await.unwrap();
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].txid, TXID);
+        assert_eq!(history[0].height, Some(800_000));
+        assert_eq!(history[1].height, None);
+    }
+
+    #[tokio::test]
+    async fn broadcast_returns_txid() {
+        let tx = test_tx();
+        let txid = tx.compute_txid().to_string();
+        let expected = txid.clone();
+
+        let mock = MockElectrum::start(move |method, params| match method {
+            "blockchain.transaction.broadcast" if params[0] == serialize_hex(&test_tx()) => {
+                Reply::Result(json!(txid))
+            }
+            _ => Reply::Error("unsupported"),
+        });
+
+        let electrs = mock.client().unwrap();
+        let broadcast = electrs.broadcast_transaction(&serialize_hex(&tx)).await.unwrap();
+
+        assert_eq!(broadcast.txid, expected);
+        assert_eq!(broadcast.via, "electrs");
+    }
+
+    #[tokio::test]
+    async fn broadcast_reject_carries_reason() {
+        let mock = MockElectrum::start(|method, _| match method {
+            "blockchain.transaction.broadcast" => Reply::Error(
+                "sendrawtransaction RPC error: {\"code\":-26,\"message\":\"min relay fee not met\"}",
+            ),
+            _ => Reply::Error("unsupported"),
+        });
+
+        let electrs = mock.client().unwrap();
+        let err = electrs.broadcast_transaction(&serialize_hex(&test_tx())).await.unwrap_err();
+
+        assert!(err.to_string().contains("min relay fee not met"), "{}", err);
+    }
+
+    #[test]
+    fn connect_fails_without_server() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+        let err = ElectrsClient::connect(&addr.to_string(), Network::Bitcoin, tcp_config())
+            .err()
+            .expect("nothing listens on the port");
+
+        assert!(err.to_string().contains("preflight"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn address_validation_follows_the_network() {
+        let electrs = MockElectrum::start(|_, _| Reply::Error("unsupported")).client().unwrap();
+
+        let valid = [
+            ADDRESS,                                                          // P2WPKH
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr", // P2TR
+            "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",                             // P2PKH
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",                             // P2SH
+            "BC1QCR8TE4KR609GCAWUTMRZA0J4XV80JY8Z306FYU",                     // bech32 in upper case
+        ];
+        for address in valid {
+            assert!(electrs.is_valid_address(address), "{}", address);
+        }
+
+        let invalid = [
+            "",
+            "hello",
+            TXID,
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyv",  // bad checksum
+            "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb",          // bad checksum
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",  // testnet
+            "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn",          // testnet legacy
+        ];
+        for address in invalid {
+            assert!(!electrs.is_valid_address(address), "{}", address);
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_carry_their_code() {
+        let mock = MockElectrum::start(|_, _| Reply::Error("unsupported"));
+        let electrs = mock.client().unwrap();
+
+        let err = electrs.get_tx_status("not-a-txid").await.unwrap_err();
+        assert_eq!(ErrorCode::of(&err), ErrorCode::InvalidTxid);
+
+        // Through the single-flight lookup as well
+        let err = electrs.get_address_balance("not-an-address").await.unwrap_err();
+        assert_eq!(ErrorCode::of(&err), ErrorCode::InvalidAddress);
+
+        let err = electrs.get_address_balance(ADDRESS).await.unwrap_err();
+        assert_eq!(ErrorCode::of(&err), ErrorCode::ElectrsUnavailable);
+    }
+
+    #[tokio::test]
+    async fn dropped_connection_fails_the_call() {
+        let mock = MockElectrum::start(|method, _| match method {
+            "server.version" => Reply::Result(json!(["mock", "1.4"])),
+            _ => Reply::Hangup,
+        });
+
+        let electrs = mock.client().unwrap();
+        let err = electrs.get_address_balance(ADDRESS).await.unwrap_err();
+
+        assert!(err.to_string().contains("Electrs balance error"), "{}", err);
+    }
+}
@@ -19,3 +19,54 @@ pub enum ServerError {
 
 pub type ServerResult<T> = Result<T, ServerError>;
 
+
+/// Machine-readable class of a failed request, sent to wallets as
+/// `error_code`
+///
+/// Attached where an error is created (see `ErrorCode::error`) so wallets
+/// can branch on it instead of parsing the human `error` text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Timeout,
+    InvalidAddress,
+    InvalidTxid,
+    InvalidTx,
+    InvalidXpub,
+    ElectrsUnavailable,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Timeout => "timeout",
+            Self::InvalidAddress => "invalid_address",
+            Self::InvalidTxid => "invalid_txid",
+            Self::InvalidTx => "invalid_tx",
+            Self::InvalidXpub => "invalid_xpub",
+            Self::ElectrsUnavailable => "electrs_unavailable",
+        }
+    }
+
+    /// An error reading `message` that reports this code
+    pub fn error(self, message: impl std::fmt::Display) -> anyhow::Error {
+        CodedError { code: self, message: message.to_string() }.into()
+    }
+
+    /// Code attached to `e` or one of its causes
+    ///
+    /// Errors without one come from talking to Electrs (connection, RPC
+    /// failures), hence `ElectrsUnavailable`.
+    pub fn of(e: &anyhow::Error) -> Self {
+        e.chain()
+            .find_map(|cause| cause.downcast_ref::<CodedError>())
+            .map_or(Self::ElectrsUnavailable, |coded| coded.code)
+    }
+}
+
+/// Error that carries its ErrorCode; displays as just the message
+#[derive(Error, Debug)]
+#[error("{message}")]
+pub struct CodedError {
+    code: ErrorCode,
+    message: String,
+}
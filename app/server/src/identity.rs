@@ -2,18 +2,76 @@
 //!
 //! Handles generation and persistence of Nostr keypairs for the Umbrel node.
 
-use nostr_sdk::{Keys, SecretKey};
+use anyhow::{anyhow, Result};
+use bitcoin::base64::engine::general_purpose::STANDARD as BASE64;
+use bitcoin::base64::Engine;
+use nostr_sdk::nips::nip06::FromMnemonic;
+use nostr_sdk::nips::nip49::{EncryptedSecretKey, KeySecurity};
+use nostr_sdk::{FromBech32, Keys, SecretKey};
+use std::env;
 use std::fs;
 use std::path::Path;
 
 const DATA_DIR: &str = "/data";
 const KEY_FILE: &str = "/data/nostr_secret.hex";
+const KEY_FILENAME: &str = "nostr_secret.hex";
 
+/// scrypt cost (log2 N) used for key backups
+#[cfg(not(test))]
+const BACKUP_SCRYPT_LOG_N: u8 = 16;
+/// Cheap in tests; the cost is stored in the backup, so import is unaffected
+#[cfg(test)]
+const BACKUP_SCRYPT_LOG_N: u8 = 8;
+
+/// Load the server keys
+///
+/// NOMAD_NSEC, when set, takes precedence over the stored key (see
+/// `import_key`). Otherwise the persisted key is loaded, or a new one is
+/// generated and persisted.
 pub fn load_or_create_keys() -> Keys {
     fs::create_dir_all(DATA_DIR).ok();
 
-    if Path::new(KEY_FILE).exists() {
-        let hex_str = fs::read_to_string(KEY_FILE)
+    if let Ok(source) = env::var("NOMAD_NSEC") {
+        let passphrase = env::var("NOMAD_MNEMONIC_PASSPHRASE").ok();
+        let account = env::var("NOMAD_NOSTR_ACCOUNT").ok().map(|a| {
+            a.trim()
+                .parse::<u32>()
+                .expect("NOMAD_NOSTR_ACCOUNT must be a non-negative integer")
+        });
+
+        let keys = import_key(&source, passphrase.as_deref(), account)
+            .expect("Invalid NOMAD_NSEC");
+
+        if Path::new(KEY_FILE).exists() {
+            log::warn!(
+                "NOMAD_NSEC is set; ignoring the stored key in {} (paired wallets must re-pair if the pubkey changed)",
+                KEY_FILE
+            );
+        }
+
+        log::info!("Imported Nostr pubkey from NOMAD_NSEC: {}", keys.public_key().to_hex());
+
+        return keys;
+    }
+
+    load_or_generate(Path::new(KEY_FILE))
+}
+
+/// Load the keys of a hosted tenant from `dir`, generating them on first use
+///
+/// Tenants have no NOMAD_NSEC override; each keeps its own key file.
+pub fn load_or_create_tenant_keys(dir: &Path) -> Keys {
+    fs::create_dir_all(dir).ok();
+
+    load_or_generate(&dir.join(KEY_FILENAME))
+}
+
+/// Load the hex key persisted at `path`, or generate and persist a new one
+fn load_or_generate(path: &Path) -> Keys {
+    if path.exists() {
+        check_key_permissions(path);
+
+        let hex_str = fs::read_to_string(path)
             .expect("Failed to read nostr secret key file")
             .trim()
             .to_string();
@@ -38,7 +96,7 @@ pub fn load_or_create_keys() -> Keys {
         let secret = keys.secret_key();
         let hex_str = hex::encode(secret.as_secret_bytes());
 
-        fs::write(KEY_FILE, &hex_str)
+        write_private(path, &hex_str)
             .expect("Failed to persist nostr secret key");
 
         log::info!(
@@ -50,3 +108,216 @@ pub fn load_or_create_keys() -> Keys {
     }
 }
 
+/// Write `contents` to a new file readable only by its owner
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    fs::write(path, contents)
+}
+
+/// Warn about (and tighten to 0600) a key file group/world can read
+///
+/// With NOMAD_STRICT_KEY_PERMISSIONS=1 the server refuses to start instead.
+#[cfg(unix)]
+fn check_key_permissions(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = match fs::metadata(path) {
+        Ok(meta) => meta.permissions().mode() & 0o777,
+        Err(e) => {
+            log::warn!("Could not stat {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    if mode & 0o077 == 0 {
+        return;
+    }
+
+    let strict = env::var("NOMAD_STRICT_KEY_PERMISSIONS")
+        .is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"));
+    if strict {
+        panic!(
+            "{} has mode {:o}; the server key must not be group/world readable (chmod 600 it)",
+            path.display(),
+            mode
+        );
+    }
+
+    log::warn!(
+        "SECURITY: {} has mode {:o} and is readable by other users; anyone who can read it can impersonate this server",
+        path.display(),
+        mode
+    );
+
+    match fs::set_permissions(path, fs::Permissions::from_mode(0o600)) {
+        Ok(()) => log::warn!("Tightened {} to mode 600", path.display()),
+        Err(e) => log::warn!("Failed to chmod {} to 600: {}", path.display(), e),
+    }
+}
+
+#[cfg(not(unix))]
+fn check_key_permissions(_path: &Path) {}
+
+/// Import keys from a bech32 `nsec` or a BIP-39 mnemonic
+///
+/// Mnemonics are derived per NIP-06 (m/44'/1237'/<account>'/0/0, account
+/// defaulting to 0) with an optional BIP-39 passphrase.
+pub fn import_key(source: &str, passphrase: Option<&str>, account: Option<u32>) -> Result<Keys> {
+    let source = source.trim();
+
+    if source.starts_with("nsec1") {
+        let secret_key = SecretKey::from_bech32(source)
+            .map_err(|e| anyhow!("Invalid nsec: {}", e))?;
+        return Ok(Keys::new(secret_key));
+    }
+
+    if source.split_whitespace().count() > 1 {
+        return Keys::from_mnemonic_with_account(source, passphrase, account)
+            .map_err(|e| anyhow!("Invalid mnemonic: {}", e));
+    }
+
+    Err(anyhow!("Expected an nsec1... key or a BIP-39 mnemonic"))
+}
+
+/// Encrypt the secret key with a passphrase for backup
+///
+/// Uses NIP-49 (scrypt + XChaCha20-Poly1305). The result is the base64 of the
+/// NIP-49 payload, whose first byte is the format version.
+pub fn export_encrypted(keys: &Keys, passphrase: &str) -> Result<String> {
+    if passphrase.is_empty() {
+        return Err(anyhow!("Passphrase must not be empty"));
+    }
+
+    let encrypted = EncryptedSecretKey::new(
+        keys.secret_key(),
+        passphrase,
+        BACKUP_SCRYPT_LOG_N,
+        KeySecurity::Unknown,
+    )
+    .map_err(|e| anyhow!("Failed to encrypt secret key: {}", e))?;
+
+    Ok(BASE64.encode(encrypted.as_vec()))
+}
+
+/// Decrypt a backup produced by `export_encrypted`
+pub fn import_encrypted(blob: &str, passphrase: &str) -> Result<Keys> {
+    let bytes = BASE64
+        .decode(blob.trim())
+        .map_err(|e| anyhow!("Invalid backup encoding: {}", e))?;
+
+    let encrypted = EncryptedSecretKey::from_slice(&bytes)
+        .map_err(|e| anyhow!("Invalid backup: {}", e))?;
+
+    let secret_key = encrypted
+        .decrypt(passphrase)
+        .map_err(|e| anyhow!("Failed to decrypt backup: {}", e))?;
+
+    Ok(Keys::new(secret_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::ToBech32;
+
+    /// NIP-06 test vectors: (mnemonic, secret key hex, nsec, public key hex)
+    const NIP06_VECTORS: [(&str, &str, &str, &str); 2] = [
+        (
+            "leader monkey parrot ring guide accident before fence cannon height naive bean",
+            "7f7ff03d123792d6ac594bfa67bf6d0c0ab55b6b1fdb6249303fe861f1ccba9a",
+            "nsec10allq0gjx7fddtzef0ax00mdps9t2kmtrldkyjfs8l5xruwvh2dq0lhhkp",
+            "17162c921dc4d2518f9a101db33695df1afb56ab82f5ff3e5da6eec3ca5cd917",
+        ),
+        (
+            "what bleak badge arrange retreat wolf trade produce cricket blur garlic valid proud rude strong choose busy staff weather area salt hollow arm fade",
+            "c15d739894c81a2fcfd3a2df85a0d2c0dbc47a280d092799f144d73d7ae78add",
+            "nsec1c9wh8xy5eqdzln7n5t0ctgxjcrdug73gp5yj0x03gntn67h83twssdfhel",
+            "d41b22899549e1f3d335a31002cfd382174006e166d3e658e3a5eecdb6463573",
+        ),
+    ];
+
+    #[test]
+    fn mnemonic_derives_nip06_vectors() {
+        for (mnemonic, secret, nsec, pubkey) in NIP06_VECTORS {
+            let keys = import_key(mnemonic, None, None).unwrap();
+
+            assert_eq!(keys.secret_key().to_secret_hex(), secret);
+            assert_eq!(keys.secret_key().to_bech32().unwrap(), nsec);
+            assert_eq!(keys.public_key().to_hex(), pubkey);
+        }
+    }
+
+    #[test]
+    fn mnemonic_account_and_passphrase_change_the_key() {
+        let (mnemonic, secret, _, _) = NIP06_VECTORS[0];
+
+        let account0 = import_key(mnemonic, None, Some(0)).unwrap();
+        assert_eq!(account0.secret_key().to_secret_hex(), secret);
+
+        let account1 = import_key(mnemonic, None, Some(1)).unwrap();
+        let with_passphrase = import_key(mnemonic, Some("extra"), None).unwrap();
+        assert_ne!(account1.secret_key().to_secret_hex(), secret);
+        assert_ne!(with_passphrase.secret_key().to_secret_hex(), secret);
+        assert_ne!(account1.public_key(), with_passphrase.public_key());
+    }
+
+    #[test]
+    fn nsec_imports_its_secret_key() {
+        let (_, secret, nsec, pubkey) = NIP06_VECTORS[1];
+
+        let keys = import_key(&format!("  {}\n", nsec), None, None).unwrap();
+        assert_eq!(keys.secret_key().to_secret_hex(), secret);
+        assert_eq!(keys.public_key().to_hex(), pubkey);
+    }
+
+    #[test]
+    fn import_rejects_bad_keys() {
+        let (_, _, nsec, _) = NIP06_VECTORS[0];
+        let corrupted = format!("{}q", &nsec[..nsec.len() - 1]);
+
+        let err = import_key(&corrupted, None, None).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid nsec"), "{}", err);
+
+        let err = import_key("abandon abandon ability", None, None).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid mnemonic"), "{}", err);
+
+        let err = import_key(NIP06_VECTORS[0].1, None, None).unwrap_err();
+        assert!(err.to_string().starts_with("Expected an nsec1"), "{}", err);
+    }
+
+    #[test]
+    fn backup_round_trips_and_needs_the_passphrase() {
+        let keys = Keys::generate();
+
+        let blob = export_encrypted(&keys, "correct horse").unwrap();
+        let bytes = BASE64.decode(&blob).unwrap();
+        assert_eq!(bytes[0], 0x02, "NIP-49 version byte");
+        assert_eq!(bytes[1], BACKUP_SCRYPT_LOG_N);
+
+        let restored = import_encrypted(&format!("{}\n", blob), "correct horse").unwrap();
+        assert_eq!(restored.secret_key(), keys.secret_key());
+
+        assert!(import_encrypted(&blob, "wrong horse").is_err());
+    }
+
+    #[test]
+    fn backup_rejects_empty_passphrase_and_bad_blobs() {
+        assert!(export_encrypted(&Keys::generate(), "").is_err());
+        assert!(import_encrypted("not base64!", "pass").is_err());
+        assert!(import_encrypted(&BASE64.encode([2u8; 10]), "pass").is_err());
+    }
+}
@@ -7,9 +7,16 @@ pub mod error;
 pub mod identity;
 pub mod relays;
 pub mod qr;
+pub mod protocol;
 pub mod pairing;
+pub mod subscriptions;
+pub mod tenants;
+pub mod watchonly;
+pub mod debugdump;
 pub mod nostr_handler;
 pub mod nostr;
 pub mod electrs;
+pub mod bitcoind;
 pub mod xpub;
+pub mod selftest;
 
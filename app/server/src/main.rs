@@ -3,27 +3,48 @@ use rustls::crypto::ring::default_provider;
 use tracing::{error, info, warn};
 
 use axum::{
-    routing::get,
+    extract::{ConnectInfo, Query, Request, State},
+    middleware::{self, Next},
+    routing::{get, post},
     Router,
     response::{IntoResponse, Response},
-    http::{StatusCode, header},
+    http::{header, HeaderValue, Method, StatusCode},
     Json,
 };
+use nostr_sdk::PublicKey;
+use serde::Deserialize;
 use tokio::net::TcpListener;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
-mod config;
-mod error;
-mod identity;
-mod relays;
-mod qr;
-mod protocol;
-mod pairing;
-mod nostr_handler;
-mod nostr;
-mod electrs;
-mod xpub;
+use nomad_server::{
+    config, electrs, identity, nostr, nostr_handler, pairing, qr, relays, selftest,
+    subscriptions, tenants,
+};
+
+/// Custom index page in the data dir (see `load_index_template`)
+const INDEX_TEMPLATE_FILENAME: &str = "index.html";
+
+/// Pause between Electrs warm-up attempts
+const WARMUP_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Startup steps `/ready` waits for
+#[derive(Default)]
+struct Readiness {
+    /// Electrs warm-up succeeded
+    electrs: AtomicBool,
+    /// The Nostr client connected to at least one relay
+    relays: AtomicBool,
+}
+
+impl Readiness {
+    fn is_ready(&self) -> bool {
+        self.electrs.load(Ordering::Relaxed) && self.relays.load(Ordering::Relaxed)
+    }
+}
 
 fn install_crypto_provider() {
     let _ = default_provider().install_default();
@@ -34,9 +55,7 @@ async fn main() -> Result<()> {
     println!("=== NOMAD_SERVER MAIN STARTED ===");
 
     install_crypto_provider();
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+    init_tracing();
 
     println!("=== NOMAD_SERVER BUILD MARKER: trace-timeout-v2 ===");
 
@@ -44,78 +63,129 @@ async fn main() -> Result<()> {
 
     let data_dir = config::get_data_dir();
     info!("Using data dir: {}", data_dir.display());
+    config::ensure_data_dir(&data_dir).context("Data directory check failed")?;
+
+    if selftest::requested() {
+        std::process::exit(if selftest::run().await { 0 } else { 1 });
+    }
 
     let keys = identity::load_or_create_keys();
     let pubkey = keys.public_key().to_hex();
-    let relay_list = relays::get_relays();
-    let nostr_state = nostr::NostrState::new(keys.clone(), relay_list.clone()).await?;
+    let relay_entries = relays::get_relays(&data_dir);
+    let relay_list: Vec<String> = relay_entries.iter().map(|r| r.url.clone()).collect();
+    let nostr_state = nostr::NostrState::new(keys.clone(), relay_entries.clone()).await?;
+    let readiness = Arc::new(Readiness::default());
 
     // ✅ Electrs MUST be initialized before Nostr handler
-    info!("Initializing Electrs client...");
-    let electrs_client = Arc::new(
-        electrs::ElectrsClient::new()
-            .context("Failed to initialize Electrs client")?
-    );
-    info!("Electrs client initialized successfully");
-    info!("Warming up Electrs...");
-    match electrs_client.warm_up() {
-        Ok(_) => info!("Electrs warm-up successful"),
-        Err(e) => warn!("Electrs warm-up failed: {}", e),
-    }
+    let electrs_client = connect_electrs(&readiness).await?;
 
     // Initialize pairing manager
     let pairing_manager = pairing::PairingManager::new(&data_dir)
         .context("Failed to init pairing manager")?;
 
+    // The primary identity plus any NOMAD_TENANTS, each with its own pairings
+    let identities = tenants::Identities::load(&data_dir, keys.clone(), pairing_manager.clone())?;
+
     // Generate QR code for pairing
     let payload = qr::PairingPayload::new(pubkey.clone(), relay_list.clone());
     let pairing_json = payload.to_json()?;
-    let qr_svg = payload.generate_qr_svg()?;
+    let qr_svg = payload.generate_qr_svg(qr::EccLevel::default(), qr::DEFAULT_QUIET_ZONE)?;
 
     let pairing_json_clone = pairing_json.clone();
+    let pairing_json_download = pairing_json.clone();
+    let pairing_version = serde_json::json!({
+        "version": payload.version,
+        "configHash": payload.config_hash,
+    });
     let qr_svg_clone = qr_svg.clone();
+    let payload_for_qr = payload.clone();
     let pubkey_clone = pubkey.clone();
     let relay_list_clone = relay_list.clone();
+    let kinds = config::EventKinds::from_env();
+    let timeouts = config::TimeoutConfig::from_env();
+
+    // Cancelled on SIGTERM / Ctrl-C; every long-running task watches it
+    let shutdown = CancellationToken::new();
+    tokio::spawn(wait_for_shutdown_signal(shutdown.clone()));
 
     // Spawn lightweight NomadServer Nostr loop (request/response)
-    {
+    let nostr_loop_task = {
         let client = nostr_state.client.clone();
         let electrs_for_nostr = Arc::clone(&electrs_client);
+        let pairing_for_nostr = pairing_manager.clone();
+        let shutdown = shutdown.clone();
         tokio::spawn(async move {
-            loop {
-                if let Err(e) =
-                    crate::nostr::run_nomadserver_nostr_loop(client.clone(), electrs_for_nostr.clone()).await
+            while !shutdown.is_cancelled() {
+                if let Err(e) = nostr::run_nomadserver_nostr_loop(
+                    client.clone(),
+                    electrs_for_nostr.clone(),
+                    pairing_for_nostr.clone(),
+                    kinds,
+                    timeouts,
+                    shutdown.clone(),
+                )
+                .await
                 {
                     error!("NS_NOSTR: loop crashed: {e:?} — restarting in 2s");
                     tokio::time::sleep(std::time::Duration::from_secs(2)).await;
                 }
             }
-        });
-    }
+        })
+    };
+
+    tokio::spawn(wait_for_relay(nostr_state.clone(), Arc::clone(&readiness), shutdown.clone()));
+
+    // Reconnect relays that dropped (e.g. after a network outage)
+    let relay_monitor_task = tokio::spawn(nostr::run_relay_monitor(
+        nostr_state.clone(),
+        shutdown.clone(),
+    ));
+
+    // Address subscriptions: registered by the handler, polled by the watcher
+    let subscription_config = config::SubscriptionConfig::from_env();
+    let address_subscriptions = Arc::new(subscriptions::SubscriptionManager::new(
+        subscription_config.idle_timeout,
+    ));
+
+    // Pushes (address activity, tx confirmations, relay updates) go to the
+    // write relays, like request responses
+    let publisher =
+        nostr_handler::Publisher::new(nostr_state.client.clone(), kinds, config::PublishConfig::from_env());
+
+    let watcher_task = tokio::spawn(nostr::run_address_watcher(
+        publisher.clone(),
+        Arc::clone(&electrs_client),
+        Arc::clone(&address_subscriptions),
+        identities.clone(),
+        subscription_config.poll_interval,
+        shutdown.clone(),
+    ));
 
     // Start Nostr handler
     info!("Server pubkey: {}", pubkey);
-    info!("NomadServer request kind: {}", crate::nostr_handler::NOMAD_SERVER_REQUEST_KIND);
-    info!("NomadServer response kind: {}", crate::nostr_handler::NOMAD_SERVER_RESPONSE_KIND);
-    info!("Nostr relays: {}", relay_list.join(", "));
+    info!("NomadServer request kind: {}", kinds.request);
+    info!("NomadServer response kind: {}", kinds.response);
+    info!("Nostr relays: {}", relays::join(&relay_entries));
 
     let nostr_task = tokio::spawn({
-        let keys_clone = keys.clone();
-        let pairing_manager_clone = pairing_manager.clone();
+        let identities_clone = identities.clone();
         let electrs_client_clone = Arc::clone(&electrs_client);
         let nostr_state_clone = nostr_state.clone();
+        let shutdown = shutdown.clone();
 
         async move {
             match nostr_handler::NostrHandler::new(
                 nostr_state_clone,
-                keys_clone,
-                pairing_manager_clone,
+                identities_clone,
                 electrs_client_clone,
+                kinds,
+                address_subscriptions,
+                timeouts,
             )
             .await
             {
                 Ok(handler) => {
-                    if let Err(e) = handler.start_listening().await {
+                    if let Err(e) = handler.start_listening(shutdown).await {
                         eprintln!("Nostr handler exited with error: {}", e);
                     }
                 }
@@ -126,7 +196,7 @@ async fn main() -> Result<()> {
         }
     });
 
-    tokio::spawn(async move {
+    let nostr_task = tokio::spawn(async move {
         if let Err(e) = nostr_task.await {
             eprintln!("Nostr task panicked: {:?}", e);
         }
@@ -137,13 +207,26 @@ async fn main() -> Result<()> {
     let app_state = nostr_state.clone();
     let pubkey_for_root = pubkey_clone.clone();
     let relay_list_for_root = relay_list_clone.clone();
+    let index_template = load_index_template(&data_dir);
     let pubkey_for_pubkey = pubkey_clone.clone();
     let pubkey_for_info = pubkey_clone.clone();
     let relay_list_for_info = relay_list_clone.clone();
+    let pubkey_for_info_json = pubkey_clone.clone();
+    let relay_list_for_info_json = relay_list_clone.clone();
+    let pairing_for_revoke = pairing_manager.clone();
+    let capabilities = nostr_handler::Capabilities::new(electrs_client.network(), kinds);
+    let keys_for_backup = keys.clone();
+    let identities_for_rotate = identities.clone();
+    let data_dir_for_relays = data_dir.clone();
+    let readiness_for_ready = Arc::clone(&readiness);
 
-    let app = Router::new()
+    let public = Router::new()
         .route("/", get(move || async move {
-            serve_html_index(pubkey_for_root.clone(), relay_list_for_root.clone())
+            serve_html_index(
+                pubkey_for_root.clone(),
+                relay_list_for_root.clone(),
+                index_template.as_deref(),
+            )
         }))
         .route("/pubkey", get(move || async move {
             serve_pubkey_plain(pubkey_for_pubkey.clone())
@@ -151,26 +234,66 @@ async fn main() -> Result<()> {
         .route("/info", get(move || async move {
             serve_info_text(pubkey_for_info.clone(), relay_list_for_info.clone())
         }))
+        .route("/info.json", get(move || async move {
+            serve_info_json(
+                pubkey_for_info_json.clone(),
+                relay_list_for_info_json.clone(),
+                capabilities.clone(),
+            )
+        }))
         .route("/pairing", get(move || async move { pairing_json_clone.clone() }))
-        .route("/qr", get(move || async move { serve_svg(qr_svg_clone.clone()) }))
+        .route("/pairing.json", get(move || async move {
+            serve_pairing_download(pairing_json_download.clone())
+        }))
+        .route("/pairing/version", get(move || async move { Json(pairing_version.clone()) }))
+        .route("/qr", get(move |query| async move {
+            serve_qr(payload_for_qr.clone(), qr_svg_clone.clone(), query)
+        }))
         .route("/health", get(|| async {
             info!("HTTP GET /health request received");
             (StatusCode::OK, "OK").into_response()
         }))
+        .route("/ready", get(move || async move { serve_ready(&readiness_for_ready) }))
+        .route("/health/relays", get(serve_relay_health))
+        .route("/relays/stats", get(serve_relay_stats))
+        .route("/metrics", get(serve_metrics))
         .route("/health/electrs", get(move || {
-            let electrs_client = Arc::clone(&electrs_client_health);
-            async move {
-                info!("HTTP GET /health/electrs request received");
-                match tokio::task::spawn_blocking(move || electrs_client.test_connectivity()).await {
-                    Ok(_) => (StatusCode::OK, "OK"),
-                    Err(e) => {
-                        error!("Electrs health check failed: {}", e);
-                        (StatusCode::SERVICE_UNAVAILABLE, "Electrs unavailable")
-                    }
-                }
-            }
+            serve_electrs_health(Arc::clone(&electrs_client_health))
+        }));
+
+    // Read-only routes may be fetched cross-origin by NOMAD_CORS_ORIGINS
+    let cors_origins = Arc::new(config::cors_origins());
+    let public = if cors_origins.is_empty() {
+        public
+    } else {
+        info!("CORS allowed origins: {}", cors_origins.join(", "));
+        public.layer(middleware::from_fn(move |req, next| {
+            cors(Arc::clone(&cors_origins), req, next)
+        }))
+    };
+
+    // Localhost-only admin routes (and GET /relays, which shares its path)
+    let admin = Router::new()
+        .route("/pairing/revoke", post(move |connect_info, body| {
+            revoke_pairing(pairing_for_revoke.clone(), connect_info, body)
+        }))
+        .route("/pairing/rotate", post(move |state, connect_info, body| {
+            rotate_relays(
+                identities_for_rotate.clone(),
+                publisher.clone(),
+                state,
+                connect_info,
+                body,
+            )
+        }))
+        .route("/identity/backup", post(move |connect_info, body| {
+            serve_identity_backup(keys_for_backup.clone(), connect_info, body)
         }))
-        .with_state(app_state);
+        .route("/relays", get(serve_relays).post(move |state, connect_info, body| {
+            update_relays(data_dir_for_relays.clone(), state, connect_info, body)
+        }));
+
+    let app = public.merge(admin).with_state(app_state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3829));
     info!("Listening on http://{}", addr);
@@ -180,10 +303,642 @@ async fn main() -> Result<()> {
         .context("Failed to bind")?;
 
     info!("Server ready. Waiting for Android app pairing...");
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+        .await?;
+
+    // HTTP is down; give the Nostr tasks time to finish in-flight requests
+    // (e.g. broadcasts) before we exit.
+    let drained = tokio::time::timeout(Duration::from_secs(10), async {
+        let _ = nostr_task.await;
+        let _ = nostr_loop_task.await;
+        let _ = watcher_task.await;
+        let _ = relay_monitor_task.await;
+    })
+    .await;
+
+    if drained.is_err() {
+        warn!("Nostr tasks did not finish within 10s; exiting anyway");
+    }
+
+    nostr_state.client.shutdown().await;
+
+    info!("Shutdown complete");
     Ok(())
 }
 
+/// Human-readable logs by default; JSON lines when NOMAD_LOG_FORMAT=json
+fn init_tracing() {
+    let filter = tracing_subscriber::EnvFilter::from_default_env();
+
+    if config::log_format_json() {
+        tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_env_filter(filter)
+            .init();
+    } else {
+        tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .init();
+    }
+}
+
+/// Resolve on SIGTERM (container stop) or Ctrl-C and cancel `shutdown`
+async fn wait_for_shutdown_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
+    info!("Shutting down");
+    shutdown.cancel();
+}
+
+/// CORS for the read-only routes
+///
+/// Requests whose `Origin` is in `origins` (or any, with `*`) get it echoed
+/// in `Access-Control-Allow-Origin`; preflight requests are answered here.
+/// Other origins get no CORS headers, so browsers keep blocking them.
+async fn cors(origins: Arc<Vec<String>>, req: Request, next: Next) -> Response {
+    let origin = req
+        .headers()
+        .get(header::ORIGIN)
+        .filter(|o| {
+            o.to_str()
+                .is_ok_and(|o| origins.iter().any(|allowed| allowed == "*" || allowed == o))
+        })
+        .cloned();
+
+    let Some(origin) = origin else {
+        return next.run(req).await;
+    };
+
+    let preflight = req.method() == Method::OPTIONS;
+    let mut response = if preflight {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        next.run(req).await
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+    if preflight {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("GET"));
+        headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static("600"));
+    }
+
+    response
+}
+
+/// GET /ready
+///
+/// 200 once Electrs warmed up and a relay connected, 503 before. Unlike
+/// /health (liveness) this says whether requests can be served yet.
+fn serve_ready(readiness: &Readiness) -> Response {
+    let body = serde_json::json!({
+        "ready": readiness.is_ready(),
+        "electrs": readiness.electrs.load(Ordering::Relaxed),
+        "relays": readiness.relays.load(Ordering::Relaxed),
+    });
+
+    let code = if readiness.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (code, Json(body)).into_response()
+}
+
+/// Create the Electrs client and warm it up, retrying while Electrs starts
+///
+/// Attempts repeat every WARMUP_RETRY_INTERVAL for up to
+/// NOMAD_WARMUP_MAX_WAIT_SECS. If the client connects but the warm-up
+/// still fails by then, startup continues not-ready and the warm-up is
+/// retried in the background; failing to create the client is fatal.
+async fn connect_electrs(readiness: &Arc<Readiness>) -> Result<Arc<electrs::ElectrsClient>> {
+    let deadline = std::time::Instant::now() + config::warmup_max_wait();
+    let mut attempt = 1;
+
+    loop {
+        info!("Initializing Electrs client (attempt {})...", attempt);
+        let retry = std::time::Instant::now() < deadline;
+
+        match electrs::ElectrsClient::new() {
+            Ok(client) => {
+                let client = Arc::new(client);
+
+                info!("Warming up Electrs...");
+                match client.warm_up() {
+                    Ok(()) => {
+                        info!("Electrs warm-up successful");
+                        readiness.electrs.store(true, Ordering::Relaxed);
+                        return Ok(client);
+                    }
+                    Err(e) if !retry => {
+                        warn!("Electrs warm-up failed: {}; starting not-ready, retrying in the background", e);
+                        tokio::spawn(retry_warm_up(Arc::clone(&client), Arc::clone(readiness)));
+                        return Ok(client);
+                    }
+                    Err(e) => warn!("Electrs warm-up failed: {}; retrying in {:?}", e, WARMUP_RETRY_INTERVAL),
+                }
+            }
+            Err(e) if !retry => return Err(e.context("Failed to initialize Electrs client")),
+            Err(e) => warn!("Electrs not reachable: {}; retrying in {:?}", e, WARMUP_RETRY_INTERVAL),
+        }
+
+        tokio::time::sleep(WARMUP_RETRY_INTERVAL).await;
+        attempt += 1;
+    }
+}
+
+/// Keep warming up Electrs until it succeeds, then mark it ready
+async fn retry_warm_up(client: Arc<electrs::ElectrsClient>, readiness: Arc<Readiness>) {
+    loop {
+        tokio::time::sleep(WARMUP_RETRY_INTERVAL).await;
+
+        let client = Arc::clone(&client);
+        match tokio::task::spawn_blocking(move || client.warm_up()).await {
+            Ok(Ok(())) => {
+                info!("Electrs warm-up successful; Electrs ready");
+                readiness.electrs.store(true, Ordering::Relaxed);
+                return;
+            }
+            Ok(Err(e)) => warn!("Electrs warm-up failed: {}", e),
+            Err(e) => {
+                error!("Electrs warm-up task failed: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Mark relays ready once the first relay connects
+async fn wait_for_relay(
+    nostr_state: nostr::NostrState,
+    readiness: Arc<Readiness>,
+    shutdown: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = ticker.tick() => {}
+        }
+
+        let connected = nostr_state
+            .relay_status()
+            .await
+            .iter()
+            .any(|(_, status)| *status == nostr_sdk::RelayStatus::Connected);
+
+        if connected {
+            info!("First relay connected; relays ready");
+            readiness.relays.store(true, Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+/// GET /health/relays
+///
+/// 200 if at least one relay is connected, 503 otherwise. Includes how long
+/// each relay has been in its state and the monitor's reconnect attempts.
+async fn serve_relay_health(State(nostr_state): State<nostr::NostrState>) -> Response {
+    let relays = nostr_state.relay_status().await;
+    let health = nostr_state.relay_health();
+    let connected = relays
+        .iter()
+        .any(|(_, status)| *status == nostr_sdk::RelayStatus::Connected);
+
+    let body = serde_json::json!({
+        "connected": connected,
+        "relays": relays
+            .iter()
+            .map(|(url, status)| {
+                let tracked = health.get(url).filter(|h| h.status == *status);
+                serde_json::json!({
+                    "url": url,
+                    "status": status.to_string(),
+                    "since_secs": tracked.map(|h| h.since.elapsed().as_secs()),
+                    "reconnect_attempts": tracked.map(|h| h.reconnect_attempts).unwrap_or(0),
+                })
+            })
+            .collect::<Vec<_>>(),
+    });
+
+    let code = if connected {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (code, Json(body)).into_response()
+}
+
+/// GET /health/electrs
+///
+/// 200 when Electrs answers and its tip is recent, 503 while it is
+/// unreachable or still catching up (balances would be stale).
+async fn serve_electrs_health(electrs_client: Arc<electrs::ElectrsClient>) -> Response {
+    info!("HTTP GET /health/electrs request received");
+
+    let cache = electrs_client.cache_stats();
+    let cache = serde_json::json!({ "hits": cache.hits, "misses": cache.misses });
+
+    match electrs_client.get_sync_status().await {
+        Ok(status) => {
+            let body = serde_json::json!({
+                "connected": true,
+                "synced": status.synced,
+                "tip_height": status.tip_height,
+                "tip_age_secs": status.tip_age_secs,
+                "lag_blocks": status.lag_blocks,
+                "cache": cache,
+            });
+
+            let code = if status.synced {
+                StatusCode::OK
+            } else {
+                warn!(
+                    "Electrs tip {} is {}s old; reporting unhealthy",
+                    status.tip_height, status.tip_age_secs
+                );
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+
+            (code, Json(body)).into_response()
+        }
+        Err(e) => {
+            error!("Electrs health check failed: {}", e);
+            let body = serde_json::json!({
+                "connected": false,
+                "synced": false,
+                "cache": cache,
+            });
+            (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct QrQuery {
+    ecc: Option<String>,
+    margin: Option<u8>,
+}
+
+/// GET /qr[?ecc=L|M|Q|H&margin=N]
+///
+/// Without parameters serves the QR rendered at startup; otherwise renders
+/// one with the requested error correction and quiet zone.
+fn serve_qr(payload: qr::PairingPayload, default_svg: String, Query(query): Query<QrQuery>) -> Response {
+    if query.ecc.is_none() && query.margin.is_none() {
+        return serve_svg(default_svg);
+    }
+
+    let ecc = match query.ecc.as_deref().map(str::parse::<qr::EccLevel>).transpose() {
+        Ok(ecc) => ecc.unwrap_or_default(),
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let margin = query.margin.unwrap_or(qr::DEFAULT_QUIET_ZONE);
+    if margin > qr::MAX_QUIET_ZONE {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("margin must be at most {}", qr::MAX_QUIET_ZONE),
+        )
+            .into_response();
+    }
+
+    match payload.generate_qr_svg(ecc, margin) {
+        Ok(svg) => serve_svg(svg),
+        Err(e) => {
+            error!("Failed to render QR code: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to render QR code").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BackupRequest {
+    passphrase: String,
+}
+
+/// POST /identity/backup (localhost only)
+///
+/// Takes `{ "passphrase": ... }` and returns the server secret key encrypted
+/// with it (see `identity::export_encrypted`). The passphrase is in the body
+/// rather than the query so it stays out of access logs, proxy logs and
+/// shell history.
+async fn serve_identity_backup(
+    keys: nostr_sdk::Keys,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Json(body): Json<BackupRequest>,
+) -> Response {
+    if !remote.ip().is_loopback() {
+        warn!("Rejected /identity/backup from non-local address {}", remote);
+        return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+    }
+
+    if body.passphrase.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Passphrase must not be empty").into_response();
+    }
+
+    // scrypt is deliberately slow; keep it off the async workers
+    let result = tokio::task::spawn_blocking(move || {
+        identity::export_encrypted(&keys, &body.passphrase)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(blob)) => {
+            info!("Exported encrypted identity backup");
+            (StatusCode::OK, blob).into_response()
+        }
+        Ok(Err(e)) => {
+            error!("Failed to export identity backup: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to export backup").into_response()
+        }
+        Err(e) => {
+            error!("Identity backup task failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to export backup").into_response()
+        }
+    }
+}
+
+/// GET /relays
+///
+/// `standby` lists relays held back by NOMAD_MAX_RELAYS.
+async fn serve_relays(State(nostr_state): State<nostr::NostrState>) -> Response {
+    let standby: Vec<String> = nostr_state.standby_relays().into_iter().map(|r| r.url).collect();
+
+    Json(serde_json::json!({ "relays": nostr_state.relay_urls().await, "standby": standby }))
+        .into_response()
+}
+
+/// GET /relays/stats
+///
+/// Events received/published, reconnects and ping RTT per active relay.
+async fn serve_relay_stats(State(nostr_state): State<nostr::NostrState>) -> Response {
+    Json(serde_json::json!({ "relays": nostr_state.relay_stats().await })).into_response()
+}
+
+/// GET /metrics
+///
+/// The relay stats in the Prometheus text format.
+async fn serve_metrics(State(nostr_state): State<nostr::NostrState>) -> Response {
+    let stats = nostr_state.relay_stats().await;
+    let mut out = String::new();
+
+    let mut metric = |name: &str, kind: &str, help: &str, value: &dyn Fn(&nostr::RelayStats) -> Option<u64>| {
+        out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+        for s in &stats {
+            if let Some(v) = value(s) {
+                out.push_str(&format!("{name}{{relay=\"{}\"}} {v}\n", s.url));
+            }
+        }
+    };
+
+    metric("nomad_relay_events_received_total", "counter", "Events received from the relay", &|s| {
+        Some(s.events_received)
+    });
+    metric("nomad_relay_events_published_total", "counter", "Events accepted by the relay", &|s| {
+        Some(s.events_published)
+    });
+    metric("nomad_relay_reconnects_total", "counter", "Reconnects started by the relay monitor", &|s| {
+        Some(s.reconnects)
+    });
+    metric("nomad_relay_rtt_milliseconds", "gauge", "Average ping round trip", &|s| s.rtt_ms);
+    metric("nomad_relay_connected", "gauge", "Whether the relay is connected", &|s| {
+        Some(u64::from(s.status == "Connected"))
+    });
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out).into_response()
+}
+
+#[derive(Deserialize)]
+struct UpdateRelaysRequest {
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+/// POST /relays (localhost only)
+///
+/// Adds and removes relays on the running client and saves the resulting
+/// list to the data dir. Added entries may carry a role (`wss://x,read`).
+/// Paired wallets are not told; use /pairing/rotate for that.
+async fn update_relays(
+    data_dir: std::path::PathBuf,
+    State(nostr_state): State<nostr::NostrState>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Json(body): Json<UpdateRelaysRequest>,
+) -> Response {
+    if !remote.ip().is_loopback() {
+        warn!("Rejected /relays update from non-local address {}", remote);
+        return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+    }
+
+    let add = relays::parse_relay_entries(body.add.iter().map(|r| r.as_str()));
+    let remove = relays::normalize_relays(body.remove.iter().map(|r| r.as_str()));
+    if add.len() != body.add.len() || remove.len() != body.remove.len() {
+        return (StatusCode::BAD_REQUEST, "Invalid or duplicate relay URL").into_response();
+    }
+
+    let current: Vec<String> = nostr_state.relay_entries().await.into_iter().map(|r| r.url).collect();
+    let remaining = current.iter().filter(|r| !remove.contains(r)).count()
+        + add.iter().filter(|r| !current.contains(&r.url)).count();
+    if remaining == 0 {
+        return (StatusCode::BAD_REQUEST, "Refusing to remove every relay").into_response();
+    }
+
+    for relay in &add {
+        if let Err(e) = nostr_state.add_relay(relay).await {
+            error!("Failed to add relay {}: {}", relay.url, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to add relay {}", relay.url))
+                .into_response();
+        }
+    }
+
+    for url in remove.iter().filter(|r| current.contains(r)) {
+        if let Err(e) = nostr_state.remove_relay(url).await {
+            error!("Failed to remove relay {}: {}", url, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to remove relay {}", url))
+                .into_response();
+        }
+    }
+
+    let entries = nostr_state.relay_entries().await;
+    if let Err(e) = relays::save_relays(&data_dir, &entries) {
+        error!("Failed to save relay list: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to save relay list").into_response();
+    }
+
+    info!("Relay list updated: {}", relays::join(&entries));
+    Json(serde_json::json!({ "relays": nostr_state.relay_urls().await })).into_response()
+}
+
+#[derive(Deserialize)]
+struct RotateRelaysRequest {
+    relays: Vec<String>,
+}
+
+/// POST /pairing/rotate (localhost only)
+///
+/// Sends a `relay_update` with the new relay list to every paired device,
+/// signed by the identity (primary or tenant) the device is paired with, so
+/// wallets can switch relays without re-scanning the QR. The new relays are
+/// then added to the pool. Update NOSTR_RELAYS as well so the new list
+/// survives a restart.
+async fn rotate_relays(
+    identities: tenants::Identities,
+    publisher: nostr_handler::Publisher,
+    State(nostr_state): State<nostr::NostrState>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Json(body): Json<RotateRelaysRequest>,
+) -> Response {
+    if !remote.ip().is_loopback() {
+        warn!("Rejected /pairing/rotate from non-local address {}", remote);
+        return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+    }
+
+    let entries = relays::parse_relay_entries(body.relays.iter().map(|r| r.as_str()));
+    if entries.is_empty() {
+        return (StatusCode::BAD_REQUEST, "No valid relays").into_response();
+    }
+    let relays: Vec<String> = entries.iter().map(|r| r.url.clone()).collect();
+
+    let mut notified = 0;
+    let mut updates = Vec::new();
+    for identity in identities.iter() {
+        let recipients = match identity.pairing.paired_pubkeys() {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Failed to read pairings of {}: {}", identity.name, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read pairings").into_response();
+            }
+        };
+
+        let pubkey = identity.keys.public_key().to_hex();
+        let config_hash = qr::config_hash(&pubkey, &relays);
+
+        match nostr::publish_relay_update(&publisher, &identity.keys, &recipients, &relays, &config_hash)
+            .await
+        {
+            Ok(n) => {
+                notified += n;
+                updates.push(serde_json::json!({
+                    "identity": identity.name,
+                    "pubkey": pubkey,
+                    "configHash": config_hash,
+                    "notified": n,
+                }));
+            }
+            Err(e) => {
+                error!("Failed to publish relay update for {}: {}", identity.name, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to publish relay update")
+                    .into_response();
+            }
+        }
+    }
+
+    for relay in &entries {
+        if let Err(e) = nostr_state.add_relay(relay).await {
+            warn!("Failed to add relay {}: {}", relay.url, e);
+        }
+    }
+
+    info!("Rotated relays to {}", relays::join(&entries));
+    Json(serde_json::json!({
+        "relays": relays,
+        "configHash": updates[0]["configHash"],
+        "notified": notified,
+        "identities": updates,
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct RevokePairingRequest {
+    pubkey: String,
+}
+
+/// POST /pairing/revoke (localhost only)
+///
+/// Removes a paired device so its requests are rejected from now on.
+async fn revoke_pairing(
+    pairing_manager: pairing::PairingManager,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Json(body): Json<RevokePairingRequest>,
+) -> Response {
+    if !remote.ip().is_loopback() {
+        warn!("Rejected /pairing/revoke from non-local address {}", remote);
+        return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+    }
+
+    let pubkey = match PublicKey::parse(&body.pubkey) {
+        Ok(pk) => pk,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid pubkey: {}", e)).into_response();
+        }
+    };
+
+    match pairing_manager.remove_device(&pubkey) {
+        Ok(true) => {
+            info!("Revoked pairing for {}", pubkey.to_hex());
+            (StatusCode::OK, "Revoked").into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, "Pubkey not paired").into_response(),
+        Err(e) => {
+            error!("Failed to revoke pairing: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to revoke pairing").into_response()
+        }
+    }
+}
+
+/// GET /pairing.json
+///
+/// Same payload as /pairing, typed as JSON and offered as a download.
+fn serve_pairing_download(json: String) -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=nomad-pairing.json"),
+        ],
+        json,
+    )
+        .into_response()
+}
+
 fn serve_svg(svg: String) -> Response {
     (
         StatusCode::OK,
@@ -193,13 +948,46 @@ fn serve_svg(svg: String) -> Response {
         .into_response()
 }
 
-fn serve_html_index(pubkey: String, relay_list: Vec<String>) -> Response {
+/// Operator-provided index page, read once at startup
+///
+/// `index.html` in the data dir replaces the built-in page; `{pubkey}` and
+/// `{relays}` (a list of `<li>` items) are filled in when served.
+fn load_index_template(data_dir: &std::path::Path) -> Option<String> {
+    let path = data_dir.join(INDEX_TEMPLATE_FILENAME);
+
+    match std::fs::read_to_string(&path) {
+        Ok(template) => {
+            info!("Serving custom index page from {}", path.display());
+            Some(template)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            warn!("Ignoring unreadable {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn serve_html_index(pubkey: String, relay_list: Vec<String>, template: Option<&str>) -> Response {
     let relay_list_html: String = relay_list
         .iter()
         .map(|relay| format!("<li>{}</li>", html_escape(relay)))
         .collect::<Vec<_>>()
         .join("\n    ");
 
+    if let Some(template) = template {
+        let html = template
+            .replace("{pubkey}", &html_escape(&pubkey))
+            .replace("{relays}", &relay_list_html);
+
+        return (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+            html,
+        )
+            .into_response();
+    }
+
 
     let html = format!(
         r#"<!DOCTYPE html>
@@ -263,14 +1051,22 @@ fn serve_html_index(pubkey: String, relay_list: Vec<String>) -> Response {
     <ul>
         <li><a href="/qr">📱 QR Code</a> - Scan with your phone to pair</li>
         <li><a href="/pairing">🔗 Pairing JSON</a> - View raw JSON (open in new tab to copy)</li>
+        <li><a href="/pairing.json">💾 Download pairing JSON</a> - Save as nomad-pairing.json</li>
     </ul>
     
     <h2>API Endpoints</h2>
     <ul>
         <li><a href="/pubkey">/pubkey</a> - Plain text public key</li>
         <li><a href="/info">/info</a> - Human-readable server info</li>
+        <li><a href="/info.json">/info.json</a> - Server info and capabilities (JSON)</li>
+        <li><a href="/pairing/version">/pairing/version</a> - Pairing config hash</li>
         <li><a href="/health">/health</a> - Health check</li>
-        <li><a href="/health/electrs">/health/electrs</a> - Electrs connectivity check</li>
+        <li><a href="/ready">/ready</a> - Readiness probe</li>
+        <li><a href="/relays">/relays</a> - Current relay list (JSON)</li>
+        <li><a href="/health/relays">/health/relays</a> - Relay connectivity check</li>
+        <li><a href="/relays/stats">/relays/stats</a> - Per-relay traffic and RTT</li>
+        <li><a href="/metrics">/metrics</a> - Prometheus metrics</li>
+        <li><a href="/health/electrs">/health/electrs</a> - Electrs connectivity and sync status</li>
     </ul>
     
     <h2>Connected Relays</h2>
@@ -345,6 +1141,19 @@ fn serve_pubkey_plain(pubkey: String) -> Response {
         .into_response()
 }
 
+fn serve_info_json(
+    pubkey: String,
+    relay_list: Vec<String>,
+    capabilities: nostr_handler::Capabilities,
+) -> Response {
+    Json(serde_json::json!({
+        "pubkey": pubkey,
+        "relays": relay_list,
+        "capabilities": capabilities,
+    }))
+    .into_response()
+}
+
 fn serve_info_text(pubkey: String, relay_list: Vec<String>) -> Response {
     let relay_list_text = relay_list.join("\n  - ");
 
@@ -368,10 +1177,22 @@ API Endpoints:
   - GET /          - This info page (HTML)
   - GET /pubkey    - Plain text public key
   - GET /info      - This info (text format)
+  - GET /info.json - Server info and capabilities (JSON)
   - GET /pairing   - Pairing JSON
-  - GET /qr        - QR code (SVG)
+  - GET /pairing.json - Pairing JSON as a download (application/json)
+  - GET /pairing/version - Pairing config hash (detect relay/key changes)
+  - GET /qr        - QR code (SVG, optional ?ecc=L|M|Q|H&margin=N)
   - GET /health    - Health check
-  - GET /health/electrs - Electrs connectivity
+  - GET /ready     - Readiness (Electrs warmed up and a relay connected)
+  - GET /relays    - Current relay list
+  - POST /relays   - Add/remove relays at runtime (localhost only)
+  - GET /health/relays - Relay connectivity
+  - GET /relays/stats - Per-relay traffic and RTT
+  - GET /metrics - Prometheus metrics
+  - GET /health/electrs - Electrs connectivity and sync status
+  - POST /pairing/revoke - Revoke a paired device (localhost only)
+  - POST /pairing/rotate - Announce a new relay list to paired devices (localhost only)
+  - POST /identity/backup - Encrypted key backup, passphrase in the body (localhost only)
 
 To pair your wallet:
   1. Scan the QR code at /qr with your phone
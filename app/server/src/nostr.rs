@@ -1,44 +1,529 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
+use nostr_sdk::client::{Connection, ConnectionTarget};
+use nostr_sdk::pool::RelayNotification;
 use nostr_sdk::{
-    Alphabet, Client, Event, EventBuilder, Filter, Keys, Kind, PublicKey, RelayPoolNotification,
-    SingleLetterTag, Tag,
+    Alphabet, Client, ClientMessage, ClientOptions, Event, EventBuilder, Filter, Keys, Kind,
+    PublicKey, RelayMessage, RelayPoolNotification, RelayStatus, RelayUrl, SingleLetterTag, Tag,
 };
+use serde::Serialize;
 use serde_json::Value;
 use tokio::time::timeout;
 use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{EventKinds, TimeoutConfig};
+use crate::electrs::{ElectrsClient, TxStatus};
+use crate::nostr_handler::Publisher;
+use crate::pairing::PairingManager;
+use crate::relays::{RelayEntry, RelayRole};
+use crate::subscriptions::{SubscriptionManager, TxWatch};
+use crate::tenants::Identities;
+
+/// How often the relay monitor checks connection state
+const RELAY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Upper bound for the reconnect backoff of a single relay
+const RELAY_MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+/// Failed reconnects after which an active relay is swapped for a standby
+const RELAY_PROMOTE_AFTER: u32 = 3;
+
+/// Relay connection state as tracked by the relay monitor
+#[derive(Debug, Clone)]
+pub struct RelayHealth {
+    pub status: RelayStatus,
+    /// When the relay entered `status`
+    pub since: Instant,
+    /// Reconnect attempts since the relay was last connected
+    pub reconnect_attempts: u32,
+    next_attempt: Instant,
+}
+
+/// Traffic counters for one relay since startup
+#[derive(Debug, Clone, Default)]
+pub struct RelayCounters {
+    /// Events delivered by the relay (before dedup across relays)
+    pub events_received: u64,
+    /// Events the relay accepted with an OK
+    pub events_published: u64,
+    /// Reconnects started by the relay monitor
+    pub reconnects: u64,
+}
 
-use crate::electrs::ElectrsClient;
+/// Per-relay traffic and latency for GET /relays/stats and /metrics
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayStats {
+    pub url: String,
+    pub status: String,
+    pub events_received: u64,
+    pub events_published: u64,
+    pub reconnects: u64,
+    /// Average ping round trip; None until enough pings were measured
+    pub rtt_ms: Option<u64>,
+}
 
 #[derive(Clone)]
 pub struct NostrState {
     pub client: Arc<Client>,
+    relay_health: Arc<Mutex<HashMap<String, RelayHealth>>>,
+    relay_counters: Arc<Mutex<HashMap<String, RelayCounters>>>,
+    /// Relays beyond NOMAD_MAX_RELAYS, in promotion order
+    standby: Arc<Mutex<Vec<RelayEntry>>>,
+    max_relays: Option<usize>,
 }
 
 impl NostrState {
-    pub async fn new(keys: Keys, relays: Vec<String>) -> Result<Self> {
+    pub async fn new(keys: Keys, mut relays: Vec<RelayEntry>) -> Result<Self> {
+        // .onion relays (or all relays) through NOMAD_SOCKS_PROXY, e.g. Tor
+        let mut opts = ClientOptions::new();
+        if let Some(proxy) = crate::config::socks_proxy() {
+            let target = if proxy.onion_only {
+                ConnectionTarget::Onion
+            } else {
+                ConnectionTarget::All
+            };
+            log::info!("NS_NOSTR: relays via SOCKS5 proxy {} ({:?})", proxy.addr, target);
+            opts = opts.connection(Connection::new().proxy(proxy.addr).target(target));
+        }
+
         // IMPORTANT: pass OWNED Keys, not &Keys
-        let client = Client::new(keys);
+        let client = Arc::new(Client::builder().signer(keys.clone()).opts(opts).build());
+
+        // NIP-42 AUTH is answered by our own responder (see below) so we can
+        // log which relays asked for it and whether it succeeded.
+        client.automatic_authentication(false);
+        tokio::spawn(run_auth_responder(client.clone(), keys));
+
+        let relay_counters = Arc::new(Mutex::new(HashMap::new()));
+        tokio::spawn(run_relay_counter(client.clone(), Arc::clone(&relay_counters)));
 
         // nostr-sdk v0.44.1 API
-        for relay in relays {
-            client.add_relay(relay).await?;
+        // Subscriptions go to READ relays, send_event to WRITE relays
+        if !relays.iter().any(|r| r.role != RelayRole::Write) {
+            log::warn!("NS_NOSTR: no read relays configured; requests will not be received");
+        }
+        if !relays.iter().any(|r| r.role != RelayRole::Read) {
+            log::warn!("NS_NOSTR: no write relays configured; responses will not be published");
+        }
+
+        // The first NOMAD_MAX_RELAYS are connected, the rest wait as fallbacks
+        let max_relays = crate::config::max_relays();
+        let standby = relays.split_off(max_relays.unwrap_or(relays.len()).min(relays.len()));
+
+        for relay in &relays {
+            if let Err(e) = add_relay_with_role(&client, relay).await {
+                log::warn!("NS_NOSTR: skipping relay {}: {}", relay.url, e);
+            }
+        }
+
+        log::info!("NS_RELAY: active relays: {}", crate::relays::join(&relays));
+        if !standby.is_empty() {
+            log::info!("NS_RELAY: standby relays: {}", crate::relays::join(&standby));
         }
 
         // connect() returns ()
         client.connect().await;
 
         Ok(Self {
-            client: Arc::new(client),
+            client,
+            relay_health: Arc::new(Mutex::new(HashMap::new())),
+            relay_counters,
+            standby: Arc::new(Mutex::new(standby)),
+            max_relays,
         })
     }
+
+    /// Connection state of every configured relay
+    pub async fn relay_status(&self) -> Vec<(String, RelayStatus)> {
+        let mut status: Vec<(String, RelayStatus)> = self
+            .client
+            .relays()
+            .await
+            .into_iter()
+            .map(|(url, relay)| (url.to_string(), relay.status()))
+            .collect();
+
+        status.sort_by(|a, b| a.0.cmp(&b.0));
+        status
+    }
+
+    /// URLs of every configured relay, sorted
+    pub async fn relay_urls(&self) -> Vec<String> {
+        self.relay_status()
+            .await
+            .into_iter()
+            .map(|(url, _)| url)
+            .collect()
+    }
+
+    /// Every configured relay (active and standby) with its role, sorted by URL
+    pub async fn relay_entries(&self) -> Vec<RelayEntry> {
+        let mut entries = self.active_relay_entries().await;
+        entries.extend(self.standby_relays());
+
+        entries.sort_by(|a, b| a.url.cmp(&b.url));
+        entries
+    }
+
+    /// Relays the client is connected (or connecting) to, with their role
+    async fn active_relay_entries(&self) -> Vec<RelayEntry> {
+        self.client
+            .relays()
+            .await
+            .into_iter()
+            .map(|(url, relay)| {
+                let flags = relay.flags();
+                let role = match (flags.has_read(), flags.has_write()) {
+                    (true, false) => RelayRole::Read,
+                    (false, true) => RelayRole::Write,
+                    _ => RelayRole::ReadWrite,
+                };
+                RelayEntry { url: url.to_string(), role }
+            })
+            .collect()
+    }
+
+    /// Relays held back by NOMAD_MAX_RELAYS, in promotion order
+    pub fn standby_relays(&self) -> Vec<RelayEntry> {
+        self.standby.lock().unwrap().clone()
+    }
+
+    /// Add and connect a relay at runtime
+    ///
+    /// A new read relay inherits the pool's subscriptions, so requests
+    /// arriving through it are picked up without re-subscribing. Once
+    /// NOMAD_MAX_RELAYS relays are active the relay is put on standby.
+    pub async fn add_relay(&self, relay: &RelayEntry) -> Result<()> {
+        let active = self.client.relays().await.len();
+        if self.max_relays.is_some_and(|max| active >= max) {
+            let mut standby = self.standby.lock().unwrap();
+            if !standby.iter().any(|r| r.url == relay.url) {
+                standby.push(relay.clone());
+                log::info!("NS_NOSTR: added standby relay {}", relay);
+            }
+            return Ok(());
+        }
+
+        if add_relay_with_role(&self.client, relay).await? {
+            self.client.connect_relay(relay.url.as_str()).await?;
+            log::info!("NS_NOSTR: added relay {}", relay);
+        }
+        Ok(())
+    }
+
+    /// Disconnect and drop a relay (active or standby) at runtime
+    ///
+    /// A removed active relay is replaced by the first standby relay.
+    pub async fn remove_relay(&self, url: &str) -> Result<()> {
+        {
+            let mut standby = self.standby.lock().unwrap();
+            if let Some(i) = standby.iter().position(|r| r.url == url) {
+                standby.remove(i);
+                log::info!("NS_NOSTR: removed standby relay {}", url);
+                return Ok(());
+            }
+        }
+
+        self.client.force_remove_relay(url).await?;
+        self.relay_health.lock().unwrap().remove(url);
+        log::info!("NS_NOSTR: removed relay {}", url);
+
+        // Keep NOMAD_MAX_RELAYS relays active while standbys remain
+        let next = {
+            let mut standby = self.standby.lock().unwrap();
+            (!standby.is_empty()).then(|| standby.remove(0))
+        };
+        if let Some(next) = next {
+            self.add_relay(&next).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the active relay `url` with the first standby relay
+    ///
+    /// The replaced relay goes to the back of the standby list. Returns the
+    /// promoted relay, or None if there is no standby.
+    async fn promote_standby(&self, url: &str) -> Result<Option<RelayEntry>> {
+        let Some(promoted) = ({
+            let mut standby = self.standby.lock().unwrap();
+            (!standby.is_empty()).then(|| standby.remove(0))
+        }) else {
+            return Ok(None);
+        };
+
+        let demoted = self
+            .active_relay_entries()
+            .await
+            .into_iter()
+            .find(|r| r.url == url);
+
+        self.client.force_remove_relay(url).await?;
+        self.relay_health.lock().unwrap().remove(url);
+
+        if let Some(demoted) = demoted {
+            self.standby.lock().unwrap().push(demoted);
+        }
+
+        if add_relay_with_role(&self.client, &promoted).await? {
+            // Connection failures are retried by the relay monitor
+            if let Err(e) = self.client.connect_relay(promoted.url.as_str()).await {
+                log::warn!("NS_RELAY: connecting promoted relay {} failed: {}", promoted.url, e);
+            }
+        }
+
+        Ok(Some(promoted))
+    }
+
+    /// Relay state last recorded by the relay monitor, keyed by URL
+    pub fn relay_health(&self) -> HashMap<String, RelayHealth> {
+        self.relay_health.lock().unwrap().clone()
+    }
+
+    /// Traffic counters and RTT of every active relay, sorted by URL
+    pub async fn relay_stats(&self) -> Vec<RelayStats> {
+        let relays = self.client.relays().await;
+        let counters = self.relay_counters.lock().unwrap().clone();
+
+        let mut stats: Vec<RelayStats> = relays
+            .into_iter()
+            .map(|(url, relay)| {
+                let url = url.to_string();
+                let c = counters.get(&url).cloned().unwrap_or_default();
+                RelayStats {
+                    status: relay.status().to_string(),
+                    events_received: c.events_received,
+                    events_published: c.events_published,
+                    reconnects: c.reconnects,
+                    rtt_ms: relay.stats().latency().map(|d| d.as_millis() as u64),
+                    url,
+                }
+            })
+            .collect();
+
+        stats.sort_by(|a, b| a.url.cmp(&b.url));
+        stats
+    }
+}
+
+/// Count events received from and accepted by each relay
+async fn run_relay_counter(client: Arc<Client>, counters: Arc<Mutex<HashMap<String, RelayCounters>>>) {
+    let mut notifications = client.notifications();
+
+    loop {
+        let notif = match notifications.recv().await {
+            Ok(n) => n,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("NS_RELAY: stats missed {} notifications", n);
+                continue;
+            }
+            Err(_) => return,
+        };
+
+        if let RelayPoolNotification::Message { relay_url, message } = notif {
+            let mut counters = counters.lock().unwrap();
+            match message {
+                RelayMessage::Event { .. } => {
+                    counters.entry(relay_url.to_string()).or_default().events_received += 1;
+                }
+                RelayMessage::Ok { status: true, .. } => {
+                    counters.entry(relay_url.to_string()).or_default().events_published += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Add a relay with the service flags matching its role
+async fn add_relay_with_role(client: &Client, relay: &RelayEntry) -> Result<bool> {
+    let url = relay.url.as_str();
+
+    let added = match relay.role {
+        RelayRole::ReadWrite => client.add_relay(url).await?,
+        RelayRole::Read => client.add_read_relay(url).await?,
+        RelayRole::Write => client.add_write_relay(url).await?,
+    };
+
+    Ok(added)
+}
+
+/// Watch relay connections and reconnect dropped relays with backoff
+///
+/// Every RELAY_CHECK_INTERVAL the status of each relay is recorded (logging
+/// transitions); disconnected relays are reconnected, doubling the wait
+/// between attempts up to RELAY_MAX_BACKOFF.
+pub async fn run_relay_monitor(state: NostrState, shutdown: CancellationToken) {
+    let mut ticker = tokio::time::interval(RELAY_CHECK_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                log::info!("NS_RELAY: shutdown requested; stopping relay monitor");
+                return;
+            }
+            _ = ticker.tick() => {}
+        }
+
+        for (url, status) in state.relay_status().await {
+            let now = Instant::now();
+
+            let reconnect = {
+                let mut health = state.relay_health.lock().unwrap();
+                let entry = health.entry(url.clone()).or_insert(RelayHealth {
+                    status,
+                    since: now,
+                    reconnect_attempts: 0,
+                    next_attempt: now,
+                });
+
+                if entry.status != status {
+                    log::info!("NS_RELAY: {} {} -> {}", url, entry.status, status);
+                    entry.status = status;
+                    entry.since = now;
+                }
+
+                if status == RelayStatus::Connected {
+                    entry.reconnect_attempts = 0;
+                    entry.next_attempt = now;
+                }
+
+                let down = matches!(
+                    status,
+                    RelayStatus::Initialized | RelayStatus::Disconnected | RelayStatus::Terminated
+                );
+
+                if down && now >= entry.next_attempt {
+                    let backoff = RELAY_CHECK_INTERVAL
+                        .saturating_mul(2u32.saturating_pow(entry.reconnect_attempts))
+                        .min(RELAY_MAX_BACKOFF);
+                    entry.reconnect_attempts += 1;
+                    entry.next_attempt = now + backoff;
+                    Some((entry.reconnect_attempts, backoff))
+                } else {
+                    None
+                }
+            };
+
+            let promote = reconnect.is_some_and(|(attempt, _)| attempt > RELAY_PROMOTE_AFTER)
+                && !state.standby.lock().unwrap().is_empty();
+            if promote {
+                match state.promote_standby(&url).await {
+                    Ok(Some(promoted)) => {
+                        log::warn!(
+                            "NS_RELAY: {} still down after {} reconnects; promoted standby {}",
+                            url,
+                            RELAY_PROMOTE_AFTER,
+                            promoted
+                        );
+                        continue;
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::warn!("NS_RELAY: promoting a standby for {} failed: {}", url, e),
+                }
+            }
+
+            if let Some((attempt, backoff)) = reconnect {
+                log::warn!(
+                    "NS_RELAY: reconnecting to {} (attempt {}, next retry in {:?})",
+                    url,
+                    attempt,
+                    backoff
+                );
+
+                state.relay_counters.lock().unwrap().entry(url.clone()).or_default().reconnects += 1;
+
+                if let Err(e) = state.client.try_connect_relay(&url, Duration::from_secs(10)).await {
+                    log::warn!("NS_RELAY: reconnect to {} failed: {}", url, e);
+                }
+            }
+        }
+    }
+}
+
+/// Answer NIP-42 AUTH challenges from relays with the server keys
+async fn run_auth_responder(client: Arc<Client>, keys: Keys) {
+    let mut notifications = client.notifications();
+
+    loop {
+        let notif = match notifications.recv().await {
+            Ok(n) => n,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("NS_AUTH: notifications lagged by {}; continuing", n);
+                continue;
+            }
+            Err(_) => return,
+        };
+
+        if let RelayPoolNotification::Message {
+            relay_url,
+            message: RelayMessage::Auth { challenge },
+        } = notif
+        {
+            log::info!("NS_AUTH: relay {} requested NIP-42 authentication", relay_url);
+
+            match authenticate(&client, &keys, relay_url.clone(), challenge.into_owned()).await {
+                Ok(()) => log::info!("NS_AUTH: authenticated to {}", relay_url),
+                Err(e) => log::warn!("NS_AUTH: authentication to {} failed: {}", relay_url, e),
+            }
+        }
+    }
+}
+
+async fn authenticate(
+    client: &Client,
+    keys: &Keys,
+    relay_url: RelayUrl,
+    challenge: String,
+) -> Result<()> {
+    let relay = client.relay(&relay_url).await?;
+
+    let event = EventBuilder::auth(challenge, relay_url).sign_with_keys(keys)?;
+    let event_id = event.id;
+
+    // Subscribe before sending so the OK can't be missed
+    let mut notifications = relay.notifications();
+    relay.send_msg(ClientMessage::auth(event))?;
+
+    let ok = timeout(Duration::from_secs(10), async {
+        while let Ok(n) = notifications.recv().await {
+            if let RelayNotification::Message {
+                message: RelayMessage::Ok { event_id: id, status, message },
+            } = n
+            {
+                if id == event_id {
+                    return Some((status, message.into_owned()));
+                }
+            }
+        }
+        None
+    })
+    .await
+    .map_err(|_| anyhow!("timed out waiting for AUTH OK"))?;
+
+    match ok {
+        Some((true, _)) => {
+            // Subscriptions sent before AUTH may have been closed by the relay
+            relay.resubscribe().await?;
+            Ok(())
+        }
+        Some((false, message)) => Err(anyhow!("relay rejected AUTH: {}", message)),
+        None => Err(anyhow!("relay notifications closed")),
+    }
 }
 
 pub async fn run_nomadserver_nostr_loop(
     client: Arc<Client>,
     electrs: Arc<ElectrsClient>,
+    pairing_manager: PairingManager,
+    kinds: EventKinds,
+    timeouts: TimeoutConfig,
+    shutdown: CancellationToken,
 ) -> Result<()> {
     client.wait_for_connection(Duration::from_secs(10)).await;
 
@@ -46,19 +531,27 @@ pub async fn run_nomadserver_nostr_loop(
     let server_pk: PublicKey = client.public_key().await?;
     let server_pk_hex = server_pk.to_string();
 
-    // Filter: only kind 30078 that p-tags THIS server pubkey
+    // Filter: only request kind (default 30078) that p-tags THIS server pubkey
     let filter = Filter::new()
-        .kind(Kind::Custom(30078))
+        .kind(Kind::Custom(kinds.request))
         .custom_tag(SingleLetterTag::lowercase(Alphabet::P), server_pk_hex.clone());
 
     client.subscribe(filter, None).await?;
-    log::info!("NS_NOSTR: subscribed to kind=30078 p={}", server_pk_hex);
+    log::info!("NS_NOSTR: subscribed to kind={} p={}", kinds.request, server_pk_hex);
 
     // IMPORTANT: keep a receiver and do NOT crash on lag
     let mut notifications = client.notifications();
 
     loop {
-        let notif = match notifications.recv().await {
+        let received = tokio::select! {
+            _ = shutdown.cancelled() => {
+                log::info!("NS_NOSTR: shutdown requested; stopping loop");
+                return Ok(());
+            }
+            r = notifications.recv() => r,
+        };
+
+        let notif = match received {
             Ok(n) => n,
             Err(broadcast::error::RecvError::Lagged(n)) => {
                 log::warn!("NS_NOSTR: notifications lagged by {}; continuing", n);
@@ -70,8 +563,19 @@ pub async fn run_nomadserver_nostr_loop(
         };
 
         if let RelayPoolNotification::Event { event, .. } = notif {
+            if let Err(e) = event.verify() {
+                log::warn!("NS_NOSTR: rejecting forged request {} from {}: {}", event.id, event.pubkey, e);
+                continue;
+            }
+
+            if !pairing_manager.is_allowed(&event.pubkey) {
+                log::warn!("NS_NOSTR: ignoring request from unpaired device {}", event.pubkey);
+                continue;
+            }
+
             if let Err(e) =
-                handle_nomadserver_event(client.clone(), electrs.clone(), *event).await
+                handle_nomadserver_event(client.clone(), electrs.clone(), kinds, timeouts, *event)
+                    .await
             {
                 log::error!("NS_NOSTR: handler error: {e:?}");
             }
@@ -79,9 +583,171 @@ pub async fn run_nomadserver_nostr_loop(
     }
 }
 
+/// Poll subscribed addresses and push `address_activity` events for new txs
+///
+/// Also polls txs broadcast with `watch: true` and pushes `tx_confirmed`
+/// once they have a confirmation. Events use the response kind and p-tag
+/// the subscriber, but carry no `req` tag since they are not answers to a
+/// specific request.
+pub async fn run_address_watcher(
+    publisher: Publisher,
+    electrs: Arc<ElectrsClient>,
+    subscriptions: Arc<SubscriptionManager>,
+    identities: Identities,
+    poll_interval: Duration,
+    shutdown: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(poll_interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                log::info!("NS_WATCH: shutdown requested; stopping watcher");
+                return;
+            }
+            _ = ticker.tick() => {}
+        }
+
+        subscriptions.expire_idle();
+
+        for address in subscriptions.watched_addresses() {
+            let txids = match electrs.get_address_txs(&address).await {
+                Ok(t) => t,
+                Err(e) => {
+                    log::warn!("NS_WATCH: history lookup failed for {}: {}", address, e);
+                    continue;
+                }
+            };
+
+            for (pubkey, server, new_txids) in subscriptions.record_history(&address, &txids) {
+                let keys = &identities.get(&server).unwrap_or_else(|| identities.primary()).keys;
+
+                if let Err(e) =
+                    publish_address_activity(&publisher, keys, pubkey, &address, &new_txids)
+                        .await
+                {
+                    log::error!("NS_WATCH: failed to notify {}: {e:?}", pubkey);
+                }
+            }
+        }
+
+        for watch in subscriptions.watched_txs() {
+            let status = match electrs.get_tx_status(&watch.txid).await {
+                Ok(s) => s,
+                Err(e) => {
+                    log::warn!("NS_WATCH: status lookup failed for {}: {}", watch.txid, e);
+                    continue;
+                }
+            };
+            if !status.confirmed {
+                continue;
+            }
+
+            let keys = &identities.get(&watch.server).unwrap_or_else(|| identities.primary()).keys;
+
+            match publish_tx_confirmed(&publisher, keys, &watch, &status).await {
+                Ok(()) => subscriptions.unwatch_tx(&watch.txid, &watch.requester),
+                Err(e) => log::error!("NS_WATCH: failed to notify {}: {e:?}", watch.requester),
+            }
+        }
+    }
+}
+
+async fn publish_tx_confirmed(
+    publisher: &Publisher,
+    keys: &Keys,
+    watch: &TxWatch,
+    status: &TxStatus,
+) -> Result<()> {
+    let content = serde_json::json!({
+        "type": "tx_confirmed",
+        "txid": watch.txid,
+        "block_height": status.block_height,
+        "confirmations": status.confirmations,
+        "broadcast_req": watch.req,
+    })
+    .to_string();
+
+    publisher.publish(keys, watch.requester, None, &content).await?;
+
+    log::info!(
+        "NS_WATCH: notified {} that {} confirmed",
+        watch.requester,
+        watch.txid
+    );
+
+    Ok(())
+}
+
+async fn publish_address_activity(
+    publisher: &Publisher,
+    keys: &Keys,
+    pubkey: PublicKey,
+    address: &str,
+    txids: &[String],
+) -> Result<()> {
+    let content = serde_json::json!({
+        "type": "address_activity",
+        "address": address,
+        "txids": txids,
+    })
+    .to_string();
+
+    publisher.publish(keys, pubkey, None, &content).await?;
+
+    log::info!(
+        "NS_WATCH: notified {} of {} new tx(s) on {}",
+        pubkey,
+        txids.len(),
+        address
+    );
+
+    Ok(())
+}
+
+/// Announce a new relay list to paired devices (`relay_update`)
+///
+/// The event is signed with `keys`, the (unchanged) key of the identity the
+/// devices are paired with, and sent on the current write relays. Returns
+/// the number of devices notified.
+pub async fn publish_relay_update(
+    publisher: &Publisher,
+    keys: &Keys,
+    recipients: &[PublicKey],
+    relays: &[String],
+    config_hash: &str,
+) -> Result<usize> {
+    let content = serde_json::json!({
+        "type": "relay_update",
+        "relays": relays,
+        "configHash": config_hash,
+    })
+    .to_string();
+
+    let mut notified = 0;
+    for pubkey in recipients {
+        match publisher.publish(keys, *pubkey, None, &content).await {
+            Ok(_) => notified += 1,
+            Err(e) => log::warn!("NS_NOSTR: relay_update to {} failed: {}", pubkey, e),
+        }
+    }
+
+    log::info!(
+        "NS_NOSTR: relay_update sent to {}/{} paired devices (hash {})",
+        notified,
+        recipients.len(),
+        config_hash
+    );
+
+    Ok(notified)
+}
+
 async fn handle_nomadserver_event(
     client: Arc<Client>,
     electrs: Arc<ElectrsClient>,
+    kinds: EventKinds,
+    timeouts: TimeoutConfig,
     event: Event,
 ) -> Result<()> {
     // Parse JSON payload
@@ -112,20 +778,21 @@ async fn handle_nomadserver_event(
     log::info!(
         "NS_NOSTR: received request req={} from={} query={}",
         req_id,
-        event.pubkey.to_string(),
+        event.pubkey,
         query
     );
 
-    let response_json = build_response_json(electrs, query, &req_id).await?;
+    let response_json = build_response_json(electrs, timeouts, query, &req_id).await?;
 
-    // Publish response event kind 30079
-    let mut tags: Vec<Tag> = Vec::new();
-    tags.push(Tag::parse(vec!["req".to_string(), req_id.clone()])?);
-    // Optional but recommended: p-tag back to requester
-    tags.push(Tag::parse(vec!["p".to_string(), event.pubkey.to_string()])?);
+    // Publish response event (default kind 30079)
+    let tags: Vec<Tag> = vec![
+        Tag::parse(vec!["req".to_string(), req_id.clone()])?,
+        // Optional but recommended: p-tag back to requester
+        Tag::parse(vec!["p".to_string(), event.pubkey.to_string()])?,
+    ];
 
     // Build EventBuilder (no explicit pubkey; client injects and signs)
-    let builder = EventBuilder::new(Kind::Custom(30079), response_json)
+    let builder = EventBuilder::new(Kind::Custom(kinds.response), response_json)
         .tags(tags);
 
     // Sign using client-held keys
@@ -134,25 +801,35 @@ async fn handle_nomadserver_event(
     // Publish
     client.send_event(&signed).await?;
 
-    log::info!("NS_NOSTR: published response req={} kind=30079", req_id);
+    log::info!("NS_NOSTR: published response req={} kind={}", req_id, kinds.response);
 
     Ok(())
 }
 
 async fn build_response_json(
     electrs: Arc<ElectrsClient>,
+    timeouts: TimeoutConfig,
     query: &str,
     req_id: &str,
 ) -> Result<String> {
+    if !electrs.is_valid_address(query) {
+        log::warn!("NS_NOSTR: invalid address in req={}: {}", req_id, query);
+        return Ok(serde_json::json!({
+            "req": req_id,
+            "error": "invalid_address",
+        })
+        .to_string());
+    }
+
     let (confirmed, unconfirmed) = timeout(
-        Duration::from_secs(30),
+        timeouts.balance,
         electrs.get_address_balance(query),
     )
     .await
     .map_err(|_| anyhow!("Electrs balance timeout"))??;
 
     let txids = match timeout(
-        Duration::from_secs(20),
+        timeouts.txs,
         electrs.get_address_txs(query),
     )
     .await
@@ -161,24 +838,66 @@ async fn build_response_json(
         _ => Vec::new(),
     };
 
-    let transactions: Vec<Value> = txids
+    // Resolve amounts for the newest txs only (history is oldest first)
+    let split = txids.len().saturating_sub(crate::config::max_resolved_txs());
+    let amounts = match timeout(
+        timeouts.txs,
+        electrs.get_transaction_amounts(query, &txids[split..]),
+    )
+    .await
+    {
+        Ok(Ok(v)) => v.into_iter().map(Some).collect(),
+        _ => vec![None; txids.len() - split],
+    };
+
+    let mut transactions: Vec<Value> = Vec::with_capacity(txids.len());
+    for (txid, amount) in txids
         .iter()
-        .map(|txid| {
-            serde_json::json!({
-                "txid": txid,
-                "confirmations": 0,
-                "amount": 0
-            })
-        })
-        .collect();
+        .zip(std::iter::repeat_n(None, split).chain(amounts))
+    {
+        // Signed net amount for this address (negative = send); null if
+        // unknown or beyond the resolution cap
+        transactions.push(serde_json::json!({
+            "txid": txid,
+            "confirmations": 0,
+            "amount": amount
+        }));
+    }
 
     let resp = serde_json::json!({
         "req": req_id,
         "confirmedBalance": confirmed,
         "unconfirmedBalance": unconfirmed,
-        "transactions": transactions
+        "transactions": transactions,
+        "truncated": split > 0
     });
 
     Ok(resp.to_string())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::electrs::mock::{MockElectrum, Reply};
+
+    #[tokio::test]
+    async fn lookup_rejects_invalid_address_without_calling_electrs() {
+        let mock = MockElectrum::start(|method, _| match method {
+            "server.version" => Reply::Error("unsupported"),
+            _ => panic!("unexpected Electrs call {}", method),
+        });
+        let electrs = Arc::new(mock.client().unwrap());
+
+        for query in ["not-an-address", "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"] {
+            let json = build_response_json(electrs.clone(), TimeoutConfig::from_env(), query, "r1")
+                .await
+                .unwrap();
+
+            assert_eq!(
+                serde_json::from_str::<Value>(&json).unwrap(),
+                serde_json::json!({ "req": "r1", "error": "invalid_address" })
+            );
+        }
+    }
+}
@@ -1,24 +1,124 @@
 use anyhow::{anyhow, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
 use nostr_sdk::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::time::{timeout, Duration};
-use tracing::{error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 
-use crate::electrs::ElectrsClient;
+use crate::config::{
+    EventKinds, FeeConfig, PublishConfig, PublishStrategy, RateLimitConfig, TimeoutConfig,
+};
+use crate::electrs::{ElectrsClient, UtxoSet};
+use crate::error::ErrorCode;
 use crate::nostr::NostrState;
-use crate::pairing::PairingManager;
+use crate::tenants::{Identities, Identity};
+use crate::watchonly::{UsedAddress, WatchOnlyStore, WatchedXpub};
+use crate::debugdump::DebugDump;
+use crate::subscriptions::SubscriptionManager;
 
 pub const NOMAD_SERVER_REQUEST_KIND: u16 = 30078;
 pub const NOMAD_SERVER_RESPONSE_KIND: u16 = 30079;
 
+/// Default and maximum gap limit for `scan_xpub`
+const DEFAULT_XPUB_GAP_LIMIT: u32 = 20;
+const MAX_XPUB_GAP_LIMIT: u32 = 100;
+
+/// Default and maximum page size for `get_address_history` (txids per page)
+const DEFAULT_HISTORY_PAGE_SIZE: usize = 25;
+const MAX_HISTORY_PAGE_SIZE: usize = 100;
+
+/// Default and maximum transactions listed per `bitcoin_lookup` response
+const DEFAULT_LOOKUP_TX_LIMIT: usize = 50;
+const MAX_LOOKUP_TX_LIMIT: usize = 500;
+
+/// Request types this server knows; `get_capabilities` reports the subset
+/// enabled by NOMAD_ENABLED_REQUESTS
+pub const SUPPORTED_REQUEST_TYPES: &[&str] = &[
+    "bitcoin_lookup",
+    "broadcast_tx",
+    "test_broadcast",
+    "get_fees",
+    "get_utxos",
+    "get_mempool_info",
+    "get_fee_histogram",
+    "get_tx_details",
+    "get_tx_ancestry",
+    "get_tx_status",
+    "scan_xpub",
+    "register_xpub",
+    "sync_xpub",
+    "get_xpub_info",
+    "get_address_history",
+    "lookup_by_scripthash",
+    "subscribe_address",
+    "electrs_proxy",
+    "get_capabilities",
+];
+
+/// Publish a `scan_progress` event every this many scanned addresses
+const SCAN_PROGRESS_INTERVAL: u32 = 20;
+
+/// How often the request queue depth is logged while requests are pending
+const QUEUE_DEPTH_LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+
 /* -------------------- Request / Response -------------------- */
 
+/// Address in a multi-address request, optionally with a wallet label
+///
+/// Either a plain string or `{ "address": ..., "label": ... }`; the label
+/// (any JSON value, e.g. a derivation index) is echoed in the per-address
+/// results.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum AddressQuery {
+    Plain(String),
+    Labeled {
+        address: String,
+        label: Option<serde_json::Value>,
+    },
+}
+
+/// Wallet labels by address, from `AddressQuery::Labeled` entries
+type AddressLabels = HashMap<String, serde_json::Value>;
+
+/// Split queries into addresses (in order) and their labels
+fn split_labels(queries: Vec<AddressQuery>) -> (Vec<String>, AddressLabels) {
+    let mut labels = AddressLabels::new();
+
+    let addresses = queries
+        .into_iter()
+        .map(|q| match q {
+            AddressQuery::Plain(address) => address,
+            AddressQuery::Labeled { address, label } => {
+                if let Some(label) = label {
+                    labels.insert(address.clone(), label);
+                }
+                address
+            }
+        })
+        .collect();
+
+    (addresses, labels)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct BitcoinLookupRequest {
     #[serde(rename = "type")]
     req_type: String,
-    query: String,
+    /// Single address (original form)
+    query: Option<String>,
+    /// Several addresses aggregated into one response
+    queries: Option<Vec<AddressQuery>>,
+    /// Most transactions listed, newest first (default 50, max 500)
+    limit: Option<usize>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,6 +127,17 @@ struct BroadcastTxRequest {
     req_type: String,
     #[serde(rename = "txHex")]
     tx_hex: String,
+    /// Push a `tx_confirmed` event once the tx confirms
+    #[serde(default)]
+    watch: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TestBroadcastRequest {
+    #[serde(rename = "type")]
+    req_type: String,
+    #[serde(rename = "txHex")]
+    tx_hex: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -37,19 +148,106 @@ struct GetFeesRequest {
 
 #[derive(Debug, Serialize, Deserialize)]
 struct GetUtxosRequest {
+    #[serde(rename = "type")]
+    req_type: String,
+    addresses: Vec<AddressQuery>,
+    /// False to return only spendable UTXOs: confirmed, and mature if
+    /// coinbase (default true)
+    include_unconfirmed: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GetTxDetailsRequest {
+    #[serde(rename = "type")]
+    req_type: String,
+    txid: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GetTxAncestryRequest {
+    #[serde(rename = "type")]
+    req_type: String,
+    txid: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GetTxStatusRequest {
+    #[serde(rename = "type")]
+    req_type: String,
+    txid: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LookupByScripthashRequest {
+    #[serde(rename = "type")]
+    req_type: String,
+    /// Electrum scripthash: sha256 of the output script, byte-reversed, hex
+    scripthash: String,
+    /// Most transactions listed, newest first (default 50, max 500)
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ScanXpubRequest {
+    #[serde(rename = "type")]
+    req_type: String,
+    xpub: String,
+    gap_limit: Option<u32>,
+    /// Skip the scan cache
+    #[serde(default)]
+    force_rescan: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RegisterXpubRequest {
+    #[serde(rename = "type")]
+    req_type: String,
+    xpub: String,
+    gap_limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GetXpubInfoRequest {
+    #[serde(rename = "type")]
+    req_type: String,
+    /// xpub/ypub/zpub/tpub/... or a single-key output descriptor
+    xpub: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GetAddressHistoryRequest {
+    #[serde(rename = "type")]
+    req_type: String,
+    address: String,
+    last_seen_txid: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SubscribeAddressRequest {
     #[serde(rename = "type")]
     req_type: String,
     addresses: Vec<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct ElectrsProxyRequest {
+    #[serde(rename = "type")]
+    req_type: String,
+    method: String,
+    #[serde(default)]
+    params: Vec<serde_json::Value>,
+}
+
 /*
  Android MVP compatibility:
  - req inside JSON
  - legacy field names
 */
 #[derive(Debug, Serialize)]
+#[allow(non_snake_case)]
 struct BitcoinLookupResponse {
-    // Android MVP fields
+    // Android MVP fields (unsigned; a negative pending balance reads as 0)
     req: String,
     confirmedBalance: u64,
     unconfirmedBalance: u64,
@@ -58,16 +256,103 @@ struct BitcoinLookupResponse {
 
     // Modern fields
     confirmed_balance: u64,
-    unconfirmed_balance: u64,
+    /// Negative while unconfirmed txs spend confirmed coins
+    unconfirmed_balance: i64,
+    /// Newest first, at most the request's `limit`
     transactions: Vec<TransactionInfo>,
+    /// Transactions before `limit` was applied
+    total_count: usize,
+    /// Some older transactions have `amount: null` (see NOMAD_MAX_RESOLVED_TXS)
+    truncated: bool,
+    /// Mempool txids touching the address(es), pending incoming or outgoing
+    unconfirmed_txids: Vec<String>,
+    /// Some queried address already has confirmed history; receiving to it
+    /// again is address reuse
+    previously_used: bool,
+    /// Some queried address has any history (confirmed or mempool); false
+    /// means every address is fresh, not merely emptied
+    seen: bool,
+
+    // Per-address breakdown, only for multi-address (`queries`) lookups
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    addresses: Vec<AddressLookup>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Machine-readable failure reason (see `error_code`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
+
+impl BitcoinLookupResponse {
+    fn failed(req_id: &str, error: String, error_code: &'static str) -> Self {
+        Self {
+            req: req_id.to_string(),
+            confirmedBalance: 0,
+            unconfirmedBalance: 0,
+            confirmations: 0,
+            amount: 0,
+            confirmed_balance: 0,
+            unconfirmed_balance: 0,
+            transactions: vec![],
+            total_count: 0,
+            truncated: false,
+            unconfirmed_txids: vec![],
+            previously_used: false,
+            seen: false,
+            addresses: vec![],
+            error: Some(error),
+            error_code: Some(error_code),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
+struct AddressLookup {
+    address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<serde_json::Value>,
+    confirmed_balance: u64,
+    unconfirmed_balance: i64,
+    transactions: Vec<TransactionInfo>,
+    total_count: usize,
+    unconfirmed_txids: Vec<String>,
+    previously_used: bool,
+    seen: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
 struct BroadcastTxResponse {
     req: String,
     success: bool,
     txid: Option<String>,
+    error: Option<String>,         // raw error from Electrs / bitcoind
+    /// Backend that accepted the tx: "electrs" or "bitcoind" (RPC fallback)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    via: Option<&'static str>,
+    reject_code: Option<String>,   // e.g. "mempool_conflict", see parse_reject_reason
+    reject_reason: Option<String>, // human-readable explanation of reject_code
+    /// Set when the broadcast failed before a node judged the tx; node
+    /// rejections are classified by `reject_code` instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
+
+/// Dry-run result; distinct from BroadcastTxResponse so a wallet can never
+/// mistake a test for a real broadcast
+#[derive(Debug, Serialize)]
+struct TestBroadcastResponse {
+    req: String,
+    test: bool, // always true
+    allowed: bool,
+    txid: Option<String>,
+    fee: Option<u64>, // sats
+    vsize: Option<u64>,
+    reject_code: Option<String>,
+    reject_reason: Option<String>,
     error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
 }
 
 #[derive(Debug, Serialize)]
@@ -76,454 +361,3485 @@ struct GetFeesResponse {
     fast: u64,   // sat/vB
     medium: u64, // sat/vB
     slow: u64,   // sat/vB
+    /// The fees above are configured defaults, not a live estimate
+    fallback: bool,
+
+    /// Set when Electrs failed and the fees above are fallback defaults
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
 }
 
 #[derive(Debug, Serialize)]
 struct GetUtxosResponse {
     req: String,
     utxos: Vec<UtxoInfo>,
+    /// Addresses that could not be queried; `utxos` covers the rest
+    failed_addresses: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ScanXpubResponse {
+    req: String,
+    #[serde(rename = "type")]
+    response_type: &'static str, // "scan_complete"; earlier events are "scan_progress"
+    /// False if the scan failed; `error` / `error_code` say why
+    ok: bool,
+    /// Served from the scan cache (same xpub and tip as an earlier scan)
+    cached: bool,
+
+    confirmed_balance: u64,
+    unconfirmed_balance: i64,
+    first_unused_index: u32,
+    /// Same as `first_unused_index`, for the change chain
+    first_unused_change_index: u32,
+    used_addresses: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
+
+impl ScanXpubResponse {
+    fn failed(req_id: &str, error: String, error_code: &'static str) -> Self {
+        Self {
+            req: req_id.to_string(),
+            response_type: "scan_complete",
+            ok: false,
+            cached: false,
+            confirmed_balance: 0,
+            unconfirmed_balance: 0,
+            first_unused_index: 0,
+            first_unused_change_index: 0,
+            used_addresses: vec![],
+            error: Some(error),
+            error_code: Some(error_code),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
-struct TransactionInfo {
-    txid: String,
+struct RegisterXpubResponse {
+    req: String,
+    registered: bool,
+    gap_limit: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
+
+/// What changed for a registered xpub since the previous `sync_xpub`
+#[derive(Debug, Default, Serialize)]
+struct SyncXpubResponse {
+    req: String,
+    /// Addresses that got their first transaction since the last sync
+    new_used_addresses: Vec<String>,
+    /// Transactions not reported by an earlier sync
+    new_txids: Vec<String>,
+
+    // Current totals over every used address
+    confirmed_balance: u64,
+    unconfirmed_balance: i64,
+    used_count: usize,
+    first_unused_index: u32,
+    first_unused_change_index: u32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
 }
 
 #[derive(Debug, Serialize)]
-pub struct UtxoInfo {
-    pub txid: String,
-    pub vout: u32,
-    pub value: u64,
-    pub address: String,
-    pub confirmations: u32,
+struct GetXpubInfoResponse {
+    req: String,
+    master_fingerprint: Option<String>,
+    fingerprint: String,
+    parent_fingerprint: String,
+    depth: u8,
+    derivation_path: Option<String>,
+    path_from_descriptor: bool,
+    script_type: &'static str,
+    network: String,
+    /// False if the key is for another network than this server
+    network_matches: bool,
 }
 
-/* -------------------- Handler -------------------- */
+/// Intermediate update during a `scan_xpub`
+#[derive(Debug, Serialize)]
+struct ScanProgressResponse {
+    req: String,
+    #[serde(rename = "type")]
+    response_type: &'static str, // "scan_progress"
+    scanned: u32,
+    used: usize,
+    confirmed_balance: u64,
+    unconfirmed_balance: i64,
+}
 
-pub struct NostrHandler {
-    client: Arc<Client>,
-    keys: Keys,
-    electrs_client: Arc<ElectrsClient>,
+#[derive(Debug, Serialize)]
+struct GetTxDetailsResponse {
+    req: String,
+    txid: String,
+    fee: Option<u64>, // sats, null for coinbase / unknown prevouts
+    vsize: u64,       // weight / 4, rounded up
+    weight: u64,
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
 }
 
-impl NostrHandler {
-    pub async fn new(
-        nostr_state: NostrState,
-        keys: Keys,
-        _pairing_manager: PairingManager,
-        electrs_client: Arc<ElectrsClient>,
-    ) -> Result<Self> {
-        Ok(Self {
-            client: nostr_state.client.clone(),
-            keys,
-            electrs_client,
-        })
-    }
+/// Unconfirmed ancestor package for RBF/CPFP fee bumping; the totals
+/// include the tx itself and are null once it confirmed
+#[derive(Debug, Serialize)]
+struct GetTxAncestryResponse {
+    req: String,
+    txid: String,
+    confirmed: bool,
+    ancestor_count: Option<u32>,
+    ancestor_fees: Option<u64>, // sats
+    ancestor_vsize: Option<u64>,
 
-    pub async fn start_listening(&self) -> Result<()> {
-        let filter = Filter::new()
-            .kinds(vec![Kind::Custom(NOMAD_SERVER_REQUEST_KIND)]);
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
 
-        self.client.subscribe(filter, None).await?;
+/// Lightweight confirmation check for polling after a broadcast
+#[derive(Debug, Serialize)]
+struct GetTxStatusResponse {
+    req: String,
+    txid: String,
+    confirmed: bool,
+    block_height: Option<u32>,
+    confirmations: u32,
 
-        info!(
-            "Subscribed to NomadServer request kind={}",
-            NOMAD_SERVER_REQUEST_KIND
-        );
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
 
-        let mut notifications = self.client.notifications();
+/// Like a single-address lookup; without the script, tx amounts are null
+#[derive(Debug, Serialize)]
+struct ScripthashLookupResponse {
+    req: String,
+    scripthash: String,
+    confirmed_balance: u64,
+    unconfirmed_balance: i64,
+    /// Newest first, at most the request's `limit`
+    transactions: Vec<TransactionInfo>,
+    total_count: usize,
+    unconfirmed_txids: Vec<String>,
+    seen: bool,
 
-        // IMPORTANT: never exit this loop on bad events
-        while let Ok(notification) = notifications.recv().await {
-            if let RelayPoolNotification::Event { event, .. } = notification {
-                if event.kind.as_u16() != NOMAD_SERVER_REQUEST_KIND {
-                    continue;
-                }
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
 
-                let from_pk = event.pubkey;
+impl ScripthashLookupResponse {
+    fn failed(req_id: &str, scripthash: String, error: String, error_code: &'static str) -> Self {
+        Self {
+            req: req_id.to_string(),
+            scripthash,
+            confirmed_balance: 0,
+            unconfirmed_balance: 0,
+            transactions: vec![],
+            total_count: 0,
+            unconfirmed_txids: vec![],
+            seen: false,
+            error: Some(error),
+            error_code: Some(error_code),
+        }
+    }
+}
 
-                // 🔑 FIX: ignore events without req tag instead of crashing
-                let req_id = match extract_req_id(&event) {
-                    Some(v) => v,
-                    None => {
-                        warn!(
-                            "Ignoring NomadServer request without req tag (from={})",
-                            from_pk.to_hex()
-                        );
-                        continue;
-                    }
-                };
+#[derive(Debug, Serialize)]
+struct MempoolInfoResponse {
+    req: String,
+    total_vsize: u64,
+    total_fee: u64, // sats (approximate, from histogram)
+    fee_histogram: Vec<(f64, u64)>, // [sat/vB, vsize]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
 
-                // Parse JSON to extract type field
-                let content_value: serde_json::Value = match serde_json::from_str(&event.content) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        warn!(
-                            "Invalid request JSON (from={} req={}): {}",
-                            from_pk.to_hex(),
-                            req_id,
-                            e
-                        );
-                        continue;
-                    }
-                };
+#[derive(Debug, Serialize)]
+struct GetFeeHistogramResponse {
+    req: String,
+    histogram: Vec<(f64, u64)>, // [sat/vB, vsize], highest fee rate first
 
-                let req_type = content_value
-                    .get("type")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("")
-                    .to_string();
-
-                // Route based on message type
-                let result = match req_type.as_str() {
-                    "bitcoin_lookup" => {
-                        let parsed: BitcoinLookupRequest =
-                            match serde_json::from_value(content_value.clone()) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    warn!("Invalid bitcoin_lookup request: {}", e);
-                                    continue;
-                                }
-                            };
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
 
-                        info!(
-                            "Nostr lookup request: from={} req={} query={}",
-                            from_pk.to_hex(),
-                            req_id,
-                            parsed.query
-                        );
+#[derive(Debug, Serialize)]
+struct GetAddressHistoryResponse {
+    req: String,
+    address: String,
+    txids: Vec<String>,    // newest first
+    has_more: bool,
+    last_txid: Option<String>, // pass as last_seen_txid for the next page
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
 
-                        self.lookup_and_publish(from_pk, &req_id, parsed.query)
-                            .await
-                    }
+#[derive(Debug, Serialize)]
+struct SubscribeAddressResponse {
+    req: String,
+    subscribed: usize, // addresses now watched for this requester
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
 
-                    "broadcast_tx" => {
-                        let parsed: BroadcastTxRequest =
-                            match serde_json::from_value(content_value.clone()) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    warn!("Invalid broadcast_tx request: {}", e);
-                                    continue;
-                                }
-                            };
+#[derive(Debug, Serialize)]
+struct ElectrsProxyResponse {
+    req: String,
+    method: String,
+    result: Option<serde_json::Value>, // raw Electrum JSON result
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
 
-                        info!(
-                            "Nostr broadcast_tx request: from={} req={}",
-                            from_pk.to_hex(),
-                            req_id
-                        );
+/// Server version and feature handshake (`get_capabilities`, `/info.json`)
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    pub version: String,
+    pub request_types: Vec<String>,
+    pub network: String,
+    pub request_kind: u16,
+    pub response_kind: u16,
+    /// Responses carry a per-device `seq` and a server `ts` (unix seconds)
+    pub response_seq: bool,
+}
 
-                        self.broadcast_and_publish(from_pk, &req_id, parsed.tx_hex)
-                            .await
-                    }
+impl Capabilities {
+    pub fn new(network: bitcoin::Network, kinds: EventKinds) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            request_types: crate::config::enabled_requests(),
+            network: network.to_string(),
+            request_kind: kinds.request,
+            response_kind: kinds.response,
+            response_seq: crate::config::response_seq(),
+        }
+    }
+}
 
-                    "get_fees" => {
-                        info!(
-                            "Nostr get_fees request: from={} req={}",
-                            from_pk.to_hex(),
-                            req_id
-                        );
+#[derive(Debug, Serialize)]
+struct CapabilitiesResponse {
+    req: String,
+    #[serde(flatten)]
+    capabilities: Capabilities,
+}
 
-                        self.fees_and_publish(from_pk, &req_id).await
-                    }
+/// Envelope for a response whose JSON was gzipped (see `publish_response`)
+#[derive(Debug, Serialize)]
+struct CompressedResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    req: Option<String>,
+    content_encoding: &'static str,
+    /// base64 of the gzipped JSON response
+    payload: String,
+}
 
-                    "get_utxos" => {
-                        let parsed: GetUtxosRequest = match serde_json::from_value(content_value.clone()) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                warn!("Invalid get_utxos request: {}", e);
-                                continue;
-                            }
-                        };
+/// Generic error response for requests that were not processed
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    req: String,
+    error: String,
+    /// Same as `error`, which is already a code here; lets wallets read
+    /// `error_code` from every response
+    error_code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
 
-                        info!(
-                            "Nostr get_utxos request: from={} req={} addresses={}",
-                            from_pk.to_hex(),
-                            req_id,
-                            parsed.addresses.len()
-                        );
+#[derive(Debug, Clone, Serialize)]
+struct TransactionInfo {
+    txid: String,
+    /// Signed net effect on the queried address in sats (negative = send);
+    /// null if it couldn't be computed
+    amount: Option<i64>,
+    /// Null while unconfirmed
+    block_height: Option<u32>,
+    /// Null while unconfirmed, or if the header couldn't be fetched
+    block_hash: Option<String>,
+    /// Block header timestamp (unix seconds); null like `block_hash`
+    block_time: Option<u32>,
+}
 
-                        self.utxos_and_publish(from_pk, &req_id, parsed.addresses)
-                            .await
-                    }
+#[derive(Debug, Clone, Serialize)]
+pub struct UtxoInfo {
+    pub txid: String,
+    pub vout: u32,
+    pub value: u64,
+    pub address: String,
+    /// Wallet label sent with the address in the request, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<serde_json::Value>,
+    /// Output script kind ("p2wpkh", "p2tr", ...; "p2sh" may be nested SegWit)
+    pub script_type: &'static str,
+    pub confirmations: u32,
+    /// Confirmed, and mature if it's a coinbase output
+    pub spendable: bool,
+}
 
-                    _ => {
-                        warn!(
-                            "Unknown request type: {} from={} req={}",
-                            req_type,
-                            from_pk.to_hex(),
-                            req_id
-                        );
-                        continue;
-                    }
-                };
+/* -------------------- Broadcast idempotency -------------------- */
 
-                if let Err(e) = result {
-                    error!(
-                        "Request failed: type={} from={} req={} err={}",
-                        req_type,
-                        from_pk.to_hex(),
-                        req_id,
-                        e
-                    );
-                }
-            }
+/// How long a broadcast result is replayed for a resubmitted tx
+const BROADCAST_CACHE_TTL: Duration = Duration::from_secs(600);
+
+/// Recent broadcast results keyed by txid
+struct BroadcastCache {
+    entries: Mutex<HashMap<String, (BroadcastTxResponse, Instant)>>,
+}
+
+impl BroadcastCache {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
         }
+    }
 
-        Ok(())
+    fn get(&self, txid: &str) -> Option<BroadcastTxResponse> {
+        let entries = self.entries.lock().unwrap();
+
+        entries
+            .get(txid)
+            .filter(|(_, at)| at.elapsed() < BROADCAST_CACHE_TTL)
+            .map(|(response, _)| response.clone())
     }
 
+    /// Remember a final outcome: success or a recognised node rejection
+    ///
+    /// Timeouts and connection errors say nothing about the tx, so they are
+    /// not kept and a retry really tries again.
+    fn insert(&self, txid: &str, response: &BroadcastTxResponse) {
+        if !response.success && response.reject_code.is_none() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        entries.retain(|_, (_, at)| at.elapsed() < BROADCAST_CACHE_TTL);
+        entries.insert(txid.to_string(), (response.clone(), Instant::now()));
+    }
+}
+
+/* -------------------- Xpub scan cache -------------------- */
+
+/// Most scan results kept at once
+const SCAN_CACHE_CAPACITY: usize = 256;
+
+/// `scan_xpub` results keyed by a hash of xpub and gap limit, valid at one
+/// tip height
+///
+/// Hashed so the keys don't keep wallets' xpubs around in memory.
+/// Unconfirmed balances can change without a new block; wallets that care
+/// pass `force_rescan`.
+struct ScanCache {
+    entries: Mutex<HashMap<[u8; 32], (u32, ScanXpubResponse)>>,
+}
+
+impl ScanCache {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn key(xpub: &str, gap_limit: u32) -> [u8; 32] {
+        use bitcoin::hashes::{sha256, Hash};
+
+        sha256::Hash::hash(format!("{}\n{}", xpub, gap_limit).as_bytes()).to_byte_array()
+    }
+
+    fn get(&self, key: &[u8; 32], tip: u32) -> Option<ScanXpubResponse> {
+        let entries = self.entries.lock().unwrap();
+
+        entries
+            .get(key)
+            .filter(|(at, _)| *at == tip)
+            .map(|(_, response)| response.clone())
+    }
+
+    fn insert(&self, key: [u8; 32], tip: u32, response: ScanXpubResponse) {
+        let mut entries = self.entries.lock().unwrap();
+
+        // Results from older tips are useless now
+        entries.retain(|_, (at, _)| *at == tip);
+        if entries.len() >= SCAN_CACHE_CAPACITY {
+            entries.clear();
+        }
+        entries.insert(key, (tip, response));
+    }
+}
+
+/* -------------------- Duplicate delivery -------------------- */
+
+/// How long a received event id is remembered
+const RECENT_EVENT_TTL: Duration = Duration::from_secs(300);
+
+/// Most event ids remembered at once; the oldest are dropped first
+const RECENT_EVENT_CAPACITY: usize = 4096;
+
+/// Event ids received in the last RECENT_EVENT_TTL
+///
+/// Relays (or several relays carrying the same event) may deliver an event
+/// more than once. Keyed on the event id, not `req`: a wallet retrying a
+/// request signs a new event.
+struct RecentEvents {
+    seen: HashMap<EventId, Instant>,
+}
+
+impl RecentEvents {
+    fn new() -> Self {
+        Self {
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Start out knowing `ids`, e.g. those processed before a restart
+    fn with_ids(ids: impl IntoIterator<Item = EventId>) -> Self {
+        let mut recent = Self::new();
+        for id in ids {
+            recent.insert(id);
+        }
+        recent
+    }
+
+    /// Remember `id`; false if it was already seen recently
+    fn insert(&mut self, id: EventId) -> bool {
+        let now = Instant::now();
+
+        if let Some(at) = self.seen.get(&id) {
+            if at.elapsed() < RECENT_EVENT_TTL {
+                return false;
+            }
+        }
+
+        if self.seen.len() >= RECENT_EVENT_CAPACITY {
+            self.seen.retain(|_, at| at.elapsed() < RECENT_EVENT_TTL);
+
+            if self.seen.len() >= RECENT_EVENT_CAPACITY {
+                if let Some(oldest) = self.seen.iter().min_by_key(|(_, at)| **at).map(|(id, _)| *id) {
+                    self.seen.remove(&oldest);
+                }
+            }
+        }
+
+        self.seen.insert(id, now);
+        true
+    }
+}
+
+/// Whether a delivered event should be handled: its signature checks out
+/// and `recent` hasn't seen its id (relays redeliver events)
+fn accept_delivery(recent: &mut RecentEvents, event: &Event) -> bool {
+    // Don't rely on the relay (or SDK settings) having checked id and
+    // signature: a forged event could impersonate a paired device, or claim
+    // the id of a genuine one to get it dropped as a duplicate
+    if let Err(e) = event.verify() {
+        warn!(
+            id = %event.id.to_hex(),
+            from = %event.pubkey.to_hex(),
+            err = %e,
+            "Rejecting request with invalid signature"
+        );
+        return false;
+    }
+    if !recent.insert(event.id) {
+        debug!(id = %event.id.to_hex(), "Skipping duplicate event delivery");
+        return false;
+    }
+
+    true
+}
+
+/* -------------------- Event checkpoint -------------------- */
+
+const CHECKPOINT_FILENAME: &str = "last_event.json";
+
+/// How far before the newest processed request the subscription resumes
+///
+/// Wallet clocks disagree and relays deliver out of order, so a request
+/// older than the newest one seen may still be unanswered.
+const CHECKPOINT_MARGIN_SECS: u64 = 120;
+
+/// How often a changed checkpoint and response seqs are written to disk
+const CHECKPOINT_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Newest processed request timestamp and the requests processed within
+/// CHECKPOINT_MARGIN_SECS of it
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CheckpointFile {
+    created_at: u64,
+    #[serde(default)]
+    recent: Vec<(String, u64)>, // event id, created_at
+}
+
+/// Remembers the last processed request across restarts
+///
+/// Only sets where the subscription resumes; duplicates are dropped by
+/// event id in RecentEvents, which starts out with the ids recorded here.
+struct EventCheckpoint {
+    path: PathBuf,
+    last: Mutex<CheckpointFile>,
+    dirty: Mutex<bool>,
+}
+
+impl EventCheckpoint {
+    fn load(data_dir: &Path) -> Self {
+        let path = data_dir.join(CHECKPOINT_FILENAME);
+
+        let last = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!(err = %e, "Invalid event checkpoint; starting fresh");
+                CheckpointFile::default()
+            }),
+            Err(_) => CheckpointFile::default(),
+        };
+
+        Self {
+            path,
+            last: Mutex::new(last),
+            dirty: Mutex::new(false),
+        }
+    }
+
+    /// Subscription start, if anything was processed before
+    fn since(&self) -> Option<Timestamp> {
+        let last = self.last.lock().unwrap();
+        (last.created_at > 0)
+            .then(|| Timestamp::from(last.created_at.saturating_sub(CHECKPOINT_MARGIN_SECS)))
+    }
+
+    /// Ids of the requests processed around the checkpoint
+    fn recent_ids(&self) -> Vec<EventId> {
+        let last = self.last.lock().unwrap();
+        last.recent
+            .iter()
+            .filter_map(|(id, _)| EventId::from_hex(id).ok())
+            .collect()
+    }
+
+    /// Record a processed `event`; written out by the next `flush`
+    ///
+    /// Timestamps are clamped to now so a device with a fast clock can't
+    /// push the checkpoint into the future.
+    fn record(&self, event: &Event) {
+        let mut last = self.last.lock().unwrap();
+        let created_at = event.created_at.as_secs().min(Timestamp::now().as_secs());
+
+        last.created_at = last.created_at.max(created_at);
+        let oldest = last.created_at.saturating_sub(CHECKPOINT_MARGIN_SECS);
+        last.recent.retain(|(_, at)| *at >= oldest);
+        if created_at >= oldest {
+            last.recent.push((event.id.to_hex(), created_at));
+        }
+        if last.recent.len() > RECENT_EVENT_CAPACITY {
+            let excess = last.recent.len() - RECENT_EVENT_CAPACITY;
+            last.recent.drain(..excess);
+        }
+
+        *self.dirty.lock().unwrap() = true;
+    }
+
+    /// Write the checkpoint if it changed since the last flush
+    async fn flush(&self) {
+        if !std::mem::take(&mut *self.dirty.lock().unwrap()) {
+            return;
+        }
+
+        let json = match serde_json::to_string(&*self.last.lock().unwrap()) {
+            Ok(json) => json,
+            Err(e) => {
+                warn!(err = %e, "Failed to serialize event checkpoint");
+                return;
+            }
+        };
+        let path = self.path.clone();
+
+        match tokio::task::spawn_blocking(move || fs::write(path, json)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!(err = %e, "Failed to persist event checkpoint"),
+            Err(e) => warn!(err = %e, "Event checkpoint write panicked"),
+        }
+    }
+}
+
+/* -------------------- Rate limiting -------------------- */
+
+/// Token bucket per requester pubkey (hex)
+struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take one token for `pubkey`; returns false if the bucket is empty
+    fn check(&self, pubkey: &str) -> bool {
+        let capacity = self.config.max_requests as f64;
+        let refill_per_sec = capacity / self.config.window.as_secs_f64().max(0.001);
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // Drop buckets that have fully refilled so the map stays small
+        if buckets.len() > 1024 {
+            buckets.retain(|_, (tokens, last)| {
+                *tokens + last.elapsed().as_secs_f64() * refill_per_sec < capacity
+            });
+        }
+
+        let (tokens, last) = buckets
+            .entry(pubkey.to_string())
+            .or_insert((capacity, now));
+
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * refill_per_sec).min(capacity);
+        *last = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Which identity answers each in-flight request, by (requester, req id)
+///
+/// Set when a request is routed and cleared when its handling ends, so
+/// `publish_response` signs with the identity the request addressed.
+type ReplyIdentities = Mutex<HashMap<(PublicKey, String), PublicKey>>;
+
+/// Clears a request's `ReplyIdentities` entry when handling ends
+///
+/// The map doubles as the set of in-flight `(requester, req)` pairs.
+struct ReplyAs<'a> {
+    map: &'a ReplyIdentities,
+    key: (PublicKey, String),
+}
+
+impl<'a> ReplyAs<'a> {
+    /// None if `req_id` from `from` is already in flight
+    fn claim(map: &'a ReplyIdentities, from: PublicKey, req_id: &str, identity: &Identity) -> Option<Self> {
+        let key = (from, req_id.to_string());
+
+        let mut map_guard = map.lock().unwrap();
+        if map_guard.contains_key(&key) {
+            return None;
+        }
+        map_guard.insert(key.clone(), identity.keys.public_key());
+
+        Some(Self { map, key })
+    }
+}
+
+impl Drop for ReplyAs<'_> {
+    fn drop(&mut self) {
+        self.map.lock().unwrap().remove(&self.key);
+    }
+}
+
+/* -------------------- Publishing -------------------- */
+
+/// Signs and sends response-kind events to the write relays
+///
+/// Shared by request responses and pushes (address activity, tx
+/// confirmations, relay updates) so they all get the same compression,
+/// chunking, event-size limit and NOMAD_PUBLISH_STRATEGY.
+#[derive(Clone)]
+pub struct Publisher {
+    client: Arc<Client>,
+    config: PublishConfig,
+    kind: u16,
+    compress_threshold: usize,
+    chunk_size: usize,
+    max_event_bytes: usize,
+}
+
+impl Publisher {
+    pub fn new(client: Arc<Client>, kinds: EventKinds, config: PublishConfig) -> Self {
+        Self {
+            client,
+            config,
+            kind: kinds.response,
+            compress_threshold: crate::config::compress_threshold(),
+            chunk_size: crate::config::chunk_size(),
+            max_event_bytes: crate::config::max_event_bytes(),
+        }
+    }
+
+    /// Sign `json` for `to_pubkey` and send it (see `sign`)
+    pub async fn publish(
+        &self,
+        keys: &Keys,
+        to_pubkey: PublicKey,
+        req_id: Option<&str>,
+        json: &str,
+    ) -> Result<()> {
+        let label = req_id.unwrap_or("push");
+
+        for event in &self.sign(keys, to_pubkey, req_id, json)? {
+            self.send_with_strategy(event, label).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sign `json` as one or more events to `to_pubkey`, tagged with
+    /// `req_id` if it answers a request
+    ///
+    /// JSON over NOMAD_COMPRESS_THRESHOLD bytes is gzipped and sent as
+    /// `{ "req", "content_encoding": "gzip", "payload": <base64> }` with a
+    /// `content_encoding` tag, so it fits under relay event-size limits.
+    ///
+    /// Content still over NOMAD_CHUNK_SIZE is split across several events
+    /// tagged `chunk_index` / `chunk_total`; the wallet concatenates their
+    /// contents in index order to get the JSON back.
+    fn sign(
+        &self,
+        keys: &Keys,
+        to_pubkey: PublicKey,
+        req_id: Option<&str>,
+        json: &str,
+    ) -> Result<Vec<Event>> {
+        let label = req_id.unwrap_or("push");
+        let mut json = json.to_string();
+
+        let mut tags = vec![Tag::parse(["p", to_pubkey.to_hex().as_str()])?];
+        if let Some(req_id) = req_id {
+            tags.push(Tag::parse(["req", req_id])?);
+        }
+
+        if self.compress_threshold > 0 && json.len() > self.compress_threshold {
+            let payload = gzip_base64(json.as_bytes())?;
+            info!(
+                req = %label,
+                raw = json.len(),
+                compressed = payload.len(),
+                "Compressing response"
+            );
+
+            json = serde_json::to_string(&CompressedResponse {
+                req: req_id.map(str::to_string),
+                content_encoding: "gzip",
+                payload,
+            })?;
+            tags.push(Tag::parse(["content_encoding", "gzip"])?);
+        }
+
+        // Escaping the content and the event envelope add to the chunk
+        // size, so shrink chunks until every signed event fits the limit
+        let mut chunk_size = self.chunk_size;
+        loop {
+            let events = self.sign_chunks(keys, &json, &tags, chunk_size)?;
+            let largest = events.iter().map(|e| e.as_json().len()).max().unwrap_or(0);
+
+            if largest <= self.max_event_bytes {
+                return Ok(events);
+            }
+            if chunk_size <= MIN_CHUNK_SIZE {
+                return Err(anyhow!(
+                    "Response event is {} bytes, over NOMAD_MAX_EVENT_BYTES ({})",
+                    largest,
+                    self.max_event_bytes
+                ));
+            }
+
+            let shrunk = (chunk_size * self.max_event_bytes / largest)
+                .saturating_sub(256)
+                .max(MIN_CHUNK_SIZE);
+            debug!(req = %label, largest, from = chunk_size, to = shrunk, "Shrinking response chunks");
+            chunk_size = shrunk;
+        }
+    }
+
+    /// Split `json` into chunks of at most `chunk_size` bytes and sign one
+    /// event per chunk with `keys`
+    fn sign_chunks(
+        &self,
+        keys: &Keys,
+        json: &str,
+        tags: &[Tag],
+        chunk_size: usize,
+    ) -> Result<Vec<Event>> {
+        let chunks = split_chunks(json, chunk_size);
+        let chunk_total = chunks.len();
+
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut chunk_tags = tags.to_vec();
+                if chunk_total > 1 {
+                    chunk_tags.push(Tag::parse(["chunk_index", index.to_string().as_str()])?);
+                    chunk_tags.push(Tag::parse(["chunk_total", chunk_total.to_string().as_str()])?);
+                }
+
+                Ok(EventBuilder::new(Kind::Custom(self.kind), chunk)
+                    .tags(chunk_tags)
+                    .sign_with_keys(keys)?)
+            })
+            .collect()
+    }
+
+    /// Send `event` to every write relay, as NOMAD_PUBLISH_STRATEGY says
+    ///
+    /// `all` waits for each relay, `first-ack` for the first acceptance and
+    /// `quorum-N` for N acceptances (capped at the number of write relays).
+    /// Relays that failed or didn't answer within NOMAD_PUBLISH_TIMEOUT_SECS
+    /// get one more attempt while the strategy is unmet. Sends still running
+    /// when it returns finish in the background. `req_id` only tags the log
+    /// lines; pushes pass their type.
+    pub async fn send_with_strategy(&self, event: &Event, req_id: &str) -> Result<()> {
+        let relays: Vec<RelayUrl> = self
+            .client
+            .relays()
+            .await
+            .into_iter()
+            .filter(|(_, relay)| relay.flags().has_write())
+            .map(|(url, _)| url)
+            .collect();
+
+        // (acceptances to wait for, acceptances required)
+        let (wait_for, required) = match self.config.strategy {
+            PublishStrategy::All => (relays.len(), 1),
+            PublishStrategy::FirstAck => (1, 1),
+            PublishStrategy::Quorum(n) => {
+                let n = n.min(relays.len()).max(1);
+                (n, n)
+            }
+        };
+
+        let mut accepted: HashSet<RelayUrl> = HashSet::new();
+        let mut pending = relays;
+
+        for attempt in 1..=2 {
+            // One task per relay so an early return doesn't cancel the rest
+            let mut sends: FuturesUnordered<_> = pending
+                .iter()
+                .map(|url| {
+                    let client = Arc::clone(&self.client);
+                    let event = event.clone();
+                    let url = url.clone();
+                    let send_timeout = self.config.timeout;
+
+                    tokio::spawn(async move {
+                        let result = match timeout(send_timeout, client.send_event_to([url.clone()], &event)).await {
+                            Ok(Ok(output)) if output.success.contains(&url) => Ok(()),
+                            Ok(Ok(mut output)) => Err(output.failed.remove(&url).unwrap_or_default()),
+                            Ok(Err(e)) => Err(e.to_string()),
+                            Err(_) => Err("timed out".to_string()),
+                        };
+                        (url, result)
+                    })
+                })
+                .collect();
+
+            while accepted.len() < wait_for {
+                match sends.next().await {
+                    Some(Ok((url, Ok(())))) => {
+                        accepted.insert(url);
+                    }
+                    Some(Ok((url, Err(e)))) => {
+                        debug!(req = %req_id, relay = %url, err = %e, attempt, "Relay rejected response");
+                    }
+                    Some(Err(e)) => warn!(req = %req_id, err = %e, attempt, "Response send task failed"),
+                    None => break,
+                }
+            }
+
+            pending.retain(|url| !accepted.contains(url));
+            if accepted.len() >= wait_for || pending.is_empty() {
+                break;
+            }
+        }
+
+        let mut relays: Vec<String> = accepted.iter().map(|u| u.to_string()).collect();
+        relays.sort();
+
+        if accepted.len() < required {
+            return Err(anyhow!(
+                "response reached {} of the {} required relays",
+                accepted.len(),
+                required
+            ));
+        }
+
+        info!(req = %req_id, relays = %relays.join(","), "Response accepted");
+        Ok(())
+    }
+}
+
+/* -------------------- Handler -------------------- */
+
+pub struct NostrHandler {
+    client: Arc<Client>,
+    identities: Identities,
+    reply_as: ReplyIdentities,
+    electrs_client: Arc<ElectrsClient>,
+    rate_limiter: RateLimiter,
+    kinds: EventKinds,
+    subscriptions: Arc<SubscriptionManager>,
+    timeouts: TimeoutConfig,
+    max_resolved_txs: usize,
+    broadcast_cache: BroadcastCache,
+    max_concurrent_requests: usize,
+    request_lookback: Option<Duration>,
+    checkpoint: EventCheckpoint,
+    max_request_addresses: usize,
+    response_seq: bool,
+    fees: FeeConfig,
+    enabled_requests: Vec<String>,
+    publisher: Publisher,
+    scan_cache: ScanCache,
+    watch_only: WatchOnlyStore,
+    debug_dump: Option<DebugDump>,
+}
+
+impl NostrHandler {
+    pub async fn new(
+        nostr_state: NostrState,
+        identities: Identities,
+        electrs_client: Arc<ElectrsClient>,
+        kinds: EventKinds,
+        subscriptions: Arc<SubscriptionManager>,
+        timeouts: TimeoutConfig,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: nostr_state.client.clone(),
+            identities,
+            reply_as: Mutex::new(HashMap::new()),
+            electrs_client,
+            rate_limiter: RateLimiter::new(RateLimitConfig::from_env()),
+            kinds,
+            subscriptions,
+            timeouts,
+            max_resolved_txs: crate::config::max_resolved_txs(),
+            broadcast_cache: BroadcastCache::new(),
+            max_concurrent_requests: crate::config::max_concurrent_requests(),
+            request_lookback: crate::config::request_lookback(),
+            checkpoint: EventCheckpoint::load(&crate::config::get_data_dir()),
+            max_request_addresses: crate::config::max_request_addresses(),
+            response_seq: crate::config::response_seq(),
+            fees: FeeConfig::from_env(),
+            enabled_requests: crate::config::enabled_requests(),
+            publisher: Publisher::new(nostr_state.client.clone(), kinds, PublishConfig::from_env()),
+            scan_cache: ScanCache::new(),
+            watch_only: WatchOnlyStore::new(crate::config::get_data_dir()),
+            debug_dump: DebugDump::from_env(&crate::config::get_data_dir()),
+        })
+    }
+
+    /// Process requests until `shutdown` is cancelled
+    ///
+    /// Up to NOMAD_MAX_CONCURRENT_REQUESTS requests run at once; the rest
+    /// wait for a slot. On shutdown no new requests are accepted, but those
+    /// already accepted (e.g. a broadcast) complete and get their response.
+    pub async fn start_listening(&self, shutdown: CancellationToken) -> Result<()> {
+        let mut filter = Filter::new()
+            .kinds(vec![Kind::Custom(self.kinds.request)]);
+
+        // Several identities share the relays; only take events for ours
+        if self.identities.is_multi_tenant() {
+            filter = filter.pubkeys(self.identities.pubkeys());
+        }
+
+        let window = self
+            .request_lookback
+            .map(|lookback| Timestamp::now() - lookback);
+
+        // The later of the checkpoint and the window wins
+        let resume = self
+            .checkpoint
+            .since()
+            .filter(|since| window.is_none_or(|w| *since >= w));
+
+        if let Some(since) = resume {
+            info!(since = %since, "Resuming requests after last processed event");
+            filter = filter.since(since);
+        } else if let Some(window) = window {
+            info!(since = %window, "Ignoring requests older than the lookback window");
+            filter = filter.since(window);
+        }
+
+        self.client.subscribe(filter, None).await?;
+
+        info!(kind = self.kinds.request, "Subscribed to NomadServer request");
+
+        let mut notifications = self.client.notifications();
+
+        let permits = Semaphore::new(self.max_concurrent_requests);
+        let mut in_flight = FuturesUnordered::new();
+        let mut recent = RecentEvents::with_ids(self.checkpoint.recent_ids());
+        let mut depth_log = tokio::time::interval(QUEUE_DEPTH_LOG_INTERVAL);
+        let mut state_flush = tokio::time::interval(CHECKPOINT_FLUSH_INTERVAL);
+
+        // IMPORTANT: never exit this loop on bad events
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => {
+                    info!("Shutdown requested; Nostr handler stopping");
+                    break;
+                }
+                Some(()) = in_flight.next(), if !in_flight.is_empty() => {}
+                _ = state_flush.tick() => self.flush_state().await,
+                _ = depth_log.tick() => {
+                    if !in_flight.is_empty() {
+                        let running = self.max_concurrent_requests - permits.available_permits();
+                        info!(
+                            running,
+                            queued = in_flight.len().saturating_sub(running),
+                            "Request queue depth"
+                        );
+                    }
+                }
+                r = notifications.recv() => match r {
+                    Ok(RelayPoolNotification::Event { event, .. }) => {
+                        if accept_delivery(&mut recent, &event) {
+                            in_flight.push(self.handle_request(event, &permits));
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(_) => break,
+                },
+            }
+        }
+
+        // Let accepted requests finish (e.g. broadcasts) before returning
+        while in_flight.next().await.is_some() {}
+        self.flush_state().await;
+
+        Ok(())
+    }
+
+    /// Persist the event checkpoint and the response sequence numbers
+    async fn flush_state(&self) {
+        self.checkpoint.flush().await;
+
+        for identity in self.identities.iter() {
+            let pairing = identity.pairing.clone();
+
+            match tokio::task::spawn_blocking(move || pairing.flush()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!(err = %e, "Failed to persist response seq"),
+                Err(e) => warn!(err = %e, "Response seq write panicked"),
+            }
+        }
+    }
+
+    /// Validate, route and answer one request event
+    ///
+    /// Unpaired, untagged and rate-limited requests are dropped before
+    /// taking a worker slot from `permits`.
+    async fn handle_request(&self, event: Box<Event>, permits: &Semaphore) {
+        if event.kind.as_u16() != self.kinds.request {
+            return;
+        }
+
+        if let Some(dump) = &self.debug_dump {
+            dump.request(&event, extract_req_id(&event).as_deref());
+        }
+
+        let from_pk = event.pubkey;
+        let identity = self.identities.route(&event);
+
+        // Whitelist: only devices paired (non-revoked) with the addressed
+        // identity are served
+        if !identity.pairing.is_allowed(&from_pk) {
+            warn!(
+                from = %from_pk.to_hex(),
+                identity = %identity.name,
+                "Ignoring NomadServer request from unpaired device"
+            );
+            return;
+        }
+
+        // Where to resume after a restart; replays are dropped by id
+        // (RecentEvents) before getting here
+        self.checkpoint.record(&event);
+
+        // 🔑 FIX: ignore events without req tag instead of crashing
+        let req_id = match extract_req_id(&event) {
+            Some(v) => v,
+            None => {
+                warn!(
+                    from = %from_pk.to_hex(),
+                    "Ignoring NomadServer request without req tag"
+                );
+                return;
+            }
+        };
+
+        // Before claiming the req id, so over-limit requests can't hold
+        // in-flight slots or draw duplicate_req_id replies
+        if !self.rate_limiter.check(&from_pk.to_hex()) {
+            warn!(
+                from = %from_pk.to_hex(),
+                req = %req_id,
+                "Rate limited NomadServer request"
+            );
+            // Claimed only so the error is signed by the addressed identity
+            let _reply_as = ReplyAs::claim(&self.reply_as, from_pk, &req_id, identity);
+            if let Err(e) = self.publish_error(from_pk, &req_id, "rate_limited", None).await {
+                error!(req = %req_id, err = %e, "Failed to publish rate_limited response");
+            }
+            return;
+        }
+
+        // Two responses with one `req` would confuse the wallet's matching
+        let Some(_reply_as) = ReplyAs::claim(&self.reply_as, from_pk, &req_id, identity) else {
+            warn!(
+                from = %from_pk.to_hex(),
+                req = %req_id,
+                "Rejecting request reusing an in-flight req id"
+            );
+            let detail = Some("Another request with this req id is still being processed".to_string());
+            if let Err(e) = self.publish_error(from_pk, &req_id, "duplicate_req_id", detail).await {
+                error!(req = %req_id, err = %e, "Failed to publish duplicate_req_id response");
+            }
+            return;
+        };
+
+        // Wait for a worker slot; requests beyond the limit queue here
+        let _permit = permits
+            .acquire()
+            .await
+            .expect("request semaphore is never closed");
+
+        // Parse JSON to extract type field
+        let content_value: serde_json::Value = match serde_json::from_str(&event.content) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    err = %e,
+                    "Invalid request JSON"
+                );
+                self.reject_invalid(from_pk, &req_id, format!("Invalid JSON: {}", e))
+                    .await;
+                return;
+            }
+        };
+
+        let req_type = content_value
+            .get("type")
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string();
+
+        // Known but switched off by NOMAD_ENABLED_REQUESTS
+        if SUPPORTED_REQUEST_TYPES.contains(&req_type.as_str())
+            && !self.enabled_requests.contains(&req_type)
+        {
+            warn!(
+                "type" = %req_type,
+                from = %from_pk.to_hex(),
+                req = %req_id,
+                "Request type disabled"
+            );
+            let detail = format!("Request type '{}' is disabled on this server", req_type);
+            if let Err(e) = self.publish_error(from_pk, &req_id, "disabled", Some(detail)).await {
+                error!(req = %req_id, err = %e, "Failed to publish disabled response");
+            }
+            return;
+        }
+
+        // Route based on message type
+        let result = match req_type.as_str() {
+            "bitcoin_lookup" => {
+                let parsed: BitcoinLookupRequest =
+                    match serde_json::from_value(content_value.clone()) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            warn!("Invalid bitcoin_lookup request: {}", e);
+                            self.reject_invalid(
+                                from_pk,
+                                &req_id,
+                                format!("Invalid bitcoin_lookup request: {}", e),
+                            )
+                            .await;
+                            return;
+                        }
+                    };
+
+                let multi = parsed.queries.is_some();
+                let (addresses, labels) = match (parsed.queries, parsed.query) {
+                    (Some(queries), _) if !queries.is_empty() => split_labels(queries),
+                    (_, Some(query)) => (vec![query], AddressLabels::new()),
+                    _ => {
+                        warn!(req = %req_id, "bitcoin_lookup without query");
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            "Missing query or queries".to_string(),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                if addresses.len() > self.max_request_addresses {
+                    self.reject_too_many_addresses(from_pk, &req_id, addresses.len())
+                        .await;
+                    return;
+                }
+
+                let limit = parsed
+                    .limit
+                    .unwrap_or(DEFAULT_LOOKUP_TX_LIMIT)
+                    .clamp(1, MAX_LOOKUP_TX_LIMIT);
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    query = %addresses.join(","),
+                    "Nostr lookup request"
+                );
+
+                self.lookup_and_publish(from_pk, &req_id, addresses, labels, multi, limit)
+                    .await
+            }
+
+            "broadcast_tx" => {
+                let parsed: BroadcastTxRequest =
+                    match serde_json::from_value(content_value.clone()) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            warn!("Invalid broadcast_tx request: {}", e);
+                            self.reject_invalid(
+                                from_pk,
+                                &req_id,
+                                format!("Invalid broadcast_tx request: {}", e),
+                            )
+                            .await;
+                            return;
+                        }
+                    };
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    "Nostr broadcast_tx request"
+                );
+
+                self.broadcast_and_publish(from_pk, &req_id, parsed.tx_hex, parsed.watch)
+                    .await
+            }
+
+            "test_broadcast" => {
+                let parsed: TestBroadcastRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid test_broadcast request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid test_broadcast request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    "Nostr test_broadcast request"
+                );
+
+                self.test_broadcast_and_publish(from_pk, &req_id, parsed.tx_hex)
+                    .await
+            }
+
+            "get_fees" => {
+                let _parsed: GetFeesRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid get_fees request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid get_fees request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                info!(from = %from_pk.to_hex(), req = %req_id, "Nostr get_fees request");
+
+                self.fees_and_publish(from_pk, &req_id).await
+            }
+
+            "get_utxos" => {
+                let parsed: GetUtxosRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid get_utxos request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid get_utxos request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                if parsed.addresses.len() > self.max_request_addresses {
+                    self.reject_too_many_addresses(from_pk, &req_id, parsed.addresses.len())
+                        .await;
+                    return;
+                }
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    addresses = parsed.addresses.len(),
+                    "Nostr get_utxos request"
+                );
+
+                let include_unconfirmed = parsed.include_unconfirmed.unwrap_or(true);
+                let (addresses, labels) = split_labels(parsed.addresses);
+
+                self.utxos_and_publish(from_pk, &req_id, addresses, labels, include_unconfirmed)
+                    .await
+            }
+
+            "get_mempool_info" => {
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    "Nostr get_mempool_info request"
+                );
+
+                self.mempool_info_and_publish(from_pk, &req_id).await
+            }
+
+            "get_fee_histogram" => {
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    "Nostr get_fee_histogram request"
+                );
+
+                self.fee_histogram_and_publish(from_pk, &req_id).await
+            }
+
+            "get_capabilities" => {
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    "Nostr get_capabilities request"
+                );
+
+                self.capabilities_and_publish(from_pk, &req_id).await
+            }
+
+            "get_tx_details" => {
+                let parsed: GetTxDetailsRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid get_tx_details request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid get_tx_details request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    txid = %parsed.txid,
+                    "Nostr get_tx_details request"
+                );
+
+                self.tx_details_and_publish(from_pk, &req_id, parsed.txid)
+                    .await
+            }
+
+            "get_tx_ancestry" => {
+                let parsed: GetTxAncestryRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid get_tx_ancestry request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid get_tx_ancestry request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    txid = %parsed.txid,
+                    "Nostr get_tx_ancestry request"
+                );
+
+                self.tx_ancestry_and_publish(from_pk, &req_id, parsed.txid)
+                    .await
+            }
+
+            "get_tx_status" => {
+                let parsed: GetTxStatusRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid get_tx_status request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid get_tx_status request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    txid = %parsed.txid,
+                    "Nostr get_tx_status request"
+                );
+
+                self.tx_status_and_publish(from_pk, &req_id, parsed.txid)
+                    .await
+            }
+
+            "lookup_by_scripthash" => {
+                let parsed: LookupByScripthashRequest =
+                    match serde_json::from_value(content_value.clone()) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            warn!("Invalid lookup_by_scripthash request: {}", e);
+                            self.reject_invalid(
+                                from_pk,
+                                &req_id,
+                                format!("Invalid lookup_by_scripthash request: {}", e),
+                            )
+                            .await;
+                            return;
+                        }
+                    };
+
+                let limit = parsed
+                    .limit
+                    .unwrap_or(DEFAULT_LOOKUP_TX_LIMIT)
+                    .clamp(1, MAX_LOOKUP_TX_LIMIT);
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    scripthash = %parsed.scripthash,
+                    "Nostr lookup_by_scripthash request"
+                );
+
+                self.scripthash_lookup_and_publish(from_pk, &req_id, parsed.scripthash, limit)
+                    .await
+            }
+
+            "scan_xpub" => {
+                let parsed: ScanXpubRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid scan_xpub request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid scan_xpub request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                let gap_limit = parsed
+                    .gap_limit
+                    .unwrap_or(DEFAULT_XPUB_GAP_LIMIT)
+                    .clamp(1, MAX_XPUB_GAP_LIMIT);
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    gap_limit,
+                    "Nostr scan_xpub request"
+                );
+
+                self.scan_xpub_and_publish(
+                    from_pk,
+                    &req_id,
+                    parsed.xpub,
+                    gap_limit,
+                    parsed.force_rescan,
+                )
+                .await
+            }
+
+            "register_xpub" => {
+                let parsed: RegisterXpubRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid register_xpub request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid register_xpub request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                let gap_limit = parsed
+                    .gap_limit
+                    .unwrap_or(DEFAULT_XPUB_GAP_LIMIT)
+                    .clamp(1, MAX_XPUB_GAP_LIMIT);
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    gap_limit,
+                    "Nostr register_xpub request"
+                );
+
+                self.register_xpub_and_publish(from_pk, &req_id, parsed.xpub, gap_limit)
+                    .await
+            }
+
+            "sync_xpub" => {
+                info!(from = %from_pk.to_hex(), req = %req_id, "Nostr sync_xpub request");
+
+                self.sync_xpub_and_publish(from_pk, &req_id).await
+            }
+
+            "get_xpub_info" => {
+                let parsed: GetXpubInfoRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid get_xpub_info request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid get_xpub_info request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                info!(from = %from_pk.to_hex(), req = %req_id, "Nostr get_xpub_info request");
+
+                self.xpub_info_and_publish(from_pk, &req_id, parsed.xpub).await
+            }
+
+            "get_address_history" => {
+                let parsed: GetAddressHistoryRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid get_address_history request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid get_address_history request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                let limit = parsed
+                    .limit
+                    .unwrap_or(DEFAULT_HISTORY_PAGE_SIZE)
+                    .clamp(1, MAX_HISTORY_PAGE_SIZE);
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    address = %parsed.address,
+                    limit,
+                    "Nostr get_address_history request"
+                );
+
+                self.history_and_publish(
+                    from_pk,
+                    &req_id,
+                    parsed.address,
+                    parsed.last_seen_txid,
+                    limit,
+                )
+                .await
+            }
+
+            "subscribe_address" => {
+                let parsed: SubscribeAddressRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid subscribe_address request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid subscribe_address request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    addresses = parsed.addresses.len(),
+                    "Nostr subscribe_address request"
+                );
+
+                self.subscribe_and_publish(from_pk, &req_id, parsed.addresses)
+                    .await
+            }
+
+            "electrs_proxy" => {
+                let parsed: ElectrsProxyRequest = match serde_json::from_value(content_value.clone()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Invalid electrs_proxy request: {}", e);
+                        self.reject_invalid(
+                            from_pk,
+                            &req_id,
+                            format!("Invalid electrs_proxy request: {}", e),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
+                info!(
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    method = %parsed.method,
+                    "Nostr electrs_proxy request"
+                );
+
+                self.proxy_and_publish(from_pk, &req_id, parsed.method, parsed.params)
+                    .await
+            }
+
+            _ => {
+                warn!(
+                    "type" = %req_type,
+                    from = %from_pk.to_hex(),
+                    req = %req_id,
+                    "Unknown request type"
+                );
+                self.reject_invalid(
+                    from_pk,
+                    &req_id,
+                    format!("Unknown request type '{}'", req_type),
+                )
+                .await;
+                return;
+            }
+        };
+
+        if let Err(e) = result {
+            error!(
+                "type" = %req_type,
+                from = %from_pk.to_hex(),
+                req = %req_id,
+                err = %e,
+                "Request failed"
+            );
+        }
+    }
+
+    /// Balance and transactions (with net amounts) of a single address
+    ///
+    /// Only the newest `max_resolved_txs` transactions get an amount; the
+    /// returned flag is true when older ones were left unresolved.
+    async fn lookup_address(
+        &self,
+        req_id: &str,
+        address: &str,
+    ) -> Result<(u64, i64, Vec<TransactionInfo>, bool)> {
+        let (confirmed, unconfirmed) = timeout(
+            self.timeouts.balance,
+            self.electrs_client.get_address_balance(address),
+        )
+        .await
+        .map_err(|_| ErrorCode::Timeout.error("Electrs balance timeout"))??;
+
+        let history = match timeout(
+            self.timeouts.txs,
+            self.electrs_client.get_address_history_entries(address),
+        )
+        .await
+        {
+            Ok(Ok(v)) => v,
+            _ => vec![],
+        };
+        let txids: Vec<String> = history.iter().map(|h| h.txid.clone()).collect();
+
+        // History is oldest first; resolve the tail
+        let split = txids.len().saturating_sub(self.max_resolved_txs);
+        let truncated = split > 0;
+
+        let amounts = match timeout(
+            self.timeouts.txs,
+            self.electrs_client
+                .get_transaction_amounts(address, &txids[split..]),
+        )
+        .await
+        {
+            Ok(Ok(v)) => v.into_iter().map(Some).collect(),
+            Ok(Err(e)) => {
+                warn!(req = %req_id, err = %e, "Tx amounts failed");
+                vec![None; txids.len() - split]
+            }
+            Err(_) => {
+                warn!(req = %req_id, "Tx amounts timeout");
+                vec![None; txids.len() - split]
+            }
+        };
+
+        if truncated {
+            info!(
+                req = %req_id,
+                txs = txids.len(),
+                resolved = txids.len() - split,
+                "Tx amount resolution truncated"
+            );
+        }
+
+        // Block hashes and times, for the same tail as the amounts
+        let heights: Vec<u32> = history[split..].iter().filter_map(|h| h.height).collect();
+        let blocks = if heights.is_empty() {
+            HashMap::new()
+        } else {
+            match timeout(self.timeouts.txs, self.electrs_client.get_block_infos(&heights)).await {
+                Ok(Ok(v)) => v,
+                Ok(Err(e)) => {
+                    warn!(req = %req_id, err = %e, "Block infos failed");
+                    HashMap::new()
+                }
+                Err(_) => {
+                    warn!(req = %req_id, "Block infos timeout");
+                    HashMap::new()
+                }
+            }
+        };
+
+        let transactions = history
+            .into_iter()
+            .zip(std::iter::repeat_n(None, split).chain(amounts))
+            .map(|(entry, amount)| {
+                let block = entry.height.and_then(|h| blocks.get(&h));
+                TransactionInfo {
+                    txid: entry.txid,
+                    amount,
+                    block_height: entry.height,
+                    block_hash: block.map(|b| b.hash.clone()),
+                    block_time: block.map(|b| b.time),
+                }
+            })
+            .collect();
+
+        Ok((confirmed, unconfirmed, transactions, truncated))
+    }
+
+    /// Sum balances and merge transactions over `addresses`
+    async fn lookup_addresses(
+        &self,
+        req_id: &str,
+        addresses: Vec<String>,
+        labels: &AddressLabels,
+        multi: bool,
+        limit: usize,
+    ) -> Result<BitcoinLookupResponse> {
+        let mut confirmed: u64 = 0;
+        let mut unconfirmed: i64 = 0;
+        let mut transactions: Vec<TransactionInfo> = Vec::new();
+        let mut breakdown = Vec::with_capacity(addresses.len());
+        let mut truncated = false;
+        let mut unconfirmed_txids: Vec<String> = Vec::new();
+        let mut previously_used = false;
+        let mut seen = false;
+
+        for address in addresses {
+            let (c, u, txs, t) = self.lookup_address(req_id, &address).await?;
+            truncated |= t;
+
+            // Unused addresses can't have mempool activity; skip the call
+            let pending = if txs.is_empty() {
+                vec![]
+            } else {
+                match timeout(
+                    self.timeouts.txs,
+                    self.electrs_client.get_address_mempool(&address),
+                )
+                .await
+                {
+                    Ok(Ok(v)) => v,
+                    Ok(Err(e)) => {
+                        warn!(req = %req_id, err = %e, "Address mempool failed");
+                        vec![]
+                    }
+                    Err(_) => {
+                        warn!(req = %req_id, "Address mempool timeout");
+                        vec![]
+                    }
+                }
+            };
+
+            for txid in &pending {
+                if !unconfirmed_txids.contains(txid) {
+                    unconfirmed_txids.push(txid.clone());
+                }
+            }
+
+            confirmed = confirmed.saturating_add(c);
+            unconfirmed = unconfirmed.saturating_add(u);
+
+            // A tx touching several of the addresses is listed once with the
+            // summed net amount
+            for tx in &txs {
+                match transactions.iter_mut().find(|t| t.txid == tx.txid) {
+                    Some(existing) => {
+                        existing.amount = existing.amount.zip(tx.amount).map(|(a, b)| a + b);
+                    }
+                    None => transactions.push(tx.clone()),
+                }
+            }
+
+            let used = txs.iter().any(|t| t.block_height.is_some());
+            previously_used |= used;
+
+            // History includes mempool txs; a balance without history means
+            // the history call failed, so count it as seen as well
+            let address_seen = !txs.is_empty() || c != 0 || u != 0;
+            seen |= address_seen;
+
+            if multi {
+                let total_count = txs.len();
+                breakdown.push(AddressLookup {
+                    label: labels.get(&address).cloned(),
+                    address,
+                    confirmed_balance: c,
+                    unconfirmed_balance: u,
+                    transactions: newest_first(txs, limit),
+                    total_count,
+                    unconfirmed_txids: pending,
+                    previously_used: used,
+                    seen: address_seen,
+                });
+            }
+        }
+
+        info!(
+            req = %req_id,
+            confirmed,
+            unconfirmed,
+            txs = transactions.len(),
+            "Lookup OK"
+        );
+
+        let total_count = transactions.len();
+
+        Ok(BitcoinLookupResponse {
+            req: req_id.to_string(),
+            confirmedBalance: confirmed,
+            unconfirmedBalance: unconfirmed.max(0) as u64,
+            confirmations: total_count as u64,
+            amount: confirmed.saturating_add_signed(unconfirmed),
+
+            confirmed_balance: confirmed,
+            unconfirmed_balance: unconfirmed,
+            transactions: newest_first(transactions, limit),
+            total_count,
+            truncated,
+            unconfirmed_txids,
+            previously_used,
+            seen,
+            addresses: breakdown,
+            error: None,
+            error_code: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
     async fn lookup_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        addresses: Vec<String>,
+        labels: AddressLabels,
+        multi: bool,
+        limit: usize,
+    ) -> Result<()> {
+        let response = if let Some(address) = addresses
+            .iter()
+            .find(|a| !self.electrs_client.is_valid_address(a))
+        {
+            warn!(req = %req_id, query = %address, "Lookup rejected: invalid address");
+            BitcoinLookupResponse::failed(
+                req_id,
+                format!(
+                    "Not a valid {} address: {}",
+                    self.electrs_client.network(),
+                    address
+                ),
+                "invalid_address",
+            )
+        } else {
+            match self.lookup_addresses(req_id, addresses, &labels, multi, limit).await {
+                Ok(response) => response,
+                Err(e) => {
+                    warn!(req = %req_id, err = %e, "Lookup failed");
+                    BitcoinLookupResponse::failed(req_id, e.to_string(), error_code(&e))
+                }
+            }
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn broadcast_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        tx_hex: String,
+        watch: bool,
+    ) -> Result<()> {
+        let txid = txid_from_hex(&tx_hex);
+
+        // A retried broadcast (lost response) gets the original result back
+        let cached = txid.as_deref().and_then(|t| self.broadcast_cache.get(t));
+
+        let response = if let Some(mut cached) = cached {
+            info!(req = %req_id, txid = ?txid, "Broadcast replayed from cache");
+            cached.req = req_id.to_string();
+            cached
+        } else {
+            info!(req = %req_id, "Broadcasting transaction");
+
+            let electrs = self.electrs_client.clone();
+            let hex = tx_hex.to_string();
+
+            let result = timeout(
+                self.timeouts.broadcast,
+                electrs.broadcast_transaction(&hex),
+            )
+            .await;
+
+            let response = match result {
+                Ok(Ok(broadcast)) => {
+                    info!(req = %req_id, txid = %broadcast.txid, via = broadcast.via, "Broadcast OK");
+                    BroadcastTxResponse {
+                        req: req_id.to_string(),
+                        success: true,
+                        txid: Some(broadcast.txid),
+                        error: None,
+                        via: Some(broadcast.via),
+                        reject_code: None,
+                        reject_reason: None,
+                        error_code: None,
+                    }
+                }
+                Ok(Err(e)) => {
+                    let raw = format!("{}", e);
+                    let reject = parse_reject_reason(&raw);
+                    warn!(
+                        req = %req_id,
+                        err = %e,
+                        reject_code = reject.map(|(code, _)| code).unwrap_or("unknown"),
+                        "Broadcast failed"
+                    );
+                    BroadcastTxResponse {
+                        req: req_id.to_string(),
+                        success: false,
+                        txid: None,
+                        error: Some(raw),
+                        via: None,
+                        reject_code: reject.map(|(code, _)| code.to_string()),
+                        reject_reason: reject.map(|(_, reason)| reason.to_string()),
+                        error_code: reject.is_none().then(|| error_code(&e)),
+                    }
+                }
+                Err(_) => {
+                    warn!(req = %req_id, "Broadcast timeout");
+                    BroadcastTxResponse {
+                        req: req_id.to_string(),
+                        success: false,
+                        txid: None,
+                        error: Some("Timeout".to_string()),
+                        via: None,
+                        reject_code: None,
+                        reject_reason: None,
+                        error_code: Some("timeout"),
+                    }
+                }
+            };
+
+            if let Some(txid) = &txid {
+                self.broadcast_cache.insert(txid, &response);
+            }
+
+            response
+        };
+
+        if let (true, true, Some(txid)) = (watch, response.success, &response.txid) {
+            let server = self.reply_identity(to_pubkey, req_id).keys.public_key();
+            if self.subscriptions.watch_tx(txid, to_pubkey, server, req_id) {
+                info!(req = %req_id, txid = %txid, "Watching tx for confirmation");
+            } else {
+                warn!(req = %req_id, txid = %txid, "Too many watched txs; not watching");
+            }
+        }
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn test_broadcast_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        tx_hex: String,
+    ) -> Result<()> {
+        let result = timeout(
+            self.timeouts.broadcast,
+            self.electrs_client.test_mempool_accept(&tx_hex),
+        )
+        .await;
+
+        let response = match result {
+            Ok(Ok(accept)) => {
+                let reject = accept.reject_reason.as_deref().and_then(parse_reject_reason);
+                info!(
+                    req = %req_id,
+                    txid = %accept.txid,
+                    allowed = accept.allowed,
+                    reason = ?accept.reject_reason,
+                    "Test broadcast OK"
+                );
+                TestBroadcastResponse {
+                    req: req_id.to_string(),
+                    test: true,
+                    allowed: accept.allowed,
+                    txid: Some(accept.txid),
+                    fee: accept.fee,
+                    vsize: Some(accept.vsize),
+                    reject_code: reject.map(|(code, _)| code.to_string()),
+                    reject_reason: reject
+                        .map(|(_, reason)| reason.to_string())
+                        .or(accept.reject_reason),
+                    error: None,
+                    error_code: None,
+                }
+            }
+            Ok(Err(e)) => {
+                warn!(req = %req_id, err = %e, "Test broadcast failed");
+                TestBroadcastResponse {
+                    req: req_id.to_string(),
+                    test: true,
+                    allowed: false,
+                    txid: None,
+                    fee: None,
+                    vsize: None,
+                    reject_code: None,
+                    reject_reason: None,
+                    error: Some(format!("{}", e)),
+                    error_code: Some(error_code(&e)),
+                }
+            }
+            Err(_) => {
+                warn!(req = %req_id, "Test broadcast timeout");
+                TestBroadcastResponse {
+                    req: req_id.to_string(),
+                    test: true,
+                    allowed: false,
+                    txid: None,
+                    fee: None,
+                    vsize: None,
+                    reject_code: None,
+                    reject_reason: None,
+                    error: Some("Timeout".to_string()),
+                    error_code: Some("timeout"),
+                }
+            }
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn fees_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+    ) -> Result<()> {
+        info!(req = %req_id, "Estimating fees");
+
+        let electrs = self.electrs_client.clone();
+
+        let result = timeout(
+            self.timeouts.fees,
+            electrs.estimate_fees(),
+        )
+        .await;
+
+        let (fast, medium, slow) = self.fees.fallback;
+
+        let ((fast, medium, slow), error, code) = match result {
+            Ok(Ok((f, m, s))) => {
+                info!(req = %req_id, fast = f, medium = m, slow = s, "Fees OK");
+
+                // A misbehaving node can report absurd rates; cap them
+                let ceiling = self.fees.ceiling;
+                if f > ceiling || m > ceiling || s > ceiling {
+                    warn!(
+                        req = %req_id,
+                        fast = f,
+                        medium = m,
+                        slow = s,
+                        ceiling,
+                        "Fee estimate above ceiling, clamping"
+                    );
+                }
+
+                ((f.min(ceiling), m.min(ceiling), s.min(ceiling)), None, None)
+            }
+            Ok(Err(e)) => {
+                warn!(req = %req_id, err = %e, "Fee estimation failed, using defaults");
+                ((fast, medium, slow), Some(e.to_string()), Some(error_code(&e)))
+            }
+            Err(_) => {
+                warn!(req = %req_id, "Fee estimation timed out, using defaults");
+                (
+                    (fast, medium, slow),
+                    Some("Fee estimation timed out".to_string()),
+                    Some("timeout"),
+                )
+            }
+        };
+
+        let response = GetFeesResponse {
+            req: req_id.to_string(),
+            fast,
+            medium,
+            slow,
+            fallback: error.is_some(),
+            error,
+            error_code: code,
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn utxos_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        addresses: Vec<String>,
+        labels: AddressLabels,
+        include_unconfirmed: bool,
+    ) -> Result<()> {
+        info!(req = %req_id, addresses = addresses.len(), "Fetching UTXOs");
+
+        let electrs = self.electrs_client.clone();
+        let addrs = addresses.to_vec();
+
+        // Respect the single-flight gate pattern
+        let result = timeout(
+            self.timeouts.utxos,
+            electrs.get_utxos(&addrs),
+        )
+        .await;
+
+        let (set, error, code) = match result {
+            Ok(Ok(set)) => {
+                info!(
+                    req = %req_id,
+                    count = set.utxos.len(),
+                    failed = set.failed_addresses.len(),
+                    "UTXOs OK"
+                );
+                (set, None, None)
+            }
+            Ok(Err(e)) => {
+                warn!(req = %req_id, err = %e, "UTXO fetch error");
+                let set = UtxoSet { utxos: vec![], failed_addresses: addresses };
+                (set, Some(e.to_string()), Some(error_code(&e)))
+            }
+            Err(_) => {
+                warn!(req = %req_id, "UTXO fetch timeout");
+                let set = UtxoSet { utxos: vec![], failed_addresses: addresses };
+                (set, Some("UTXO fetch timed out".to_string()), Some("timeout"))
+            }
+        };
+
+        let mut utxos = set.utxos;
+        if !include_unconfirmed {
+            utxos.retain(|u| u.spendable);
+        }
+        for utxo in utxos.iter_mut() {
+            utxo.label = labels.get(&utxo.address).cloned();
+        }
+
+        let response = GetUtxosResponse {
+            req: req_id.to_string(),
+            utxos,
+            failed_addresses: set.failed_addresses,
+            error,
+            error_code: code,
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn mempool_info_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+    ) -> Result<()> {
+        let result = timeout(
+            self.timeouts.mempool,
+            self.electrs_client.get_mempool(),
+        )
+        .await;
+
+        let response = match result {
+            Ok(Ok(info)) => {
+                info!(
+                    req = %req_id,
+                    vsize = info.total_vsize,
+                    buckets = info.fee_histogram.len(),
+                    "Mempool info OK"
+                );
+                MempoolInfoResponse {
+                    req: req_id.to_string(),
+                    total_vsize: info.total_vsize,
+                    total_fee: info.total_fee,
+                    fee_histogram: info.fee_histogram,
+                    error: None,
+                    error_code: None,
+                }
+            }
+            Ok(Err(e)) => {
+                warn!(req = %req_id, err = %e, "Mempool info failed");
+                MempoolInfoResponse {
+                    req: req_id.to_string(),
+                    total_vsize: 0,
+                    total_fee: 0,
+                    fee_histogram: vec![],
+                    error: Some(format!("{}", e)),
+                    error_code: Some(error_code(&e)),
+                }
+            }
+            Err(_) => {
+                warn!(req = %req_id, "Mempool info timeout");
+                MempoolInfoResponse {
+                    req: req_id.to_string(),
+                    total_vsize: 0,
+                    total_fee: 0,
+                    fee_histogram: vec![],
+                    error: Some("Timeout".to_string()),
+                    error_code: Some("timeout"),
+                }
+            }
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn fee_histogram_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+    ) -> Result<()> {
+        let result = timeout(
+            self.timeouts.fees,
+            self.electrs_client.get_fee_histogram(),
+        )
+        .await;
+
+        let (histogram, error, code) = match result {
+            Ok(Ok(h)) => {
+                info!(req = %req_id, buckets = h.len(), "Fee histogram OK");
+                (h, None, None)
+            }
+            Ok(Err(e)) => {
+                warn!(req = %req_id, err = %e, "Fee histogram failed");
+                (vec![], Some(e.to_string()), Some(error_code(&e)))
+            }
+            Err(_) => {
+                warn!(req = %req_id, "Fee histogram timeout");
+                (vec![], Some("Fee histogram timed out".to_string()), Some("timeout"))
+            }
+        };
+
+        let response = GetFeeHistogramResponse {
+            req: req_id.to_string(),
+            histogram,
+            error,
+            error_code: code,
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn xpub_info_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        xpub_str: String,
+    ) -> Result<()> {
+        let info = match crate::xpub::xpub_info(&xpub_str, self.electrs_client.network()) {
+            Ok(info) => info,
+            Err(e) => {
+                warn!(req = %req_id, err = %e, "Xpub info failed");
+                return self
+                    .publish_error(to_pubkey, req_id, "invalid_xpub", Some(e.to_string()))
+                    .await;
+            }
+        };
+
+        if !info.network_matches {
+            warn!(
+                req = %req_id,
+                key_network = %info.network,
+                "Xpub is for a different network than this server"
+            );
+        }
+
+        let response = GetXpubInfoResponse {
+            req: req_id.to_string(),
+            master_fingerprint: info.master_fingerprint,
+            fingerprint: info.fingerprint,
+            parent_fingerprint: info.parent_fingerprint,
+            depth: info.depth,
+            derivation_path: info.derivation_path,
+            path_from_descriptor: info.path_from_descriptor,
+            script_type: info.script_type.as_str(),
+            network: info.network.to_string(),
+            network_matches: info.network_matches,
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn history_and_publish(
         &self,
         to_pubkey: PublicKey,
         req_id: &str,
         address: String,
+        last_seen_txid: Option<String>,
+        limit: usize,
     ) -> Result<()> {
-        let (confirmed, unconfirmed) = timeout(
-            Duration::from_secs(30),
-            self.electrs_client.get_address_balance(&address),
+        let result = timeout(
+            self.timeouts.history,
+            self.electrs_client
+                .get_address_history(&address, last_seen_txid.as_deref(), limit),
         )
-        .await
-        .map_err(|_| anyhow!("Electrs balance timeout"))??;
+        .await;
+
+        let response = match result {
+            Ok(Ok(page)) => {
+                info!(
+                    req = %req_id,
+                    txs = page.txids.len(),
+                    has_more = page.has_more,
+                    "Address history OK"
+                );
+                GetAddressHistoryResponse {
+                    req: req_id.to_string(),
+                    address,
+                    last_txid: page.txids.last().cloned(),
+                    txids: page.txids,
+                    has_more: page.has_more,
+                    error: None,
+                    error_code: None,
+                }
+            }
+            Ok(Err(e)) => {
+                warn!(req = %req_id, err = %e, "Address history failed");
+                GetAddressHistoryResponse {
+                    req: req_id.to_string(),
+                    address,
+                    txids: vec![],
+                    has_more: false,
+                    last_txid: None,
+                    error: Some(format!("{}", e)),
+                    error_code: Some(error_code(&e)),
+                }
+            }
+            Err(_) => {
+                warn!(req = %req_id, "Address history timeout");
+                GetAddressHistoryResponse {
+                    req: req_id.to_string(),
+                    address,
+                    txids: vec![],
+                    has_more: false,
+                    last_txid: None,
+                    error: Some("Timeout".to_string()),
+                    error_code: Some("timeout"),
+                }
+            }
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn subscribe_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        addresses: Vec<String>,
+    ) -> Result<()> {
+        let invalid = addresses
+            .iter()
+            .find(|a| !self.electrs_client.is_valid_address(a));
+
+        let response = match invalid {
+            Some(address) => {
+                warn!(req = %req_id, address = %address, "Subscribe rejected: invalid address");
+                SubscribeAddressResponse {
+                    req: req_id.to_string(),
+                    subscribed: 0,
+                    error: Some(format!("Invalid address: {}", address)),
+                    error_code: Some(ErrorCode::InvalidAddress.as_str()),
+                }
+            }
+            None => {
+                let server = self.reply_identity(to_pubkey, req_id).keys.public_key();
+                let subscribed = self.subscriptions.subscribe(to_pubkey, server, addresses);
+                info!(req = %req_id, subscribed, "Address subscription updated");
+                SubscribeAddressResponse {
+                    req: req_id.to_string(),
+                    subscribed,
+                    error: None,
+                    error_code: None,
+                }
+            }
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn proxy_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        method: String,
+        params: Vec<serde_json::Value>,
+    ) -> Result<()> {
+        let result = timeout(
+            self.timeouts.proxy,
+            self.electrs_client.proxy_call(&method, &params),
+        )
+        .await;
+
+        let response = match result {
+            Ok(Ok(value)) => {
+                info!(req = %req_id, method = %method, "Electrs proxy OK");
+                ElectrsProxyResponse {
+                    req: req_id.to_string(),
+                    method,
+                    result: Some(value),
+                    error: None,
+                    error_code: None,
+                }
+            }
+            Ok(Err(e)) => {
+                warn!(req = %req_id, method = %method, err = %e, "Electrs proxy failed");
+                ElectrsProxyResponse {
+                    req: req_id.to_string(),
+                    method,
+                    result: None,
+                    error: Some(format!("{}", e)),
+                    error_code: Some(error_code(&e)),
+                }
+            }
+            Err(_) => {
+                warn!(req = %req_id, method = %method, "Electrs proxy timeout");
+                ElectrsProxyResponse {
+                    req: req_id.to_string(),
+                    method,
+                    result: None,
+                    error: Some("Timeout".to_string()),
+                    error_code: Some("timeout"),
+                }
+            }
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn capabilities_and_publish(&self, to_pubkey: PublicKey, req_id: &str) -> Result<()> {
+        let response = CapabilitiesResponse {
+            req: req_id.to_string(),
+            capabilities: Capabilities::new(self.electrs_client.network(), self.kinds),
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn tx_details_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        txid: String,
+    ) -> Result<()> {
+        let result = timeout(
+            self.timeouts.tx_details,
+            self.electrs_client.get_transaction_details(&txid),
+        )
+        .await;
+
+        let response = match result {
+            Ok(Ok(details)) => {
+                info!(
+                    req = %req_id,
+                    txid = %details.txid,
+                    fee = ?details.fee,
+                    vsize = details.vsize,
+                    "Tx details OK"
+                );
+                GetTxDetailsResponse {
+                    req: req_id.to_string(),
+                    txid: details.txid,
+                    fee: details.fee,
+                    vsize: details.vsize,
+                    weight: details.weight,
+                    error: None,
+                    error_code: None,
+                }
+            }
+            Ok(Err(e)) => {
+                warn!(req = %req_id, err = %e, "Tx details failed");
+                GetTxDetailsResponse {
+                    req: req_id.to_string(),
+                    txid,
+                    fee: None,
+                    vsize: 0,
+                    weight: 0,
+                    error: Some(format!("{}", e)),
+                    error_code: Some(error_code(&e)),
+                }
+            }
+            Err(_) => {
+                warn!(req = %req_id, "Tx details timeout");
+                GetTxDetailsResponse {
+                    req: req_id.to_string(),
+                    txid,
+                    fee: None,
+                    vsize: 0,
+                    weight: 0,
+                    error: Some("Timeout".to_string()),
+                    error_code: Some("timeout"),
+                }
+            }
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn scripthash_lookup_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        scripthash: String,
+        limit: usize,
+    ) -> Result<()> {
+        // 32 bytes as hex; lowercase is what Electrum servers index
+        let scripthash = scripthash.trim().to_ascii_lowercase();
+        if scripthash.len() != 64 || !scripthash.chars().all(|c| c.is_ascii_hexdigit()) {
+            warn!(req = %req_id, scripthash = %scripthash, "Lookup rejected: invalid scripthash");
+            let response = ScripthashLookupResponse::failed(
+                req_id,
+                scripthash,
+                "Scripthash must be 32 bytes of hex".to_string(),
+                "invalid_scripthash",
+            );
+            return self.publish_response(to_pubkey, req_id, &response).await;
+        }
 
-        let txids = match timeout(
-            Duration::from_secs(20),
-            self.electrs_client.get_address_txs(&address),
+        let result = timeout(
+            self.timeouts.txs,
+            self.electrs_client.get_scripthash_summary(&scripthash),
         )
         .await
-        {
-            Ok(Ok(v)) => v,
-            _ => vec![],
+        .map_err(|_| ErrorCode::Timeout.error("Electrs scripthash timeout"))
+        .and_then(|r| r);
+
+        let summary = match result {
+            Ok(summary) => summary,
+            Err(e) => {
+                warn!(req = %req_id, err = %e, "Scripthash lookup failed");
+                let response =
+                    ScripthashLookupResponse::failed(req_id, scripthash, e.to_string(), error_code(&e));
+                return self.publish_response(to_pubkey, req_id, &response).await;
+            }
+        };
+
+        // Block hashes and times for the confirmed txs that will be listed
+        let mut heights: Vec<u32> = summary.history.iter().filter_map(|h| h.height).collect();
+        heights.sort_unstable();
+        heights.dedup();
+        let heights: Vec<u32> = heights.into_iter().rev().take(limit).collect();
+        let blocks = if heights.is_empty() {
+            HashMap::new()
+        } else {
+            match timeout(self.timeouts.txs, self.electrs_client.get_block_infos(&heights)).await {
+                Ok(Ok(v)) => v,
+                Ok(Err(e)) => {
+                    warn!(req = %req_id, err = %e, "Block infos failed");
+                    HashMap::new()
+                }
+                Err(_) => {
+                    warn!(req = %req_id, "Block infos timeout");
+                    HashMap::new()
+                }
+            }
         };
 
+        let unconfirmed_txids: Vec<String> = summary
+            .history
+            .iter()
+            .filter(|h| h.height.is_none())
+            .map(|h| h.txid.clone())
+            .collect();
+
+        let transactions: Vec<TransactionInfo> = summary
+            .history
+            .into_iter()
+            .map(|entry| {
+                let block = entry.height.and_then(|h| blocks.get(&h));
+                TransactionInfo {
+                    txid: entry.txid,
+                    amount: None,
+                    block_height: entry.height,
+                    block_hash: block.map(|b| b.hash.clone()),
+                    block_time: block.map(|b| b.time),
+                }
+            })
+            .collect();
+
+        let total_count = transactions.len();
+
         info!(
-            "Lookup OK: req={} confirmed={} unconfirmed={} txs={}",
-            req_id,
-            confirmed,
-            unconfirmed,
-            txids.len()
+            req = %req_id,
+            confirmed = summary.confirmed,
+            unconfirmed = summary.unconfirmed,
+            txs = total_count,
+            "Scripthash lookup OK"
         );
 
-        let response = BitcoinLookupResponse {
+        let response = ScripthashLookupResponse {
             req: req_id.to_string(),
-            confirmedBalance: confirmed,
-            unconfirmedBalance: unconfirmed,
-            confirmations: txids.len() as u64,
-            amount: confirmed + unconfirmed,
+            scripthash,
+            confirmed_balance: summary.confirmed,
+            unconfirmed_balance: summary.unconfirmed,
+            transactions: newest_first(transactions, limit),
+            total_count,
+            unconfirmed_txids,
+            seen: total_count > 0,
+            error: None,
+            error_code: None,
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn tx_status_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        txid: String,
+    ) -> Result<()> {
+        let result = timeout(
+            self.timeouts.tx_details,
+            self.electrs_client.get_tx_status(&txid),
+        )
+        .await
+        .map_err(|_| ErrorCode::Timeout.error("Electrs tx status timeout"))
+        .and_then(|r| r);
+
+        let response = match result {
+            Ok(status) => {
+                info!(
+                    req = %req_id,
+                    txid = %status.txid,
+                    confirmations = status.confirmations,
+                    "Tx status OK"
+                );
+                GetTxStatusResponse {
+                    req: req_id.to_string(),
+                    txid: status.txid,
+                    confirmed: status.confirmed,
+                    block_height: status.block_height,
+                    confirmations: status.confirmations,
+                    error: None,
+                    error_code: None,
+                }
+            }
+            Err(e) => {
+                warn!(req = %req_id, err = %e, "Tx status failed");
+                GetTxStatusResponse {
+                    req: req_id.to_string(),
+                    txid,
+                    confirmed: false,
+                    block_height: None,
+                    confirmations: 0,
+                    error: Some(e.to_string()),
+                    error_code: Some(error_code(&e)),
+                }
+            }
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn tx_ancestry_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        txid: String,
+    ) -> Result<()> {
+        let result = timeout(
+            self.timeouts.tx_details,
+            self.electrs_client.get_tx_ancestry(&txid),
+        )
+        .await
+        .map_err(|_| ErrorCode::Timeout.error("Electrs tx ancestry timeout"))
+        .and_then(|r| r);
+
+        let response = match result {
+            Ok(ancestry) => {
+                info!(
+                    req = %req_id,
+                    txid = %ancestry.txid,
+                    confirmed = ancestry.confirmed,
+                    count = ?ancestry.ancestor_count,
+                    "Tx ancestry OK"
+                );
+                GetTxAncestryResponse {
+                    req: req_id.to_string(),
+                    txid: ancestry.txid,
+                    confirmed: ancestry.confirmed,
+                    ancestor_count: ancestry.ancestor_count,
+                    ancestor_fees: ancestry.ancestor_fees,
+                    ancestor_vsize: ancestry.ancestor_vsize,
+                    error: None,
+                    error_code: None,
+                }
+            }
+            Err(e) => {
+                warn!(req = %req_id, err = %e, "Tx ancestry failed");
+                GetTxAncestryResponse {
+                    req: req_id.to_string(),
+                    txid,
+                    confirmed: false,
+                    ancestor_count: None,
+                    ancestor_fees: None,
+                    ancestor_vsize: None,
+                    error: Some(e.to_string()),
+                    error_code: Some(error_code(&e)),
+                }
+            }
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn scan_xpub_and_publish(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        xpub_str: String,
+        gap_limit: u32,
+        force_rescan: bool,
+    ) -> Result<()> {
+        // Deriving the first address validates the key and its network
+        let network = self.electrs_client.network();
+        if let Err(e) = crate::xpub::derive_chain_addresses(&xpub_str, network, 0, 0, 1) {
+            warn!(req = %req_id, err = %e, "Invalid xpub for scan");
+            let response = ScanXpubResponse::failed(req_id, e.to_string(), "invalid_xpub");
+            self.publish_response(to_pubkey, req_id, &response).await?;
+            return Ok(());
+        }
+
+        // Taken before scanning so a block found meanwhile invalidates the result
+        let tip = match self.electrs_client.tip_height().await {
+            Ok(tip) => Some(tip),
+            Err(e) => {
+                warn!(req = %req_id, err = %e, "Tip height unavailable; not caching scan");
+                None
+            }
+        };
+        let cache_key = ScanCache::key(&xpub_str, gap_limit);
+
+        if let Some(tip) = tip.filter(|_| !force_rescan) {
+            if let Some(mut response) = self.scan_cache.get(&cache_key, tip) {
+                info!(req = %req_id, tip, "Xpub scan served from cache");
+                response.req = req_id.to_string();
+                self.publish_response(to_pubkey, req_id, &response).await?;
+                return Ok(());
+            }
+        }
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<(String, bool)>();
+
+        // Discovery reports each scanned address; the consumer below sums
+        // balances as used addresses appear and publishes progress.
+        let discovery = async move {
+            let report = |address: &str, used: bool| {
+                let _ = tx.send((address.to_string(), used));
+            };
+
+            let receive = timeout(
+                self.timeouts.xpub_scan,
+                crate::xpub::discover_chain_with_progress(
+                    &self.electrs_client,
+                    &xpub_str,
+                    0,
+                    0,
+                    gap_limit,
+                    report,
+                ),
+            )
+            .await
+            .map_err(|_| ErrorCode::Timeout.error("Xpub scan timeout"))??;
+
+            let change = timeout(
+                self.timeouts.xpub_scan,
+                crate::xpub::discover_chain_with_progress(
+                    &self.electrs_client,
+                    &xpub_str,
+                    1,
+                    0,
+                    gap_limit,
+                    report,
+                ),
+            )
+            .await
+            .map_err(|_| ErrorCode::Timeout.error("Xpub scan timeout"))??;
+
+            Ok::<_, anyhow::Error>((receive, change))
+        };
+
+        let progress = async {
+            let mut scanned: u32 = 0;
+            let mut confirmed: u64 = 0;
+            let mut unconfirmed: i64 = 0;
+            let mut used_addresses = Vec::new();
+
+            while let Some((address, used)) = rx.recv().await {
+                scanned += 1;
+
+                if used {
+                    let (c, u) = timeout(
+                        self.timeouts.balance,
+                        self.electrs_client.get_address_balance(&address),
+                    )
+                    .await
+                    .map_err(|_| ErrorCode::Timeout.error("Electrs balance timeout"))??;
+
+                    confirmed = confirmed.saturating_add(c);
+                    unconfirmed = unconfirmed.saturating_add(u);
+                    used_addresses.push(address);
+                }
+
+                if scanned.is_multiple_of(SCAN_PROGRESS_INTERVAL) {
+                    let update = ScanProgressResponse {
+                        req: req_id.to_string(),
+                        response_type: "scan_progress",
+                        scanned,
+                        used: used_addresses.len(),
+                        confirmed_balance: confirmed,
+                        unconfirmed_balance: unconfirmed,
+                    };
+
+                    if let Err(e) = self.publish_response(to_pubkey, req_id, &update).await {
+                        warn!(req = %req_id, err = %e, "Failed to publish scan progress");
+                    }
+                }
+            }
 
-            confirmed_balance: confirmed,
-            unconfirmed_balance: unconfirmed,
-            transactions: txids
-                .into_iter()
-                .map(|txid| TransactionInfo { txid })
-                .collect(),
+            Ok::<_, anyhow::Error>((confirmed, unconfirmed, used_addresses))
         };
 
-        let json = serde_json::to_string(&response)?;
+        // Either side failing ends the scan; progress listeners always get
+        // a final scan_complete, with ok: false on failure
+        let response = match tokio::try_join!(discovery, progress) {
+            Ok(((receive, change), (confirmed, unconfirmed, used_addresses))) => {
+                info!(
+                    req = %req_id,
+                    used = used_addresses.len(),
+                    confirmed,
+                    unconfirmed,
+                    "Xpub scan OK"
+                );
 
-        let tags = vec![
-            Tag::parse(["p", to_pubkey.to_hex().as_str()])?,
-            Tag::parse(["req", req_id])?,
-        ];
+                let response = ScanXpubResponse {
+                    req: req_id.to_string(),
+                    response_type: "scan_complete",
+                    ok: true,
+                    cached: false,
+                    confirmed_balance: confirmed,
+                    unconfirmed_balance: unconfirmed,
+                    first_unused_index: receive.next_index,
+                    first_unused_change_index: change.next_index,
+                    used_addresses,
+                    error: None,
+                    error_code: None,
+                };
 
-        let event = EventBuilder::new(
-            Kind::Custom(NOMAD_SERVER_RESPONSE_KIND),
-            json,
-        )
-        .tags(tags)
-        .sign_with_keys(&self.keys)?;
+                if let Some(tip) = tip {
+                    self.scan_cache.insert(cache_key, tip, ScanXpubResponse { cached: true, ..response.clone() });
+                }
 
-        info!(
-            "Publishing response: kind={} to={} req={}",
-            NOMAD_SERVER_RESPONSE_KIND,
-            to_pubkey.to_hex(),
-            req_id
-        );
+                response
+            }
+            Err(e) => {
+                warn!(req = %req_id, err = %e, "Xpub scan failed");
+                ScanXpubResponse::failed(req_id, e.to_string(), error_code(&e))
+            }
+        };
 
-        self.client.send_event(&event).await?;
+        self.publish_response(to_pubkey, req_id, &response).await?;
 
         Ok(())
     }
 
-    async fn broadcast_and_publish(
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn register_xpub_and_publish(
         &self,
         to_pubkey: PublicKey,
         req_id: &str,
-        tx_hex: String,
+        xpub_str: String,
+        gap_limit: u32,
     ) -> Result<()> {
-        info!("Broadcasting transaction: req={}", req_id);
-
-        let electrs = self.electrs_client.clone();
-        let hex = tx_hex.to_string();
+        // Deriving the first address validates the key and its network
+        let network = self.electrs_client.network();
+        if let Err(e) = crate::xpub::derive_chain_addresses(&xpub_str, network, 0, 0, 1) {
+            warn!(req = %req_id, err = %e, "Xpub registration rejected");
+            return self
+                .publish_error(to_pubkey, req_id, "invalid_xpub", Some(e.to_string()))
+                .await;
+        }
 
-        let result = timeout(
-            Duration::from_secs(30),
-            electrs.broadcast_transaction(&hex),
-        )
-        .await;
+        let keys = &self.reply_identity(to_pubkey, req_id).keys;
+        let registered = WatchedXpub::new(keys, &xpub_str, gap_limit)
+            .and_then(|state| self.watch_only.save(&to_pubkey, &state));
 
-        let response = match result {
-            Ok(Ok(txid)) => {
-                info!("Broadcast OK: req={} txid={}", req_id, txid);
-                BroadcastTxResponse {
+        let response = match registered {
+            Ok(()) => {
+                info!(req = %req_id, gap_limit, "Xpub registered");
+                RegisterXpubResponse {
                     req: req_id.to_string(),
-                    success: true,
-                    txid: Some(txid),
+                    registered: true,
+                    gap_limit,
                     error: None,
+                    error_code: None,
                 }
             }
+            Err(e) => {
+                warn!(req = %req_id, err = %e, "Xpub registration failed");
+                RegisterXpubResponse {
+                    req: req_id.to_string(),
+                    registered: false,
+                    gap_limit,
+                    error: Some(e.to_string()),
+                    error_code: Some("internal_error"),
+                }
+            }
+        };
+
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(req = %req_id, from = %to_pubkey.to_hex())
+    )]
+    async fn sync_xpub_and_publish(&self, to_pubkey: PublicKey, req_id: &str) -> Result<()> {
+        let state = match self.watch_only.load(&to_pubkey) {
+            Ok(Some(state)) => state,
+            Ok(None) => {
+                let detail = "No xpub registered; send register_xpub first".to_string();
+                return self.publish_error(to_pubkey, req_id, "not_registered", Some(detail)).await;
+            }
+            Err(e) => {
+                warn!(req = %req_id, err = %e, "Failed to load watch-only state");
+                return self
+                    .publish_error(to_pubkey, req_id, "internal_error", Some(e.to_string()))
+                    .await;
+            }
+        };
+
+        // Fails if the server key changed (e.g. NOMAD_NSEC) since registering
+        let xpub_str = match state.xpub(&self.reply_identity(to_pubkey, req_id).keys) {
+            Ok(xpub) => xpub,
+            Err(e) => {
+                warn!(req = %req_id, err = %e, "Registered xpub unreadable");
+                let detail = format!("{}; register the xpub again", e);
+                return self.publish_error(to_pubkey, req_id, "not_registered", Some(detail)).await;
+            }
+        };
+
+        let sync = self.sync_xpub(to_pubkey, req_id, &xpub_str, state);
+        let response = match timeout(self.timeouts.xpub_scan, sync).await {
+            Ok(Ok(response)) => response,
             Ok(Err(e)) => {
-                warn!("Broadcast failed: req={} err={}", req_id, e);
-                BroadcastTxResponse {
+                warn!(req = %req_id, err = %e, "Xpub sync failed");
+                SyncXpubResponse {
                     req: req_id.to_string(),
-                    success: false,
-                    txid: None,
-                    error: Some(format!("{}", e)),
+                    error: Some(e.to_string()),
+                    error_code: Some(error_code(&e)),
+                    ..Default::default()
                 }
             }
             Err(_) => {
-                warn!("Broadcast timeout: req={}", req_id);
-                BroadcastTxResponse {
+                warn!(req = %req_id, "Xpub sync timeout");
+                SyncXpubResponse {
                     req: req_id.to_string(),
-                    success: false,
-                    txid: None,
-                    error: Some("Timeout".to_string()),
+                    error: Some("Xpub sync timed out".to_string()),
+                    error_code: Some("timeout"),
+                    ..Default::default()
                 }
             }
         };
 
-        let json = serde_json::to_string(&response)?;
-
-        let tags = vec![
-            Tag::parse(["p", to_pubkey.to_hex().as_str()])?,
-            Tag::parse(["req", req_id])?,
-        ];
-
-        let event = EventBuilder::new(
-            Kind::Custom(NOMAD_SERVER_RESPONSE_KIND),
-            json,
-        )
-        .tags(tags)
-        .sign_with_keys(&self.keys)?;
-
-        self.client.send_event(&event).await?;
+        self.publish_response(to_pubkey, req_id, &response).await?;
 
         Ok(())
     }
 
-    async fn fees_and_publish(
+    /// Bring a registered xpub up to date and report what changed
+    ///
+    /// Known used addresses are checked for new txids; discovery then only
+    /// continues each chain from its first unused index instead of
+    /// rescanning from 0. The updated state is saved before returning.
+    async fn sync_xpub(
         &self,
         to_pubkey: PublicKey,
         req_id: &str,
-    ) -> Result<()> {
-        info!("Estimating fees: req={}", req_id);
+        xpub_str: &str,
+        mut state: WatchedXpub,
+    ) -> Result<SyncXpubResponse> {
+        let mut new_txids: Vec<String> = Vec::new();
 
-        let electrs = self.electrs_client.clone();
+        for used in state.used.iter_mut() {
+            for txid in self.electrs_client.get_address_txs(&used.address).await? {
+                if !used.txids.contains(&txid) {
+                    used.txids.push(txid.clone());
+                    new_txids.push(txid);
+                }
+            }
+        }
 
-        let result = timeout(
-            Duration::from_secs(30),
-            electrs.estimate_fees(),
+        let receive = crate::xpub::discover_chain(
+            &self.electrs_client,
+            xpub_str,
+            0,
+            state.receive_next,
+            state.gap_limit,
         )
-        .await;
+        .await?;
+        let change = crate::xpub::discover_chain(
+            &self.electrs_client,
+            xpub_str,
+            1,
+            state.change_next,
+            state.gap_limit,
+        )
+        .await?;
 
-        let (fast, medium, slow) = match result {
-            Ok(Ok((f, m, s))) => {
-                info!("Fees OK: req={} fast={} medium={} slow={}", req_id, f, m, s);
-                (f, m, s)
-            }
-            _ => {
-                warn!("Fee estimation failed or timed out: req={}, using defaults", req_id);
-                // Return reasonable defaults if Electrs fails
-                (10, 5, 1)
-            }
-        };
+        state.receive_next = receive.next_index;
+        state.change_next = change.next_index;
 
-        let response = GetFeesResponse {
-            req: req_id.to_string(),
-            fast,
-            medium,
-            slow,
-        };
+        let mut new_used_addresses = Vec::new();
+        for address in receive.used_addresses.into_iter().chain(change.used_addresses) {
+            let txids = self.electrs_client.get_address_txs(&address).await?;
+            new_txids.extend(txids.iter().cloned());
+            state.used.push(UsedAddress { address: address.clone(), txids });
+            new_used_addresses.push(address);
+        }
 
-        let json = serde_json::to_string(&response)?;
+        // A tx touching several addresses is reported once
+        let mut seen = HashSet::new();
+        new_txids.retain(|t| seen.insert(t.clone()));
 
-        let tags = vec![
-            Tag::parse(["p", to_pubkey.to_hex().as_str()])?,
-            Tag::parse(["req", req_id])?,
-        ];
+        let mut confirmed: u64 = 0;
+        let mut unconfirmed: i64 = 0;
+        for used in &state.used {
+            let (c, u) = timeout(
+                self.timeouts.balance,
+                self.electrs_client.get_address_balance(&used.address),
+            )
+            .await
+            .map_err(|_| ErrorCode::Timeout.error("Electrs balance timeout"))??;
 
-        let event = EventBuilder::new(
-            Kind::Custom(NOMAD_SERVER_RESPONSE_KIND),
-            json,
-        )
-        .tags(tags)
-        .sign_with_keys(&self.keys)?;
+            confirmed = confirmed.saturating_add(c);
+            unconfirmed = unconfirmed.saturating_add(u);
+        }
 
-        self.client.send_event(&event).await?;
+        self.watch_only.save(&to_pubkey, &state)?;
 
-        Ok(())
+        info!(
+            req = %req_id,
+            new_used = new_used_addresses.len(),
+            new_txs = new_txids.len(),
+            used = state.used.len(),
+            "Xpub sync OK"
+        );
+
+        Ok(SyncXpubResponse {
+            req: req_id.to_string(),
+            new_used_addresses,
+            new_txids,
+            confirmed_balance: confirmed,
+            unconfirmed_balance: unconfirmed,
+            used_count: state.used.len(),
+            first_unused_index: state.receive_next,
+            first_unused_change_index: state.change_next,
+            error: None,
+            error_code: None,
+        })
     }
 
-    async fn utxos_and_publish(
+    /// Sign and send `response` to `to_pubkey`, tagged with `req_id`
+    ///
+    /// With NOMAD_RESPONSE_SEQ on, `ts` and (for paired devices) the next
+    /// `seq` are added so the wallet can spot dropped or reordered responses.
+    /// Large responses are compressed and chunked (see `Publisher::sign`).
+    async fn publish_response<T: Serialize>(
         &self,
         to_pubkey: PublicKey,
         req_id: &str,
-        addresses: Vec<String>,
+        response: &T,
     ) -> Result<()> {
-        info!("Fetching UTXOs: req={} addresses={}", req_id, addresses.len());
+        let identity = self.reply_identity(to_pubkey, req_id);
+        let mut value = serde_json::to_value(response)?;
 
-        let electrs = self.electrs_client.clone();
-        let addrs = addresses.to_vec();
+        if self.response_seq {
+            if let Some(obj) = value.as_object_mut() {
+                match identity.pairing.next_seq(&to_pubkey) {
+                    Ok(Some(seq)) => {
+                        obj.insert("seq".to_string(), seq.into());
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!(req = %req_id, err = %e, "Failed to advance response seq"),
+                }
+                obj.insert("ts".to_string(), Timestamp::now().as_secs().into());
+            }
+        }
 
-        // Respect the single-flight gate pattern
-        let result = timeout(
-            Duration::from_secs(45),
-            electrs.get_utxos(&addrs),
-        )
-        .await;
+        let json = serde_json::to_string(&value)?;
+        let events = self.publisher.sign(&identity.keys, to_pubkey, Some(req_id), &json)?;
 
-        let utxos = match result {
-            Ok(Ok(v)) => {
-                info!("UTXOs OK: req={} count={}", req_id, v.len());
-                v
-            }
-            Ok(Err(e)) => {
-                warn!("UTXO fetch error: req={} err={}", req_id, e);
-                vec![]
-            }
-            Err(_) => {
-                warn!("UTXO fetch timeout: req={}", req_id);
-                vec![]
-            }
-        };
+        info!(
+            kind = self.kinds.response,
+            to = %to_pubkey.to_hex(),
+            req = %req_id,
+            chunks = events.len(),
+            "Publishing response"
+        );
 
-        let response = GetUtxosResponse {
+        if let Some(dump) = &self.debug_dump {
+            dump.response(to_pubkey, req_id, &value, events.len());
+        }
+
+        for event in &events {
+            self.publisher.send_with_strategy(event, req_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// The identity answering `req_id` from `to_pubkey` (see `ReplyAs`)
+    fn reply_identity(&self, to_pubkey: PublicKey, req_id: &str) -> &Identity {
+        let replying = self
+            .reply_as
+            .lock()
+            .unwrap()
+            .get(&(to_pubkey, req_id.to_string()))
+            .copied();
+
+        replying
+            .and_then(|pk| self.identities.get(&pk))
+            .unwrap_or_else(|| self.identities.primary())
+    }
+
+    /// Reply to a request with `{ "req": ..., "error": ..., "detail": ... }`
+    async fn publish_error(
+        &self,
+        to_pubkey: PublicKey,
+        req_id: &str,
+        error: &'static str,
+        detail: Option<String>,
+    ) -> Result<()> {
+        let response = ErrorResponse {
             req: req_id.to_string(),
-            utxos,
+            error: error.to_string(),
+            error_code: error,
+            detail,
         };
 
-        let json = serde_json::to_string(&response)?;
+        self.publish_response(to_pubkey, req_id, &response).await?;
+
+        Ok(())
+    }
 
-        let tags = vec![
-            Tag::parse(["p", to_pubkey.to_hex().as_str()])?,
-            Tag::parse(["req", req_id])?,
-        ];
+    /// Tell the wallet its request could not be parsed so it can fail fast
+    async fn reject_invalid(&self, to_pubkey: PublicKey, req_id: &str, detail: String) {
+        if let Err(e) = self
+            .publish_error(to_pubkey, req_id, "invalid_request", Some(detail))
+            .await
+        {
+            error!(req = %req_id, err = %e, "Failed to publish invalid_request response");
+        }
+    }
 
-        let event = EventBuilder::new(
-            Kind::Custom(NOMAD_SERVER_RESPONSE_KIND),
-            json,
-        )
-        .tags(tags)
-        .sign_with_keys(&self.keys)?;
+    /// Refuse an address list over NOMAD_MAX_REQUEST_ADDRESSES before it
+    /// reaches Electrs
+    async fn reject_too_many_addresses(&self, to_pubkey: PublicKey, req_id: &str, count: usize) {
+        warn!(req = %req_id, count, max = self.max_request_addresses, "Too many addresses");
 
-        self.client.send_event(&event).await?;
+        let detail = format!(
+            "{} addresses requested, at most {} allowed",
+            count, self.max_request_addresses
+        );
 
-        Ok(())
+        if let Err(e) = self
+            .publish_error(to_pubkey, req_id, "too_many_addresses", Some(detail))
+            .await
+        {
+            error!(req = %req_id, err = %e, "Failed to publish too_many_addresses response");
+        }
     }
 }
 
 /* -------------------- Helpers -------------------- */
 
+/// Machine-readable code for a failed Electrs-backed request (see ErrorCode)
+fn error_code(e: &anyhow::Error) -> &'static str {
+    ErrorCode::of(e).as_str()
+}
+
+/// The newest `limit` of `txs`: unconfirmed first, then by height, descending
+///
+/// The sort is stable, so txs within a block (or the mempool) keep their
+/// reverse history order.
+fn newest_first(mut txs: Vec<TransactionInfo>, limit: usize) -> Vec<TransactionInfo> {
+    txs.reverse();
+    txs.sort_by_key(|t| std::cmp::Reverse(t.block_height.unwrap_or(u32::MAX)));
+    txs.truncate(limit);
+    txs
+}
+
+/// Smallest chunk publish_response shrinks to before giving up
+const MIN_CHUNK_SIZE: usize = 1024;
+
+/// Gzip `data` and base64-encode the result
+fn gzip_base64(data: &[u8]) -> Result<String> {
+    use bitcoin::base64::engine::general_purpose::STANDARD as BASE64;
+    use bitcoin::base64::Engine;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+
+    Ok(BASE64.encode(encoder.finish()?))
+}
+
+/// Split `s` into pieces of at most `max` bytes, on char boundaries
+fn split_chunks(s: &str, max: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = s;
+
+    while rest.len() > max {
+        let mut end = max;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+
+    chunks.push(rest);
+    chunks
+}
+
+/// Txid of a raw transaction hex, None if it doesn't decode
+fn txid_from_hex(tx_hex: &str) -> Option<String> {
+    let raw = hex::decode(tx_hex.trim()).ok()?;
+    let tx: bitcoin::Transaction = bitcoin::consensus::encode::deserialize(&raw).ok()?;
+
+    Some(tx.compute_txid().to_string())
+}
+
 fn extract_req_id(event: &Event) -> Option<String> {
     for t in event.tags.iter() {
         let v = t.clone().to_vec();
@@ -533,3 +3849,471 @@ fn extract_req_id(event: &Event) -> Option<String> {
     }
     None
 }
+
+/// Known bitcoind rejection messages -> (reject_code, human-readable reason)
+///
+/// Matched as substrings of the raw error, first match wins.
+const REJECT_REASONS: &[(&str, &str, &str)] = &[
+    ("txn-mempool-conflict", "mempool_conflict", "Double spend detected: an input is already spent by a transaction in the mempool"),
+    ("bad-txns-inputs-missingorspent", "missing_inputs", "Inputs are missing or already spent"),
+    ("missing-inputs", "missing_inputs", "Inputs are missing or already spent"),
+    ("insufficient fee", "insufficient_fee", "Fee too low to replace the conflicting transaction"),
+    ("mempool min fee not met", "insufficient_fee", "Fee rate is below the mempool minimum"),
+    ("min relay fee not met", "insufficient_fee", "Fee rate is below the minimum relay fee"),
+    ("txn-already-in-mempool", "already_in_mempool", "Transaction is already in the mempool"),
+    ("txn-already-known", "already_in_mempool", "Transaction is already in the mempool"),
+    ("bad-txns-in-belowout", "outputs_exceed_inputs", "Outputs spend more than the inputs provide"),
+    ("dust", "dust", "An output is below the dust limit"),
+];
+
+/// Classify a broadcast error into a structured rejection, if recognised
+fn parse_reject_reason(raw: &str) -> Option<(&'static str, &'static str)> {
+    let lower = raw.to_lowercase();
+
+    REJECT_REASONS
+        .iter()
+        .find(|(pattern, _, _)| lower.contains(pattern))
+        .map(|(_, code, reason)| (*code, *reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh directory under the system temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nomad-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn request_at(created_at: u64) -> Event {
+        EventBuilder::new(Kind::Custom(30078), "{}")
+            .custom_created_at(Timestamp::from(created_at))
+            .sign_with_keys(&Keys::generate())
+            .unwrap()
+    }
+
+    fn broadcast_failure(error: &str) -> BroadcastTxResponse {
+        let reject = parse_reject_reason(error);
+        BroadcastTxResponse {
+            req: "r1".to_string(),
+            success: false,
+            txid: None,
+            error: Some(error.to_string()),
+            via: None,
+            reject_code: reject.map(|(code, _)| code.to_string()),
+            reject_reason: reject.map(|(_, reason)| reason.to_string()),
+            error_code: None,
+        }
+    }
+
+    /// Handler on `electrs` with no relays, serving a fresh identity
+    async fn handler(electrs: ElectrsClient, name: &str) -> NostrHandler {
+        let keys = Keys::generate();
+        let state = NostrState::new(keys.clone(), vec![]).await.unwrap();
+        let pairing = crate::pairing::PairingManager::new(temp_dir(name)).unwrap();
+
+        NostrHandler::new(
+            state,
+            Identities::single(keys, pairing),
+            Arc::new(electrs),
+            EventKinds { request: 30078, response: 30079 },
+            Arc::new(SubscriptionManager::new(Duration::from_secs(60))),
+            TimeoutConfig::from_env(),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn publisher(compress_threshold: usize, chunk_size: usize, max_event_bytes: usize) -> Publisher {
+        Publisher {
+            client: Arc::new(Client::default()),
+            config: PublishConfig {
+                timeout: Duration::from_secs(1),
+                strategy: PublishStrategy::All,
+            },
+            kind: 30079,
+            compress_threshold,
+            chunk_size,
+            max_event_bytes,
+        }
+    }
+
+    fn tag_value<'a>(event: &'a Event, name: &str) -> Option<&'a str> {
+        event
+            .tags
+            .iter()
+            .map(|t| t.as_slice())
+            .find(|t| t.len() > 1 && t[0] == name)
+            .map(|t| t[1].as_str())
+    }
+
+    /// Join chunked events the way the wallet does and undo compression
+    fn reassemble(events: &[Event]) -> String {
+        use bitcoin::base64::engine::general_purpose::STANDARD as BASE64;
+        use bitcoin::base64::Engine;
+        use std::io::Read;
+
+        let mut ordered: Vec<&Event> = events.iter().collect();
+        ordered.sort_by_key(|e| tag_value(e, "chunk_index").map(|i| i.parse::<usize>().unwrap()));
+        let content: String = ordered.iter().map(|e| e.content.as_str()).collect();
+
+        if tag_value(&events[0], "content_encoding") != Some("gzip") {
+            return content;
+        }
+
+        let wrapper: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let gz = BASE64.decode(wrapper["payload"].as_str().unwrap()).unwrap();
+        let mut json = String::new();
+        flate2::read::GzDecoder::new(&gz[..]).read_to_string(&mut json).unwrap();
+        json
+    }
+
+    /// JSON that gzip can't shrink much (random hex)
+    fn incompressible_json(keys: usize) -> String {
+        let keys: Vec<String> = (0..keys).map(|_| Keys::generate().public_key().to_hex()).collect();
+        serde_json::json!({ "type": "address_activity", "txids": keys }).to_string()
+    }
+
+    #[test]
+    fn broadcast_cache_keeps_only_final_outcomes() {
+        let cache = BroadcastCache::new();
+
+        cache.insert("a", &broadcast_failure("Broadcast error: Connection refused (os error 111)"));
+        cache.insert("b", &broadcast_failure("Timeout"));
+        cache.insert("c", &broadcast_failure("Broadcast error: min relay fee not met"));
+        cache.insert("d", &BroadcastTxResponse {
+            success: true,
+            txid: Some("d".to_string()),
+            error: None,
+            reject_code: None,
+            reject_reason: None,
+            ..broadcast_failure("")
+        });
+
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_none());
+        assert_eq!(cache.get("c").unwrap().reject_code.as_deref(), Some("insufficient_fee"));
+        assert!(cache.get("d").unwrap().success);
+    }
+
+    #[test]
+    fn push_is_compressed_and_chunked_without_req() {
+        let publisher = publisher(1024, 2048, 4096);
+        let keys = Keys::generate();
+        let to = Keys::generate().public_key();
+        let json = incompressible_json(200);
+
+        let events = publisher.sign(&keys, to, None, &json).unwrap();
+
+        assert!(events.len() > 1);
+        for event in &events {
+            assert!(event.as_json().len() <= 4096);
+            assert_eq!(event.kind, Kind::Custom(30079));
+            assert_eq!(tag_value(event, "p"), Some(to.to_hex().as_str()));
+            assert_eq!(tag_value(event, "req"), None);
+            assert_eq!(tag_value(event, "content_encoding"), Some("gzip"));
+        }
+        assert_eq!(reassemble(&events), json);
+    }
+
+    #[test]
+    fn response_chunks_reassemble_in_index_order() {
+        let publisher = publisher(0, 2048, 64 * 1024);
+        let keys = Keys::generate();
+        let json = incompressible_json(200);
+
+        let mut events = publisher.sign(&keys, Keys::generate().public_key(), Some("r1"), &json).unwrap();
+
+        assert!(events.len() > 1);
+        for (index, event) in events.iter().enumerate() {
+            assert!(event.content.len() <= 2048);
+            assert_eq!(tag_value(event, "req"), Some("r1"));
+            assert_eq!(tag_value(event, "content_encoding"), None);
+            assert_eq!(tag_value(event, "chunk_index"), Some(index.to_string().as_str()));
+            assert_eq!(tag_value(event, "chunk_total"), Some(events.len().to_string().as_str()));
+        }
+
+        // Relays may deliver chunks in any order
+        events.reverse();
+        assert_eq!(reassemble(&events), json);
+    }
+
+    #[test]
+    fn large_response_is_gzipped_with_its_req() {
+        let publisher = publisher(1024, 64 * 1024, 64 * 1024);
+        let keys = Keys::generate();
+        let txids = vec!["ab".repeat(32); 500];
+        let json = serde_json::json!({ "req": "r1", "txids": txids }).to_string();
+
+        let events = publisher.sign(&keys, Keys::generate().public_key(), Some("r1"), &json).unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert!(events[0].content.len() < json.len() / 10);
+        assert_eq!(tag_value(&events[0], "chunk_index"), None);
+        assert_eq!(tag_value(&events[0], "content_encoding"), Some("gzip"));
+        let wrapper: serde_json::Value = serde_json::from_str(&events[0].content).unwrap();
+        assert_eq!(wrapper["req"], "r1");
+        assert_eq!(wrapper["content_encoding"], "gzip");
+        assert_eq!(reassemble(&events), json);
+
+        // Small responses go out as they are
+        let small = publisher.sign(&keys, Keys::generate().public_key(), Some("r2"), "{}").unwrap();
+        assert_eq!(small.len(), 1);
+        assert_eq!(small[0].content, "{}");
+        assert_eq!(tag_value(&small[0], "content_encoding"), None);
+    }
+
+    #[test]
+    fn chunks_shrink_until_events_fit() {
+        // 8 KiB chunks can't fit a 4 KiB event, so the loop has to shrink them
+        let publisher = publisher(0, 8 * 1024, 4096);
+        let keys = Keys::generate();
+        let json = incompressible_json(500);
+
+        let events = publisher.sign(&keys, Keys::generate().public_key(), Some("r1"), &json).unwrap();
+
+        assert!(events.len() > json.len() / 4096);
+        assert!(events.iter().all(|e| e.as_json().len() <= 4096));
+        assert_eq!(reassemble(&events), json);
+    }
+
+    #[test]
+    fn event_limit_below_the_smallest_chunk_is_an_error() {
+        let publisher = publisher(0, 2048, MIN_CHUNK_SIZE);
+        let json = incompressible_json(100);
+
+        let err = publisher
+            .sign(&Keys::generate(), Keys::generate().public_key(), Some("r1"), &json)
+            .unwrap_err();
+
+        assert!(err.to_string().contains("NOMAD_MAX_EVENT_BYTES"));
+    }
+
+    #[test]
+    fn split_chunks_keeps_chars_whole() {
+        assert_eq!(split_chunks("aé€😀x", 4), vec!["aé", "€", "😀", "x"]);
+        assert_eq!(split_chunks("abcd", 4), vec!["abcd"]);
+        assert_eq!(split_chunks("abcde", 2), vec!["ab", "cd", "e"]);
+        assert_eq!(split_chunks("", 4), vec![""]);
+    }
+
+    #[test]
+    fn rate_limiter_allows_a_burst_then_refills() {
+        // 3 tokens, refilled at 10 per second
+        let limiter = RateLimiter::new(RateLimitConfig {
+            max_requests: 3,
+            window: Duration::from_millis(300),
+        });
+
+        assert!((0..3).all(|_| limiter.check("a")));
+        assert!(!limiter.check("a"));
+        assert!(limiter.check("b"), "buckets are per requester");
+
+        // 1.5 tokens back: one request, then empty again
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+
+        // A long pause refills to capacity, not beyond
+        std::thread::sleep(Duration::from_millis(600));
+        assert!((0..3).all(|_| limiter.check("a")));
+        assert!(!limiter.check("a"));
+    }
+
+    #[tokio::test]
+    async fn negative_unconfirmed_balance_stays_signed() {
+        use crate::electrs::mock::{scripthash, MockElectrum, Reply};
+        use serde_json::json;
+
+        const SPENDING: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+        const RECEIVING: &str = "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g";
+        let spending = scripthash(SPENDING);
+
+        // SPENDING has 1000 confirmed with a pending spend of 300; RECEIVING
+        // has 100 incoming
+        let mock = MockElectrum::start(move |method, params| {
+            let ours = params[0] == spending.as_str();
+            match method {
+                "blockchain.scripthash.get_history" => Reply::Result(json!([{
+                    "tx_hash": "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+                    "height": if ours { 800_000 } else { 0 },
+                }])),
+                "blockchain.scripthash.get_balance" if ours => {
+                    Reply::Result(json!({ "confirmed": 1_000, "unconfirmed": -300 }))
+                }
+                "blockchain.scripthash.get_balance" => {
+                    Reply::Result(json!({ "confirmed": 0, "unconfirmed": 100 }))
+                }
+                _ => Reply::Error("unsupported"),
+            }
+        });
+        let handler = handler(mock.client().unwrap(), "negative-unconfirmed").await;
+        let labels = HashMap::new();
+        let lookup = |addresses: &[&str]| {
+            let addresses = addresses.iter().map(|a| a.to_string()).collect();
+            handler.lookup_addresses("r1", addresses, &labels, true, 50)
+        };
+
+        let both = serde_json::to_value(lookup(&[SPENDING, RECEIVING]).await.unwrap()).unwrap();
+        assert_eq!(both["confirmed_balance"], 1_000);
+        assert_eq!(both["unconfirmed_balance"], -200);
+        assert_eq!(both["addresses"][0]["unconfirmed_balance"], -300);
+        assert_eq!(both["addresses"][1]["unconfirmed_balance"], 100);
+        // The unsigned MVP fields clamp at zero and net the pending spend
+        assert_eq!(both["confirmedBalance"], 1_000);
+        assert_eq!(both["unconfirmedBalance"], 0);
+        assert_eq!(both["amount"], 800);
+
+        let receiving = serde_json::to_value(lookup(&[RECEIVING]).await.unwrap()).unwrap();
+        assert_eq!(receiving["unconfirmed_balance"], 100);
+        assert_eq!(receiving["unconfirmedBalance"], 100);
+        assert_eq!(receiving["amount"], 100);
+    }
+
+    #[tokio::test]
+    async fn redelivered_event_is_answered_once() {
+        use crate::electrs::mock::{MockElectrum, Reply};
+        use serde_json::json;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let mock = MockElectrum::start(move |method, _| match method {
+            "mempool.get_fee_histogram" => {
+                counted.fetch_add(1, Ordering::SeqCst);
+                Reply::Result(json!([[10.0, 1_000]]))
+            }
+            _ => Reply::Error("unsupported"),
+        });
+        let handler = handler(mock.client().unwrap(), "redelivered").await;
+        let device = Keys::generate();
+        let event = EventBuilder::new(Kind::Custom(30078), r#"{"type":"get_mempool_info"}"#)
+            .tags([Tag::parse(["req", "r1"]).unwrap()])
+            .sign_with_keys(&device)
+            .unwrap();
+        let permits = Semaphore::new(4);
+        let mut recent = RecentEvents::new();
+
+        // The second delivery arrives after the first was answered, so only
+        // the event id can tell it apart from a new request
+        for _ in 0..2 {
+            if accept_delivery(&mut recent, &event) {
+                handler.handle_request(Box::new(event.clone()), &permits).await;
+            }
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A retry is a new event with the same req, and is answered again
+        let retry = EventBuilder::new(Kind::Custom(30078), r#"{"type":"get_mempool_info"}"#)
+            .tags([Tag::parse(["req", "r1"]).unwrap()])
+            .custom_created_at(Timestamp::from(event.created_at.as_secs() + 1))
+            .sign_with_keys(&device)
+            .unwrap();
+        assert!(accept_delivery(&mut recent, &retry));
+        handler.handle_request(Box::new(retry), &permits).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn scan_cache_is_keyed_by_xpub_hash_gap_limit_and_tip() {
+        let cache = ScanCache::new();
+        let key = ScanCache::key("xpub-a", 20);
+        let response = ScanXpubResponse::failed("r1", "scan".to_string(), "electrs_unavailable");
+
+        assert_ne!(key, ScanCache::key("xpub-a", 30));
+        assert_ne!(key, ScanCache::key("xpub-b", 20));
+
+        cache.insert(key, 100, response);
+        assert_eq!(cache.get(&key, 100).unwrap().req, "r1");
+        assert!(cache.get(&key, 101).is_none());
+        assert!(cache.get(&ScanCache::key("xpub-a", 30), 100).is_none());
+    }
+
+    #[test]
+    fn scan_xpub_request_parses_with_defaults() {
+        let parsed: ScanXpubRequest =
+            serde_json::from_str(r#"{"type":"scan_xpub","xpub":"zpub6rFR7y4Q"}"#).unwrap();
+        assert_eq!(parsed.xpub, "zpub6rFR7y4Q");
+        assert_eq!(parsed.gap_limit, None);
+        assert!(!parsed.force_rescan);
+
+        let parsed: ScanXpubRequest = serde_json::from_str(
+            r#"{"type":"scan_xpub","xpub":"x","gap_limit":5,"force_rescan":true}"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.gap_limit, Some(5));
+        assert!(parsed.force_rescan);
+
+        assert!(serde_json::from_str::<ScanXpubRequest>(r#"{"type":"scan_xpub"}"#).is_err());
+    }
+
+    #[test]
+    fn scan_xpub_response_shape() {
+        let response = ScanXpubResponse {
+            req: "r1".to_string(),
+            response_type: "scan_complete",
+            ok: true,
+            cached: false,
+            confirmed_balance: 1_500,
+            unconfirmed_balance: -200,
+            first_unused_index: 3,
+            first_unused_change_index: 1,
+            used_addresses: vec!["bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu".to_string()],
+            error: None,
+            error_code: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            serde_json::json!({
+                "req": "r1",
+                "type": "scan_complete",
+                "ok": true,
+                "cached": false,
+                "confirmed_balance": 1_500,
+                "unconfirmed_balance": -200,
+                "first_unused_index": 3,
+                "first_unused_change_index": 1,
+                "used_addresses": ["bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"],
+            })
+        );
+
+        let failed = serde_json::to_value(ScanXpubResponse::failed("r2", "bad".to_string(), "invalid_xpub")).unwrap();
+        assert_eq!(failed["ok"], false);
+        assert_eq!(failed["type"], "scan_complete");
+        assert_eq!(failed["error"], "bad");
+        assert_eq!(failed["error_code"], "invalid_xpub");
+    }
+
+    #[test]
+    fn checkpoint_keeps_requests_older_than_the_newest() {
+        let checkpoint = EventCheckpoint::load(&temp_dir("checkpoint-order"));
+        let now = Timestamp::now().as_secs();
+        let newer = request_at(now - 10);
+        let older = request_at(now - 30);
+
+        checkpoint.record(&newer);
+        checkpoint.record(&older);
+
+        assert_eq!(checkpoint.since(), Some(Timestamp::from(now - 10 - CHECKPOINT_MARGIN_SECS)));
+        assert_eq!(checkpoint.recent_ids(), vec![newer.id, older.id]);
+    }
+
+    #[tokio::test]
+    async fn checkpoint_survives_restart_and_seeds_dedup() {
+        let dir = temp_dir("checkpoint-restart");
+        let event = request_at(Timestamp::now().as_secs() - 5);
+
+        let checkpoint = EventCheckpoint::load(&dir);
+        checkpoint.record(&event);
+        checkpoint.flush().await;
+
+        let reloaded = EventCheckpoint::load(&dir);
+        assert_eq!(reloaded.since(), checkpoint.since());
+
+        let mut recent = RecentEvents::with_ids(reloaded.recent_ids());
+        assert!(!recent.insert(event.id));
+    }
+}
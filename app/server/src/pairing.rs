@@ -1,12 +1,15 @@
 //! Pairing management for Android app
 //!
-//! Stores and retrieves the paired Android app's public key and relay list.
+//! Stores and retrieves the paired Android apps' public keys and relay lists.
+//! The pairing file is loaded once and kept in memory; every change goes
+//! through one lock and is written back with a temp file and rename.
 
 use anyhow::{Context, Result};
 use nostr_sdk::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use tracing::info;
 
 const PAIRING_FILENAME: &str = "android_pairing.json";
@@ -16,12 +19,34 @@ const PAIRING_FILENAME: &str = "android_pairing.json";
 pub struct AndroidPairing {
     pub android_pubkey: String,
     pub relays: Vec<String>,
+    /// Last response sequence number sent to this device
+    #[serde(default)]
+    pub seq: u64,
+}
+
+/// On-disk pairing file: every device currently paired with this server
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PairingFile {
+    devices: Vec<AndroidPairing>,
+}
+
+/// In-memory copy of the pairing file
+#[derive(Debug, Default)]
+struct PairingState {
+    /// Whether the pairing file exists on disk (see `has_pairing`)
+    exists: bool,
+    file: PairingFile,
+    /// Sequence numbers advanced since the file was last written
+    seq_dirty: bool,
 }
 
 /// Manages Android app pairing
 #[derive(Clone)]
 pub struct PairingManager {
     pairing_path: PathBuf,
+    // Guards every read and mutation, so a sequence bump can't write back a
+    // device list a concurrent revoke just changed
+    state: Arc<Mutex<PairingState>>,
 }
 
 impl PairingManager {
@@ -34,63 +59,295 @@ impl PairingManager {
         fs::create_dir_all(data_dir)
             .context("Failed to create data directory")?;
 
-        Ok(Self { pairing_path })
+        let state = load_pairing(&pairing_path)?;
+
+        Ok(Self {
+            pairing_path,
+            state: Arc::new(Mutex::new(state)),
+        })
     }
 
     /// Check if an Android app is paired
+    ///
+    /// The pairing file is kept (possibly empty) after devices are revoked,
+    /// so this stays true once pairing has been used on this server.
     pub fn has_pairing(&self) -> bool {
-        self.pairing_path.exists()
+        self.state.lock().unwrap().exists
     }
 
-    /// Get the paired Android pubkey
+    /// Get the first paired Android pubkey
     pub fn get_android_pubkey(&self) -> Result<Option<PublicKey>> {
-        if !self.has_pairing() {
-            return Ok(None);
-        }
-
-        let pairing = self.load_pairing()?;
-        let pubkey = PublicKey::from_hex(&pairing.android_pubkey)
-            .context("Invalid Android pubkey in pairing file")?;
+        let state = self.state.lock().unwrap();
 
-        Ok(Some(pubkey))
+        match state.file.devices.first() {
+            Some(device) => {
+                let pubkey = PublicKey::from_hex(&device.android_pubkey)
+                    .context("Invalid Android pubkey in pairing file")?;
+                Ok(Some(pubkey))
+            }
+            None => Ok(None),
+        }
     }
 
     /// Get the relay list from pairing
     pub fn get_relays(&self) -> Result<Vec<String>> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .file
+            .devices
+            .first()
+            .map(|device| device.relays.clone())
+            .unwrap_or_default())
+    }
+
+    /// Pubkeys of every paired device
+    pub fn paired_pubkeys(&self) -> Result<Vec<PublicKey>> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .file
+            .devices
+            .iter()
+            .map(|d| {
+                PublicKey::from_hex(&d.android_pubkey)
+                    .context("Invalid Android pubkey in pairing file")
+            })
+            .collect()
+    }
+
+    /// Check whether a pubkey belongs to a paired device
+    pub fn is_paired(&self, pubkey: &PublicKey) -> Result<bool> {
+        let state = self.state.lock().unwrap();
+        let hex = pubkey.to_hex();
+
+        Ok(state.file.devices.iter().any(|d| d.android_pubkey == hex))
+    }
+
+    /// Whether requests from `pubkey` should be served
+    ///
+    /// With no pairing file the server is open (legacy behaviour); once a
+    /// pairing file exists only the devices listed in it are allowed.
+    pub fn is_allowed(&self, pubkey: &PublicKey) -> bool {
         if !self.has_pairing() {
-            return Ok(Vec::new());
+            return true;
         }
 
-        let pairing = self.load_pairing()?;
-        Ok(pairing.relays)
+        self.is_paired(pubkey).unwrap_or(false)
     }
 
     /// Store pairing information (called when "hello / paired" is received)
     pub fn store_pairing(&self, android_pubkey: PublicKey, relays: Vec<String>) -> Result<()> {
-        let pairing = AndroidPairing {
-            android_pubkey: android_pubkey.to_hex(),
+        let mut state = self.state.lock().unwrap();
+        let hex = android_pubkey.to_hex();
+
+        // Re-pairing keeps the sequence going so the wallet sees no gap
+        let seq = state
+            .file
+            .devices
+            .iter()
+            .find(|d| d.android_pubkey == hex)
+            .map_or(0, |d| d.seq);
+
+        state.file.devices.retain(|d| d.android_pubkey != hex);
+        state.file.devices.push(AndroidPairing {
+            android_pubkey: hex,
             relays,
+            seq,
+        });
+
+        self.save_pairing(&mut state)?;
+
+        info!("Stored Android pairing: {}", android_pubkey.to_hex());
+
+        Ok(())
+    }
+
+    /// Increment and return the response sequence number for `pubkey`
+    ///
+    /// None if the pubkey is not paired (open server without pairing file).
+    /// Only bumps the in-memory counter; `flush` persists it.
+    pub fn next_seq(&self, pubkey: &PublicKey) -> Result<Option<u64>> {
+        let mut state = self.state.lock().unwrap();
+        let hex = pubkey.to_hex();
+
+        let Some(device) = state.file.devices.iter_mut().find(|d| d.android_pubkey == hex) else {
+            return Ok(None);
         };
 
-        let json = serde_json::to_string_pretty(&pairing)
+        device.seq += 1;
+        let seq = device.seq;
+        state.seq_dirty = true;
+
+        Ok(Some(seq))
+    }
+
+    /// Write the sequence numbers out if they advanced since the last write
+    pub fn flush(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        if !state.seq_dirty {
+            return Ok(());
+        }
+
+        self.save_pairing(&mut state)
+    }
+
+    /// Remove a paired device
+    ///
+    /// Returns `false` if the pubkey was not paired.
+    pub fn remove_device(&self, android_pubkey: &PublicKey) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let hex = android_pubkey.to_hex();
+
+        let before = state.file.devices.len();
+        state.file.devices.retain(|d| d.android_pubkey != hex);
+
+        if state.file.devices.len() == before {
+            return Ok(false);
+        }
+
+        self.save_pairing(&mut state)?;
+
+        info!("Removed Android pairing: {}", hex);
+
+        Ok(true)
+    }
+
+    /// Write `state` to the pairing file
+    ///
+    /// Written to a temporary file and renamed so readers and crashes never
+    /// see a truncated file.
+    fn save_pairing(&self, state: &mut PairingState) -> Result<()> {
+        let json = serde_json::to_string_pretty(&state.file)
             .context("Failed to serialize pairing")?;
+        let tmp = self.pairing_path.with_extension("json.tmp");
 
-        fs::write(&self.pairing_path, json)
+        fs::write(&tmp, json)
             .context("Failed to write pairing file")?;
+        fs::rename(&tmp, &self.pairing_path)
+            .context("Failed to replace pairing file")?;
 
-        info!("Stored Android pairing: {}", android_pubkey.to_hex());
+        state.exists = true;
+        state.seq_dirty = false;
 
         Ok(())
     }
+}
 
-    fn load_pairing(&self) -> Result<AndroidPairing> {
-        let content = fs::read_to_string(&self.pairing_path)
-            .context("Failed to read pairing file")?;
+fn load_pairing(path: &Path) -> Result<PairingState> {
+    if !path.exists() {
+        return Ok(PairingState::default());
+    }
 
-        let pairing: AndroidPairing = serde_json::from_str(&content)
-            .context("Invalid pairing file format")?;
+    let content = fs::read_to_string(path)
+        .context("Failed to read pairing file")?;
 
-        Ok(pairing)
-    }
+    // Older servers stored a single device object
+    let file = match serde_json::from_str::<AndroidPairing>(&content) {
+        Ok(single) => PairingFile {
+            devices: vec![single],
+        },
+        Err(_) => serde_json::from_str(&content)
+            .context("Invalid pairing file format")?,
+    };
+
+    Ok(PairingState {
+        exists: true,
+        file,
+        seq_dirty: false,
+    })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nomad-test-pairing-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn seq_is_kept_in_memory_until_flushed() {
+        let dir = temp_dir("seq-flush");
+        let pairing = PairingManager::new(&dir).unwrap();
+        let device = Keys::generate().public_key();
+        pairing.store_pairing(device, vec!["wss://relay.example".to_string()]).unwrap();
+
+        assert_eq!(pairing.next_seq(&device).unwrap(), Some(1));
+        assert_eq!(pairing.next_seq(&device).unwrap(), Some(2));
+        assert_eq!(PairingManager::new(&dir).unwrap().next_seq(&device).unwrap(), Some(1));
+
+        pairing.flush().unwrap();
+        assert!(!dir.join("android_pairing.json.tmp").exists());
+        let restarted = PairingManager::new(&dir).unwrap();
+        assert_eq!(restarted.next_seq(&device).unwrap(), Some(3));
+        assert_eq!(restarted.next_seq(&Keys::generate().public_key()).unwrap(), None);
+    }
+
+    #[test]
+    fn seq_flush_does_not_restore_a_revoked_device() {
+        let dir = temp_dir("seq-revoke");
+        let pairing = PairingManager::new(&dir).unwrap();
+        let (kept, revoked) = (Keys::generate().public_key(), Keys::generate().public_key());
+        pairing.store_pairing(kept, vec![]).unwrap();
+        pairing.store_pairing(revoked, vec![]).unwrap();
+
+        let responses = {
+            let pairing = pairing.clone();
+            std::thread::spawn(move || {
+                for _ in 0..200 {
+                    pairing.next_seq(&kept).unwrap();
+                    pairing.flush().unwrap();
+                }
+            })
+        };
+        assert!(pairing.remove_device(&revoked).unwrap());
+        responses.join().unwrap();
+
+        let restarted = PairingManager::new(&dir).unwrap();
+        assert!(restarted.is_allowed(&kept));
+        assert!(!restarted.is_allowed(&revoked));
+        assert_eq!(restarted.next_seq(&kept).unwrap(), Some(201));
+    }
+
+    #[test]
+    fn repairing_keeps_the_seq_and_revoking_keeps_the_file() {
+        let dir = temp_dir("repair");
+        let pairing = PairingManager::new(&dir).unwrap();
+        let device = Keys::generate().public_key();
+        assert!(!pairing.has_pairing());
+        assert!(pairing.is_allowed(&device));
+
+        pairing.store_pairing(device, vec![]).unwrap();
+        pairing.next_seq(&device).unwrap();
+        pairing.store_pairing(device, vec!["wss://new.example".to_string()]).unwrap();
+        assert_eq!(pairing.get_relays().unwrap(), ["wss://new.example"]);
+        assert_eq!(pairing.next_seq(&device).unwrap(), Some(2));
+
+        assert!(pairing.remove_device(&device).unwrap());
+        assert!(!pairing.remove_device(&device).unwrap());
+        let restarted = PairingManager::new(&dir).unwrap();
+        assert!(restarted.has_pairing());
+        assert!(!restarted.is_allowed(&device));
+    }
+
+    #[test]
+    fn legacy_single_device_file_loads() {
+        let dir = temp_dir("legacy");
+        let device = Keys::generate().public_key();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(PAIRING_FILENAME),
+            serde_json::json!({ "android_pubkey": device.to_hex(), "relays": ["wss://relay.example"] }).to_string(),
+        )
+        .unwrap();
+
+        let pairing = PairingManager::new(&dir).unwrap();
+
+        assert_eq!(pairing.get_android_pubkey().unwrap(), Some(device));
+        assert_eq!(pairing.next_seq(&device).unwrap(), Some(1));
+    }
+}
@@ -1,27 +1,89 @@
-use anyhow::{Context, Result};
-use qrcode::QrCode;
-use qrcode::render::svg;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use bitcoin::hashes::{sha256, Hash};
+use qrcode::{EcLevel, QrCode};
+use qrcode::render::{svg, Renderer};
 use serde::{Deserialize, Serialize};
 
 const APP_IDENTIFIER: &str = "nomad-server";
 const VERSION: u32 = 1;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Quiet zone (in modules) used unless the caller asks otherwise
+pub const DEFAULT_QUIET_ZONE: u8 = 4;
+
+/// Largest quiet zone accepted from `/qr?margin=`
+pub const MAX_QUIET_ZONE: u8 = 16;
+
+/// QR error-correction level
+///
+/// Higher levels survive more damage (smudged or badly printed labels) at
+/// the cost of a denser code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EccLevel {
+    /// ~7% recovery
+    L,
+    /// ~15% recovery
+    #[default]
+    M,
+    /// ~25% recovery
+    Q,
+    /// ~30% recovery
+    H,
+}
+
+impl From<EccLevel> for EcLevel {
+    fn from(level: EccLevel) -> Self {
+        match level {
+            EccLevel::L => EcLevel::L,
+            EccLevel::M => EcLevel::M,
+            EccLevel::Q => EcLevel::Q,
+            EccLevel::H => EcLevel::H,
+        }
+    }
+}
+
+impl FromStr for EccLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "L" => Ok(EccLevel::L),
+            "M" => Ok(EccLevel::M),
+            "Q" => Ok(EccLevel::Q),
+            "H" => Ok(EccLevel::H),
+            _ => Err(anyhow!("Invalid error-correction level: {} (expected L, M, Q or H)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PairingPayload {
     pub version: u32,
     pub app: String,
     #[serde(rename = "nodePubkey")]
     pub node_pubkey: String,
     pub relays: Vec<String>,
+    /// Short hash of pubkey + relays so the wallet can detect config drift
+    #[serde(rename = "configHash")]
+    pub config_hash: String,
 }
 
 impl PairingPayload {
-    pub fn new(node_pubkey: String, relays: Vec<String>) -> Self {
+    /// Build the payload; relays are sorted so the JSON (and QR) is
+    /// byte-identical across restarts regardless of configured order
+    pub fn new(node_pubkey: String, mut relays: Vec<String>) -> Self {
+        relays.sort_unstable();
+        relays.dedup();
+
+        let config_hash = config_hash(&node_pubkey, &relays);
+
         Self {
             version: VERSION,
             app: APP_IDENTIFIER.to_string(),
             node_pubkey,
             relays,
+            config_hash,
         }
     }
 
@@ -31,14 +93,17 @@ impl PairingPayload {
     }
 
     /// Generate QR code as SVG (stable, no image crate)
-    pub fn generate_qr_svg(&self) -> Result<String> {
+    ///
+    /// `quiet_zone` is the blank border in modules; the spec asks for 4
+    /// (`DEFAULT_QUIET_ZONE`), but many scanners cope with less.
+    pub fn generate_qr_svg(&self, ecc: EccLevel, quiet_zone: u8) -> Result<String> {
         let json = self.to_json()?;
 
-        let code = QrCode::new(json.as_bytes())
+        let code = QrCode::with_error_correction_level(json.as_bytes(), ecc.into())
             .context("Failed to generate QR code")?;
 
-        let svg = code
-            .render::<svg::Color>()
+        let colors = code.to_colors();
+        let svg = Renderer::<svg::Color>::new(&colors, code.width(), u32::from(quiet_zone))
             .min_dimensions(512, 512)
             .dark_color(svg::Color("#000000"))
             .light_color(svg::Color("#ffffff"))
@@ -47,3 +112,90 @@ impl PairingPayload {
         Ok(svg)
     }
 }
+
+/// First 8 bytes (hex) of SHA-256 over the pubkey and the sorted relay list
+///
+/// Relays are sorted first so the hash does not depend on their order.
+pub fn config_hash(node_pubkey: &str, relays: &[String]) -> String {
+    let mut sorted: Vec<&str> = relays.iter().map(|r| r.as_str()).collect();
+    sorted.sort_unstable();
+
+    let mut data = String::from(node_pubkey);
+    for relay in sorted {
+        data.push('\n');
+        data.push_str(relay);
+    }
+
+    let digest = sha256::Hash::hash(data.as_bytes());
+    hex::encode(&digest.as_byte_array()[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBKEY: &str = "17162c921dc4d2518f9a101db33695df1afb56ab82f5ff3e5da6eec3ca5cd917";
+
+    fn relays(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|u| u.to_string()).collect()
+    }
+
+    #[test]
+    fn payload_json_is_sorted_and_deduplicated() {
+        let payload = PairingPayload::new(
+            PUBKEY.to_string(),
+            relays(&["wss://nos.lol", "wss://relay.damus.io", "wss://nos.lol"]),
+        );
+
+        assert_eq!(
+            payload.to_json().unwrap(),
+            format!(
+                r#"{{"version":1,"app":"nomad-server","nodePubkey":"{}","relays":["wss://nos.lol","wss://relay.damus.io"],"configHash":"{}"}}"#,
+                PUBKEY, payload.config_hash
+            )
+        );
+        assert_eq!(payload.config_hash.len(), 16);
+    }
+
+    #[test]
+    fn relay_order_does_not_change_json_hash_or_qr() {
+        let a = PairingPayload::new(PUBKEY.to_string(), relays(&["wss://b.example", "wss://a.example"]));
+        let b = PairingPayload::new(PUBKEY.to_string(), relays(&["wss://a.example", "wss://b.example"]));
+
+        assert_eq!(a.to_json().unwrap(), b.to_json().unwrap());
+        assert_eq!(
+            a.generate_qr_svg(EccLevel::M, DEFAULT_QUIET_ZONE).unwrap(),
+            b.generate_qr_svg(EccLevel::M, DEFAULT_QUIET_ZONE).unwrap()
+        );
+        assert_eq!(
+            config_hash(PUBKEY, &relays(&["wss://b.example", "wss://a.example"])),
+            a.config_hash
+        );
+    }
+
+    #[test]
+    fn config_hash_tracks_pubkey_and_relays() {
+        let base = config_hash(PUBKEY, &relays(&["wss://a.example"]));
+
+        assert_ne!(base, config_hash(PUBKEY, &relays(&["wss://a.example", "wss://b.example"])));
+        assert_ne!(base, config_hash(&PUBKEY.replace('1', "2"), &relays(&["wss://a.example"])));
+    }
+
+    #[test]
+    fn qr_depends_on_ecc_and_quiet_zone() {
+        let payload = PairingPayload::new(PUBKEY.to_string(), relays(&["wss://a.example"]));
+        let svg = |ecc, margin| payload.generate_qr_svg(ecc, margin).unwrap();
+
+        assert!(svg(EccLevel::M, 4).starts_with("<?xml"));
+        assert_eq!(svg(EccLevel::M, 4), svg(EccLevel::M, 4));
+        assert_ne!(svg(EccLevel::M, 4), svg(EccLevel::H, 4));
+        assert_ne!(svg(EccLevel::M, 4), svg(EccLevel::M, 0));
+    }
+
+    #[test]
+    fn ecc_level_parses_case_insensitively() {
+        assert_eq!("q".parse::<EccLevel>().unwrap(), EccLevel::Q);
+        assert_eq!("H".parse::<EccLevel>().unwrap(), EccLevel::H);
+        assert!("X".parse::<EccLevel>().is_err());
+    }
+}
@@ -1,41 +1,322 @@
 //! Relay configuration
 //! 
 //! Manages the list of public Nostr relays to use.
+//!
+//! Entries may carry a role suffix: `wss://relay.example,read` is only used
+//! to receive requests, `wss://relay.example,write` only to publish
+//! responses. Entries without a suffix do both.
 
+use anyhow::{Context, Result};
+use nostr_sdk::RelayUrl;
 use std::env;
-use tracing::info;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Relay list saved by `POST /relays`, kept in the data dir
+const RELAYS_FILENAME: &str = "relays.json";
+
+/// What the server uses a relay for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RelayRole {
+    /// Subscribe for requests and publish responses
+    #[default]
+    ReadWrite,
+    /// Only subscribe for requests
+    Read,
+    /// Only publish responses
+    Write,
+}
+
+impl RelayRole {
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "read" => Some(Self::Read),
+            "write" => Some(Self::Write),
+            _ => None,
+        }
+    }
+}
+
+/// A relay URL with its role
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayEntry {
+    pub url: String,
+    pub role: RelayRole,
+}
+
+impl RelayEntry {
+    pub fn new(url: String) -> Self {
+        Self { url, role: RelayRole::ReadWrite }
+    }
+}
+
+impl fmt::Display for RelayEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.role {
+            RelayRole::ReadWrite => write!(f, "{}", self.url),
+            RelayRole::Read => write!(f, "{},read", self.url),
+            RelayRole::Write => write!(f, "{},write", self.url),
+        }
+    }
+}
 
 /// Default list of public Nostr relays
-fn default_relays() -> Vec<String> {
-    vec![
-        "wss://relay.damus.io".to_string(),
-        "wss://nostr.wine".to_string(),
-        "wss://relay.primal.net".to_string(),
-        "wss://nos.lol".to_string(),
-        "wss://relay.snort.social".to_string(),
+fn default_relays() -> Vec<RelayEntry> {
+    [
+        "wss://relay.damus.io",
+        "wss://nostr.wine",
+        "wss://relay.primal.net",
+        "wss://nos.lol",
+        "wss://relay.snort.social",
     ]
+    .into_iter()
+    .map(|url| RelayEntry::new(url.to_string()))
+    .collect()
 }
 
 /// Get the list of relays to use
 /// 
-/// Reads from NOSTR_RELAYS environment variable (comma-separated).
-/// Falls back to default list if env var is not set.
-pub fn get_relays() -> Vec<String> {
+/// A list saved at runtime (`POST /relays`) wins; otherwise reads the
+/// NOSTR_RELAYS environment variable (comma-separated).
+/// Falls back to default list if neither is set.
+/// URLs are normalized and deduplicated; invalid ones are dropped with a warning.
+pub fn get_relays(data_dir: &Path) -> Vec<RelayEntry> {
+    if let Some(saved) = load_saved_relays(data_dir) {
+        info!("Using relays saved in {}: {}", RELAYS_FILENAME, join(&saved));
+        return saved;
+    }
+
     if let Ok(relays_env) = env::var("NOSTR_RELAYS") {
-        let relays: Vec<String> = relays_env
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect();
+        let relays = parse_relay_entries(relays_env.split(','));
         
         if !relays.is_empty() {
-            info!("Using relays from NOSTR_RELAYS: {:?}", relays);
+            info!("Using relays from NOSTR_RELAYS: {}", join(&relays));
             return relays;
         }
     }
     
     let defaults = default_relays();
-    info!("Using default relay list: {:?}", defaults);
+    info!("Using default relay list: {}", join(&defaults));
     defaults
 }
 
+/// Relay list saved by `save_relays`, if any
+fn load_saved_relays(data_dir: &Path) -> Option<Vec<RelayEntry>> {
+    let content = fs::read_to_string(data_dir.join(RELAYS_FILENAME)).ok()?;
+
+    match serde_json::from_str::<Vec<String>>(&content) {
+        Ok(saved) => {
+            let relays = parse_relay_entries(saved.iter().flat_map(|r| r.split(',')));
+            (!relays.is_empty()).then_some(relays)
+        }
+        Err(e) => {
+            warn!("Ignoring invalid {}: {}", RELAYS_FILENAME, e);
+            None
+        }
+    }
+}
+
+/// Persist the relay list (with roles) so it is used after a restart
+pub fn save_relays(data_dir: &Path, relays: &[RelayEntry]) -> Result<()> {
+    let entries: Vec<String> = relays.iter().map(|r| r.to_string()).collect();
+    let json = serde_json::to_string_pretty(&entries)
+        .context("Failed to serialize relay list")?;
+
+    fs::write(data_dir.join(RELAYS_FILENAME), json)
+        .context("Failed to write relay list")?;
+
+    Ok(())
+}
+
+/// Comma-separated form of a relay list, for logs
+pub fn join(relays: &[RelayEntry]) -> String {
+    relays.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Parse relay list items where a `read` / `write` item sets the role of
+/// the URL before it (`wss://a,read,wss://b` = a read-only, b read+write)
+///
+/// URLs are normalized; invalid entries and duplicates are dropped.
+pub fn parse_relay_entries<'a>(items: impl IntoIterator<Item = &'a str>) -> Vec<RelayEntry> {
+    let mut out: Vec<RelayEntry> = Vec::new();
+    // Whether the last item was a URL that was kept (and may take a role)
+    let mut last_kept = false;
+
+    for raw in items {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            continue;
+        }
+
+        if let Some(role) = RelayRole::parse(raw) {
+            match out.last_mut() {
+                Some(entry) if last_kept && entry.role == RelayRole::ReadWrite => {
+                    entry.role = role;
+                }
+                _ => warn!("Ignoring relay role '{}' without a preceding relay URL", raw),
+            }
+            last_kept = false;
+            continue;
+        }
+
+        last_kept = false;
+        match normalize_relay_url(raw) {
+            Some(url) if !out.iter().any(|r| r.url == url) => {
+                out.push(RelayEntry::new(url));
+                last_kept = true;
+            }
+            Some(url) => warn!("Ignoring duplicate relay {}", url),
+            None => warn!("Ignoring invalid relay URL '{}'", raw),
+        }
+    }
+
+    out
+}
+
+/// Normalize a list of relay URLs, dropping invalid entries and duplicates
+pub fn normalize_relays<'a>(relays: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+
+    for raw in relays {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            continue;
+        }
+
+        match normalize_relay_url(raw) {
+            Some(url) if !out.contains(&url) => out.push(url),
+            Some(url) => warn!("Ignoring duplicate relay {}", url),
+            None => warn!("Ignoring invalid relay URL '{}'", raw),
+        }
+    }
+
+    out
+}
+
+/// Normalize one relay URL
+///
+/// Lowercases scheme and host, strips trailing slashes and defaults a
+/// missing scheme to `wss://`. Returns None for non-websocket or unparsable URLs.
+pub fn normalize_relay_url(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+
+    let (scheme, rest) = match raw.split_once("://") {
+        Some((scheme, rest)) => (scheme.to_lowercase(), rest),
+        None => ("wss".to_string(), raw),
+    };
+
+    if scheme != "wss" && scheme != "ws" {
+        return None;
+    }
+
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+
+    if host.is_empty() {
+        return None;
+    }
+
+    let url = format!(
+        "{}://{}{}",
+        scheme,
+        host.to_lowercase(),
+        path.trim_end_matches('/')
+    );
+
+    RelayUrl::parse(&url).ok()?;
+
+    Some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(url: &str, role: RelayRole) -> RelayEntry {
+        RelayEntry { url: url.to_string(), role }
+    }
+
+    #[test]
+    fn normalizes_scheme_host_and_trailing_slashes() {
+        let cases = [
+            ("wss://relay.damus.io", "wss://relay.damus.io"),
+            ("wss://relay.damus.io/", "wss://relay.damus.io"),
+            ("wss://relay.damus.io///", "wss://relay.damus.io"),
+            ("  WSS://Relay.Damus.IO/  ", "wss://relay.damus.io"),
+            ("relay.damus.io", "wss://relay.damus.io"),
+            ("ws://localhost:7777/", "ws://localhost:7777"),
+            ("wss://nostr.example/Inbox/", "wss://nostr.example/Inbox"),
+        ];
+
+        for (raw, expected) in cases {
+            assert_eq!(normalize_relay_url(raw).as_deref(), Some(expected), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn rejects_non_websocket_and_empty_hosts() {
+        for raw in ["https://relay.damus.io", "ftp://relay", "wss://", "wss:///path", ""] {
+            assert_eq!(normalize_relay_url(raw), None, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn normalize_relays_dedups_after_normalizing() {
+        let relays = normalize_relays([
+            "wss://nos.lol",
+            "wss://nos.lol/",
+            "NOS.LOL",
+            "https://nos.lol",
+            "",
+            "wss://relay.primal.net",
+        ]);
+
+        assert_eq!(relays, ["wss://nos.lol", "wss://relay.primal.net"]);
+    }
+
+    #[test]
+    fn parses_roles_and_drops_duplicates() {
+        let relays = parse_relay_entries(
+            "wss://a.example,read, wss://b.example/ ,wss://c.example,WRITE,wss://a.example/,write"
+                .split(','),
+        );
+
+        assert_eq!(
+            relays,
+            [
+                entry("wss://a.example", RelayRole::Read),
+                entry("wss://b.example", RelayRole::ReadWrite),
+                entry("wss://c.example", RelayRole::Write),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_roles_without_a_url() {
+        // Leading role, a role after a dropped URL, and a second role
+        let relays = parse_relay_entries(["read", "wss://a.example", "read", "write", "https://x", "write"]);
+
+        assert_eq!(relays, [entry("wss://a.example", RelayRole::Read)]);
+    }
+
+    #[test]
+    fn saved_list_round_trips_with_roles() {
+        let dir = std::env::temp_dir().join(format!("nomad-test-relays-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let relays = vec![
+            entry("wss://a.example", RelayRole::Read),
+            entry("wss://b.example", RelayRole::ReadWrite),
+            entry("wss://c.example", RelayRole::Write),
+        ];
+        save_relays(&dir, &relays).unwrap();
+
+        assert_eq!(get_relays(&dir), relays);
+        assert_eq!(join(&relays), "wss://a.example,read, wss://b.example, wss://c.example,write");
+    }
+}
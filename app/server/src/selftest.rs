@@ -0,0 +1,126 @@
+//! Deployment smoke test (`--self-test` / NOMAD_SELFTEST=1)
+//!
+//! Checks each component the server depends on, prints a pass/fail
+//! checklist and reports whether everything passed. The server itself is
+//! not started.
+
+use anyhow::{anyhow, Result};
+use bitcoin::{Address, Network};
+use nostr_sdk::{EventBuilder, Keys};
+
+use crate::electrs::ElectrsClient;
+
+/// BIP84 test vector ("abandon ... about"), account 0
+const TEST_ZPUB: &str = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+/// First receive address of TEST_ZPUB
+const TEST_ADDRESS: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+
+/// BIP86 test vector (same mnemonic), account 0 as a `tr()` descriptor
+const TEST_TR_DESCRIPTOR: &str = "tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*)";
+/// First receive and change addresses of TEST_TR_DESCRIPTOR
+const TEST_TR_ADDRESSES: [&str; 2] = [
+    "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr",
+    "bc1p3qkhfews2uk44qtvauqyr2ttdsw7svhkl9nkm9s9c3x4ax5h60wqwruhk7",
+];
+
+/// Whether the self-test was requested on the command line or via env
+pub fn requested() -> bool {
+    std::env::args().any(|a| a == "--self-test")
+        || std::env::var("NOMAD_SELFTEST").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
+/// Run every check; true if all passed
+pub async fn run() -> bool {
+    println!("NomadServer self-test");
+
+    let mut ok = true;
+
+    let electrs = check("Electrs connectivity", connect_electrs());
+    ok &= electrs.is_some();
+
+    let address = check("Address derivation (BIP84 vector)", derive_test_address());
+    ok &= address.is_some();
+
+    ok &= check("Taproot derivation (BIP86 vector)", derive_taproot_addresses()).is_some();
+
+    match (&electrs, &address) {
+        (Some(client), Some(address)) => {
+            ok &= check("Balance query", query_balance(client, address).await).is_some();
+        }
+        _ => {
+            println!("[SKIP] Balance query (needs Electrs and a derived address)");
+            ok = false;
+        }
+    }
+
+    ok &= check("Nostr identity and signing", sign_and_verify()).is_some();
+
+    println!("Self-test {}", if ok { "PASSED" } else { "FAILED" });
+    ok
+}
+
+/// Print one checklist line and pass the value through
+fn check<T>(name: &str, result: Result<T>) -> Option<T> {
+    match result {
+        Ok(v) => {
+            println!("[PASS] {}", name);
+            Some(v)
+        }
+        Err(e) => {
+            println!("[FAIL] {}: {}", name, e);
+            None
+        }
+    }
+}
+
+fn connect_electrs() -> Result<ElectrsClient> {
+    let client = ElectrsClient::new()?;
+    client.test_connectivity()?;
+    Ok(client)
+}
+
+/// Derive the BIP84 vector address, then re-encode its script for the
+/// configured network so the balance query is valid there too
+fn derive_test_address() -> Result<Address> {
+    let derived = crate::xpub::derive_address(TEST_ZPUB, Network::Bitcoin, false, 0)?;
+
+    if derived.to_string() != TEST_ADDRESS {
+        return Err(anyhow!("derived {}, expected {}", derived, TEST_ADDRESS));
+    }
+
+    let network = crate::config::get_network();
+    Ok(Address::from_script(&derived.script_pubkey(), network)?)
+}
+
+/// Derive the BIP86 receive/change addresses and parse them back as bech32m
+fn derive_taproot_addresses() -> Result<()> {
+    for (change, expected) in [false, true].into_iter().zip(TEST_TR_ADDRESSES) {
+        let derived = crate::xpub::derive_address(TEST_TR_DESCRIPTOR, Network::Bitcoin, change, 0)?;
+        if derived.to_string() != expected {
+            return Err(anyhow!("derived {}, expected {}", derived, expected));
+        }
+
+        // Wallets may hand over the uppercase (QR) form
+        expected.to_uppercase().parse::<Address<_>>()?.require_network(Network::Bitcoin)?;
+    }
+
+    Ok(())
+}
+
+async fn query_balance(client: &ElectrsClient, address: &Address) -> Result<()> {
+    let (confirmed, unconfirmed) = client.get_address_balance(&address.to_string()).await?;
+    println!("       {}: {} confirmed, {} unconfirmed", address, confirmed, unconfirmed);
+    Ok(())
+}
+
+/// Load the server key (as startup would) and round-trip a signed event
+fn sign_and_verify() -> Result<()> {
+    let keys: Keys = std::panic::catch_unwind(crate::identity::load_or_create_keys)
+        .map_err(|_| anyhow!("failed to load the server key"))?;
+
+    let event = EventBuilder::text_note("nomad self-test").sign_with_keys(&keys)?;
+    event.verify()?;
+
+    println!("       pubkey {}", keys.public_key().to_hex());
+    Ok(())
+}
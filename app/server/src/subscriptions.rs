@@ -0,0 +1,194 @@
+//! Address subscriptions and tx watches for push notifications
+//!
+//! Paired wallets register addresses with `subscribe_address`, and
+//! broadcast txs with `broadcast_tx` + `watch: true`; the watcher task in
+//! `nostr.rs` polls them and pushes an event when new txs appear or a
+//! watched tx confirms.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use nostr_sdk::PublicKey;
+use tracing::info;
+
+/// Maximum number of addresses a single requester may watch
+pub const MAX_SUBSCRIBED_ADDRESSES: usize = 100;
+
+/// Maximum number of broadcast txs watched for a single requester
+pub const MAX_WATCHED_TXS: usize = 100;
+
+/// How long a broadcast tx is watched for its first confirmation
+pub const TX_WATCH_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A broadcast tx a requester wants a `tx_confirmed` push for
+#[derive(Debug, Clone)]
+pub struct TxWatch {
+    pub txid: String,
+    pub requester: PublicKey,
+    /// Server identity that accepted the broadcast; signs the push
+    pub server: PublicKey,
+    /// `req` of the broadcast request
+    pub req: String,
+    since: Instant,
+}
+
+/// Addresses watched for one requester
+struct Subscription {
+    /// Address -> txids already reported (None until the first poll)
+    addresses: HashMap<String, Option<HashSet<String>>>,
+    /// Last time the requester (re)subscribed
+    renewed_at: Instant,
+    /// Server identity the requester last subscribed through; notifications
+    /// are signed by it
+    server: PublicKey,
+}
+
+/// Per-requester address subscriptions with idle expiry
+pub struct SubscriptionManager {
+    idle_timeout: Duration,
+    subs: Mutex<HashMap<PublicKey, Subscription>>,
+    txs: Mutex<Vec<TxWatch>>,
+}
+
+impl SubscriptionManager {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout,
+            subs: Mutex::new(HashMap::new()),
+            txs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Watch `txid` until it confirms or TX_WATCH_TIMEOUT passes
+    ///
+    /// Returns false if `requester` already watches MAX_WATCHED_TXS txs.
+    /// Watching a tx again is a no-op.
+    pub fn watch_tx(&self, txid: &str, requester: PublicKey, server: PublicKey, req: &str) -> bool {
+        let mut txs = self.txs.lock().unwrap();
+
+        if txs.iter().any(|w| w.txid == txid && w.requester == requester) {
+            return true;
+        }
+        if txs.iter().filter(|w| w.requester == requester).count() >= MAX_WATCHED_TXS {
+            return false;
+        }
+
+        txs.push(TxWatch {
+            txid: txid.to_string(),
+            requester,
+            server,
+            req: req.to_string(),
+            since: Instant::now(),
+        });
+        true
+    }
+
+    /// Every current tx watch, dropping those older than TX_WATCH_TIMEOUT
+    pub fn watched_txs(&self) -> Vec<TxWatch> {
+        let mut txs = self.txs.lock().unwrap();
+
+        txs.retain(|w| {
+            let keep = w.since.elapsed() < TX_WATCH_TIMEOUT;
+            if !keep {
+                info!("Tx watch expired for {} ({})", w.txid, w.requester.to_hex());
+            }
+            keep
+        });
+
+        txs.clone()
+    }
+
+    /// Stop watching `txid` for `requester` (e.g. once it confirmed)
+    pub fn unwatch_tx(&self, txid: &str, requester: &PublicKey) {
+        self.txs
+            .lock()
+            .unwrap()
+            .retain(|w| !(w.txid == txid && w.requester == *requester));
+    }
+
+    /// Add addresses for `pubkey`, subscribing through `server`, and renew
+    /// its subscription
+    ///
+    /// Returns the number of addresses now watched for `pubkey`. Addresses
+    /// beyond MAX_SUBSCRIBED_ADDRESSES are ignored.
+    pub fn subscribe(&self, pubkey: PublicKey, server: PublicKey, addresses: Vec<String>) -> usize {
+        let mut subs = self.subs.lock().unwrap();
+        let sub = subs.entry(pubkey).or_insert_with(|| Subscription {
+            addresses: HashMap::new(),
+            renewed_at: Instant::now(),
+            server,
+        });
+
+        sub.renewed_at = Instant::now();
+        sub.server = server;
+
+        for address in addresses {
+            if sub.addresses.len() >= MAX_SUBSCRIBED_ADDRESSES {
+                break;
+            }
+            sub.addresses.entry(address).or_insert(None);
+        }
+
+        sub.addresses.len()
+    }
+
+    /// Drop subscriptions that have not been renewed within the idle timeout
+    pub fn expire_idle(&self) {
+        let mut subs = self.subs.lock().unwrap();
+        let idle_timeout = self.idle_timeout;
+
+        subs.retain(|pubkey, sub| {
+            let keep = sub.renewed_at.elapsed() < idle_timeout;
+            if !keep {
+                info!("Address subscription expired for {}", pubkey.to_hex());
+            }
+            keep
+        });
+    }
+
+    /// Every distinct address currently watched by anyone
+    pub fn watched_addresses(&self) -> Vec<String> {
+        let subs = self.subs.lock().unwrap();
+        let unique: HashSet<&String> = subs.values().flat_map(|s| s.addresses.keys()).collect();
+
+        unique.into_iter().cloned().collect()
+    }
+
+    /// Record the current history of `address`
+    ///
+    /// Returns, per subscriber, the server identity it subscribed through and
+    /// the txids not reported to it before. The first poll after subscribing
+    /// only records a baseline and reports nothing.
+    pub fn record_history(
+        &self,
+        address: &str,
+        txids: &[String],
+    ) -> Vec<(PublicKey, PublicKey, Vec<String>)> {
+        let mut subs = self.subs.lock().unwrap();
+        let mut updates = Vec::new();
+
+        for (pubkey, sub) in subs.iter_mut() {
+            let Some(known) = sub.addresses.get_mut(address) else {
+                continue;
+            };
+
+            match known {
+                Some(seen) => {
+                    let new: Vec<String> = txids
+                        .iter()
+                        .filter(|t| seen.insert((*t).clone()))
+                        .cloned()
+                        .collect();
+
+                    if !new.is_empty() {
+                        updates.push((*pubkey, sub.server, new));
+                    }
+                }
+                None => *known = Some(txids.iter().cloned().collect()),
+            }
+        }
+
+        updates
+    }
+}
@@ -0,0 +1,109 @@
+//! Server identities hosted by one process
+//!
+//! Besides the primary identity, NOMAD_TENANTS adds independent identities,
+//! each with its own keys and pairings. Requests are served by the identity
+//! their `p` tag addresses, so tenants never share a pubkey on relays.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use nostr_sdk::prelude::*;
+use tracing::info;
+
+use crate::pairing::PairingManager;
+
+const TENANTS_DIR: &str = "tenants";
+
+/// One server identity: the keys it signs with and the devices paired to it
+#[derive(Clone)]
+pub struct Identity {
+    pub name: String,
+    pub keys: Keys,
+    pub pairing: PairingManager,
+}
+
+/// Every identity served by this process, keyed by pubkey
+#[derive(Clone)]
+pub struct Identities {
+    primary: PublicKey,
+    by_pubkey: HashMap<PublicKey, Identity>,
+}
+
+impl Identities {
+    /// The primary identity only
+    pub fn single(keys: Keys, pairing: PairingManager) -> Self {
+        let primary = keys.public_key();
+        let mut by_pubkey = HashMap::new();
+        by_pubkey.insert(
+            primary,
+            Identity {
+                name: "primary".to_string(),
+                keys,
+                pairing,
+            },
+        );
+
+        Self { primary, by_pubkey }
+    }
+
+    /// The primary identity plus one per NOMAD_TENANTS entry
+    pub fn load(data_dir: &Path, keys: Keys, pairing: PairingManager) -> Result<Self> {
+        let mut identities = Self::single(keys, pairing);
+
+        for name in crate::config::tenants() {
+            let dir = data_dir.join(TENANTS_DIR).join(&name);
+            let keys = crate::identity::load_or_create_tenant_keys(&dir);
+            let pairing = PairingManager::new(&dir)
+                .with_context(|| format!("Failed to init pairing manager for tenant {}", name))?;
+
+            info!(tenant = %name, pubkey = %keys.public_key().to_hex(), "Hosting tenant identity");
+
+            identities
+                .by_pubkey
+                .insert(keys.public_key(), Identity { name, keys, pairing });
+        }
+
+        Ok(identities)
+    }
+
+    pub fn primary(&self) -> &Identity {
+        &self.by_pubkey[&self.primary]
+    }
+
+    pub fn get(&self, pubkey: &PublicKey) -> Option<&Identity> {
+        self.by_pubkey.get(pubkey)
+    }
+
+    /// Whether tenants are hosted besides the primary identity
+    pub fn is_multi_tenant(&self) -> bool {
+        self.by_pubkey.len() > 1
+    }
+
+    /// Every identity, the primary first
+    pub fn iter(&self) -> impl Iterator<Item = &Identity> {
+        let tenants = self
+            .by_pubkey
+            .iter()
+            .filter(|(pk, _)| **pk != self.primary)
+            .map(|(_, identity)| identity);
+
+        std::iter::once(self.primary()).chain(tenants)
+    }
+
+    pub fn pubkeys(&self) -> Vec<PublicKey> {
+        self.by_pubkey.keys().copied().collect()
+    }
+
+    /// The identity an event is addressed to
+    ///
+    /// The first `p` tag naming a hosted identity wins; untagged events (and
+    /// those tagging no hosted identity) go to the primary identity.
+    pub fn route(&self, event: &Event) -> &Identity {
+        event
+            .tags
+            .public_keys()
+            .find_map(|pk| self.by_pubkey.get(pk))
+            .unwrap_or_else(|| self.primary())
+    }
+}
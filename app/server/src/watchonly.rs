@@ -0,0 +1,106 @@
+//! Watch-only xpubs registered by wallets (`register_xpub` / `sync_xpub`)
+//!
+//! Each requester's xpub and sync state lives in
+//! `<data dir>/watch_only/<requester pubkey>.json`. The xpub itself is
+//! NIP-44 encrypted to the server's own key, so the file alone does not
+//! reveal the wallet.
+
+use anyhow::{anyhow, Context, Result};
+use nostr_sdk::nips::nip44::{self, Version};
+use nostr_sdk::{Keys, PublicKey};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const WATCH_ONLY_DIRNAME: &str = "watch_only";
+
+/// A used address and the txids it had at the last sync
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsedAddress {
+    pub address: String,
+    pub txids: Vec<String>,
+}
+
+/// Registered xpub and what the last sync found
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchedXpub {
+    /// NIP-44 ciphertext of the xpub, encrypted to the server key
+    xpub: String,
+    pub gap_limit: u32,
+    /// One past the last used index on the receive chain
+    pub receive_next: u32,
+    /// Same as `receive_next`, for the change chain
+    pub change_next: u32,
+    /// Used addresses in discovery order
+    pub used: Vec<UsedAddress>,
+}
+
+impl WatchedXpub {
+    /// Fresh state for `xpub`; the first sync reports everything as new
+    pub fn new(keys: &Keys, xpub: &str, gap_limit: u32) -> Result<Self> {
+        let xpub = nip44::encrypt(keys.secret_key(), &keys.public_key(), xpub, Version::V2)
+            .map_err(|e| anyhow!("Failed to encrypt xpub: {}", e))?;
+
+        Ok(Self {
+            xpub,
+            gap_limit,
+            receive_next: 0,
+            change_next: 0,
+            used: Vec::new(),
+        })
+    }
+
+    /// Decrypt the xpub; fails if the server key changed since registration
+    pub fn xpub(&self, keys: &Keys) -> Result<String> {
+        nip44::decrypt(keys.secret_key(), &keys.public_key(), &self.xpub)
+            .map_err(|e| anyhow!("Failed to decrypt registered xpub: {}", e))
+    }
+}
+
+/// Per-requester watch-only state in the data dir
+#[derive(Clone)]
+pub struct WatchOnlyStore {
+    dir: PathBuf,
+}
+
+impl WatchOnlyStore {
+    pub fn new(data_dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: data_dir.as_ref().join(WATCH_ONLY_DIRNAME),
+        }
+    }
+
+    fn path(&self, requester: &PublicKey) -> PathBuf {
+        self.dir.join(format!("{}.json", requester.to_hex()))
+    }
+
+    /// State registered by `requester`, if any
+    pub fn load(&self, requester: &PublicKey) -> Result<Option<WatchedXpub>> {
+        let path = self.path(requester);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path).context("Failed to read watch-only file")?;
+        let state = serde_json::from_str(&content).context("Invalid watch-only file format")?;
+
+        Ok(Some(state))
+    }
+
+    /// Persist `state` for `requester`, replacing any earlier registration
+    ///
+    /// Written to a temporary file and renamed so a crash never leaves a
+    /// truncated file behind.
+    pub fn save(&self, requester: &PublicKey, state: &WatchedXpub) -> Result<()> {
+        fs::create_dir_all(&self.dir).context("Failed to create watch-only directory")?;
+
+        let json = serde_json::to_string_pretty(state).context("Failed to serialize watch-only state")?;
+        let path = self.path(requester);
+        let tmp = path.with_extension("json.tmp");
+
+        fs::write(&tmp, json).context("Failed to write watch-only file")?;
+        fs::rename(&tmp, &path).context("Failed to replace watch-only file")?;
+
+        Ok(())
+    }
+}
@@ -1,87 +1,500 @@
 //! Extended public key (xpub) address derivation
 //!
-//! Derives Bitcoin addresses from xpub/ypub/zpub/tpub with gap limit support.
+//! Derives Bitcoin addresses from xpub/ypub/zpub/tpub or output descriptors
+//! (`pkh`, `sh(wpkh)`, `wpkh`, `tr`) with gap limit support.
 
-use anyhow::{Context, Result};
-use bitcoin::bip32::{DerivationPath, Xpub};
+use anyhow::{anyhow, Context, Result};
+use bitcoin::base58;
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpub};
+use bitcoin::key::XOnlyPublicKey;
 use bitcoin::secp256k1::Secp256k1;
-use bitcoin::Network;
-use std::str::FromStr;
-use tracing::{info, warn};
+use bitcoin::{Address, CompressedPublicKey, Network};
+use tracing::{debug, info, warn};
+
+use crate::electrs::ElectrsClient;
+use crate::error::ErrorCode;
+
+/// BIP32 version bytes the `bitcoin` crate understands
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
+const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xCF];
+
+/// Address (script) type implied by an extended key prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptType {
+    /// Legacy pay-to-pubkey-hash (xpub/tpub)
+    P2pkh,
+    /// Nested SegWit, P2WPKH wrapped in P2SH (ypub/upub)
+    P2shP2wpkh,
+    /// Native SegWit (zpub/vpub)
+    P2wpkh,
+    /// Taproot key-path (`tr()` descriptors only)
+    P2tr,
+}
+
+impl ScriptType {
+    /// Short name used in responses
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScriptType::P2pkh => "p2pkh",
+            ScriptType::P2shP2wpkh => "p2sh-p2wpkh",
+            ScriptType::P2wpkh => "p2wpkh",
+            ScriptType::P2tr => "p2tr",
+        }
+    }
+
+    /// BIP44/49/84/86 purpose for this script type
+    fn purpose(self) -> u32 {
+        match self {
+            ScriptType::P2pkh => 44,
+            ScriptType::P2shP2wpkh => 49,
+            ScriptType::P2wpkh => 84,
+            ScriptType::P2tr => 86,
+        }
+    }
+}
+
+/// Key metadata for confirming an xpub against a hardware wallet
+#[derive(Debug, Clone)]
+pub struct XpubInfo {
+    /// Master key fingerprint: from the descriptor origin, or known
+    /// outright for depth 0/1 keys; None otherwise
+    pub master_fingerprint: Option<String>,
+    /// Fingerprint of the extended key itself
+    pub fingerprint: String,
+    pub parent_fingerprint: String,
+    pub depth: u8,
+    /// Origin path from the descriptor, or the standard account path for
+    /// the script type when the key sits at account depth; None otherwise
+    pub derivation_path: Option<String>,
+    /// Whether `derivation_path` came from the descriptor (vs. assumed)
+    pub path_from_descriptor: bool,
+    pub script_type: ScriptType,
+    /// Network implied by the key prefix (testnet covers signet/regtest)
+    pub network: Network,
+    /// False when the key belongs to a different network than the server
+    pub network_matches: bool,
+}
+
+/// Describe an xpub or single-key descriptor without deriving addresses
+///
+/// Unlike the derivation functions this does not fail on a network
+/// mismatch; `network_matches` reports it instead.
+pub fn xpub_info(input: &str, network: Network) -> Result<XpubInfo> {
+    let input = input.trim();
+
+    let (key_str, origin, script_type) = if input.contains('(') {
+        let source = parse_key_source(input, network_for(input)).map_err(|e| ErrorCode::InvalidXpub.error(format!("{:#}", e)))?;
+        let desc = input.split('#').next().unwrap_or("");
+
+        let origin = desc
+            .split_once('[')
+            .and_then(|(_, rest)| rest.split_once(']'))
+            .map(|(origin, _)| origin.to_string());
+
+        let key = desc
+            .rsplit([']', '('])
+            .next()
+            .and_then(|k| k.split('/').next())
+            .unwrap_or("");
+
+        (key.to_string(), origin, source.script_type)
+    } else {
+        (input.to_string(), None, detect_script_type(input))
+    };
+
+    let key_network = detect_network(&key_str)?;
+    let (xpub, _) = parse_extended_key(&key_str, key_network)?;
+
+    let (master_fingerprint, derivation_path, path_from_descriptor) = match origin {
+        Some(origin) => {
+            let (fp, path) = origin.split_once('/').unwrap_or((origin.as_str(), ""));
+            let path = if path.is_empty() {
+                "m".to_string()
+            } else {
+                format!("m/{}", path.replace('h', "'"))
+            };
+            (Some(fp.to_lowercase()), Some(path), true)
+        }
+        None => {
+            let master = match xpub.depth {
+                0 => Some(xpub.fingerprint().to_string()),
+                1 => Some(xpub.parent_fingerprint.to_string()),
+                _ => None,
+            };
+
+            let path = match xpub.child_number {
+                ChildNumber::Hardened { index } if xpub.depth == 3 => Some(format!(
+                    "m/{}'/{}'/{}'",
+                    script_type.purpose(),
+                    if key_network == Network::Bitcoin { 0 } else { 1 },
+                    index
+                )),
+                _ => None,
+            };
+
+            (master, path, false)
+        }
+    };
+
+    Ok(XpubInfo {
+        master_fingerprint,
+        fingerprint: xpub.fingerprint().to_string(),
+        parent_fingerprint: xpub.parent_fingerprint.to_string(),
+        depth: xpub.depth,
+        derivation_path,
+        path_from_descriptor,
+        script_type,
+        network: key_network,
+        network_matches: (key_network == Network::Bitcoin) == (network == Network::Bitcoin),
+    })
+}
+
+/// Network to parse a descriptor with so a mismatched key still parses
+fn network_for(descriptor: &str) -> Network {
+    if ["tpub", "upub", "vpub"].iter().any(|p| descriptor.contains(p)) {
+        Network::Testnet
+    } else {
+        Network::Bitcoin
+    }
+}
+
+/// A parsed xpub or descriptor: key, script type and the derivation path
+/// prefix of the receive (0) and change (1) chains
+struct KeySource {
+    xpub: Xpub,
+    script_type: ScriptType,
+    chains: [Vec<ChildNumber>; 2],
+}
 
 /// Derive addresses from an extended public key
 ///
 /// Supports xpub (mainnet), ypub/zpub (SegWit), tpub (testnet)
 /// Derives both external (receiving) and internal (change) addresses
 /// with a gap limit of 20 for each chain.
-pub fn derive_addresses(xpub_str: &str, gap_limit: u32) -> Result<Vec<String>> {
+pub fn derive_addresses(xpub_str: &str, network: Network, gap_limit: u32) -> Result<Vec<String>> {
     info!("Deriving addresses from xpub with gap_limit={}", gap_limit);
 
-    // Determine network from xpub prefix
-    let network = detect_network(xpub_str)?;
+    let mut addresses = Vec::new();
 
-    // Parse the xpub using bitcoin crate
-    let xpub = Xpub::from_str(xpub_str)
-        .context("Failed to parse extended public key")?;
+    // Derive external (receiving) addresses: m/0/0, m/0/1, ..., m/0/(gap_limit-1)
+    info!("Deriving external (receiving) addresses");
+    addresses.extend(derive_chain_addresses(xpub_str, network, 0, 0, gap_limit)?);
+
+    // Derive internal (change) addresses: m/1/0, m/1/1, ..., m/1/(gap_limit-1)
+    info!("Deriving internal (change) addresses");
+    addresses.extend(derive_chain_addresses(xpub_str, network, 1, 0, gap_limit)?);
+
+    info!("Derived {} addresses from xpub", addresses.len());
+
+    Ok(addresses)
+}
+
+/// Derive `count` addresses of one chain (0 = receive, 1 = change) starting at `start`
+pub fn derive_chain_addresses(
+    xpub_str: &str,
+    network: Network,
+    chain: u32,
+    start: u32,
+    count: u32,
+) -> Result<Vec<String>> {
+    let source = parse_key_source(xpub_str, network).map_err(|e| ErrorCode::InvalidXpub.error(format!("{:#}", e)))?;
+    let chain_path = source
+        .chains
+        .get(chain as usize)
+        .ok_or_else(|| anyhow!("Invalid chain {}", chain))?;
 
     // Create secp256k1 context for key operations
     let secp = Secp256k1::new();
 
     let mut addresses = Vec::new();
 
-    // Derive external (receiving) addresses: m/0/0, m/0/1, ..., m/0/(gap_limit-1)
-    info!("Deriving external (receiving) addresses");
-    for i in 0..gap_limit {
-        let path_str = format!("m/0/{}", i);
-        let path = DerivationPath::from_str(&path_str)
-            .context("Failed to create derivation path")?;
-        
-        match derive_address_from_path(&xpub, &path, network, &secp) {
+    for i in start..start.saturating_add(count) {
+        let mut children = chain_path.clone();
+        children.push(ChildNumber::from_normal_idx(i).context("Invalid address index")?);
+        let path = DerivationPath::from(children);
+
+        match derive_address_from_path(&source.xpub, &path, source.script_type, network, &secp) {
             Ok(addr) => {
-                addresses.push(addr);
+                addresses.push(addr.to_string());
             }
             Err(e) => {
-                warn!("Failed to derive address at path {}: {}", path_str, e);
+                warn!("Failed to derive address at path {}: {}", path, e);
                 break; // Stop if derivation fails
             }
         }
     }
 
-    // Derive internal (change) addresses: m/1/0, m/1/1, ..., m/1/(gap_limit-1)
-    info!("Deriving internal (change) addresses");
-    for i in 0..gap_limit {
-        let path_str = format!("m/1/{}", i);
-        let path = DerivationPath::from_str(&path_str)
-            .context("Failed to create derivation path")?;
-        
-        match derive_address_from_path(&xpub, &path, network, &secp) {
-            Ok(addr) => {
-                addresses.push(addr);
-            }
-            Err(e) => {
-                warn!("Failed to derive address at path {}: {}", path_str, e);
-                break; // Stop if derivation fails
+    Ok(addresses)
+}
+
+/// Derive one address from an extended key or output descriptor
+///
+/// Accepts plain keys (script type from the SLIP-132 prefix, path
+/// `<change>/<index>`) and single-key descriptors such as
+/// `wpkh([d34db33f/84'/0'/0']xpub.../0/*)`. For descriptors, `change`
+/// picks the second branch of a `<0;1>` multipath, or swaps a trailing
+/// `/0/*` for `/1/*`.
+pub fn derive_address(descriptor: &str, network: Network, change: bool, index: u32) -> Result<Address> {
+    let source = parse_key_source(descriptor, network).map_err(|e| ErrorCode::InvalidXpub.error(format!("{:#}", e)))?;
+
+    let mut children = source.chains[change as usize].clone();
+    children.push(ChildNumber::from_normal_idx(index).context("Invalid address index")?);
+
+    derive_address_from_path(
+        &source.xpub,
+        &DerivationPath::from(children),
+        source.script_type,
+        network,
+        &Secp256k1::new(),
+    )
+}
+
+/// Result of a gap-limit address discovery
+#[derive(Debug, Clone, Default)]
+pub struct Discovery {
+    /// Addresses with any transaction history, in derivation order
+    pub used_addresses: Vec<String>,
+    /// Index to resume scanning from (one past the last used address)
+    pub next_index: u32,
+}
+
+/// Discovery results for both chains of an account
+#[derive(Debug, Clone)]
+pub struct WalletDiscovery {
+    /// External chain (`0/*`)
+    pub receive: Discovery,
+    /// Internal chain (`1/*`)
+    pub change: Discovery,
+}
+
+/// Gap-limit address discovery (BIP-44) on the receive and change chains
+///
+/// Derives addresses one at a time, asks Electrs whether each has any
+/// history, and stops a chain after `gap_limit` consecutive unused ones.
+/// Each chain keeps its own gap counter and resume index.
+pub async fn discover_addresses(
+    electrs: &ElectrsClient,
+    xpub_str: &str,
+    receive_start: u32,
+    change_start: u32,
+    gap_limit: u32,
+) -> Result<WalletDiscovery> {
+    let receive = discover_chain(electrs, xpub_str, 0, receive_start, gap_limit).await?;
+    let change = discover_chain(electrs, xpub_str, 1, change_start, gap_limit).await?;
+
+    Ok(WalletDiscovery { receive, change })
+}
+
+/// Gap-limit discovery on a single chain (0 = receive, 1 = change)
+pub async fn discover_chain(
+    electrs: &ElectrsClient,
+    xpub_str: &str,
+    chain: u32,
+    start_index: u32,
+    gap_limit: u32,
+) -> Result<Discovery> {
+    discover_chain_with_progress(electrs, xpub_str, chain, start_index, gap_limit, |_, _| {}).await
+}
+
+/// `discover_chain` that reports every scanned address as `(address, used)`
+pub async fn discover_chain_with_progress(
+    electrs: &ElectrsClient,
+    xpub_str: &str,
+    chain: u32,
+    start_index: u32,
+    gap_limit: u32,
+    mut on_scanned: impl FnMut(&str, bool),
+) -> Result<Discovery> {
+    let mut discovery = Discovery {
+        used_addresses: Vec::new(),
+        next_index: start_index,
+    };
+
+    let mut index = start_index;
+    let mut unused_run = 0;
+
+    while unused_run < gap_limit {
+        let address = derive_chain_addresses(xpub_str, electrs.network(), chain, index, 1)?
+            .pop()
+            .ok_or_else(|| anyhow!("Failed to derive address at m/{}/{}", chain, index))?;
+
+        let history = electrs.get_address_txs(&address).await?;
+        on_scanned(&address, !history.is_empty());
+
+        if history.is_empty() {
+            unused_run += 1;
+        } else {
+            debug!("Discovered used address m/{}/{}: {}", chain, index, address);
+            discovery.used_addresses.push(address);
+            discovery.next_index = index + 1;
+            unused_run = 0;
+        }
+
+        index += 1;
+    }
+
+    info!(
+        "Discovery on chain {} finished: used={} next_index={}",
+        chain,
+        discovery.used_addresses.len(),
+        discovery.next_index
+    );
+
+    Ok(discovery)
+}
+
+/// Parse an xpub/ypub/zpub/tpub/upub/vpub string
+///
+/// SLIP-132 prefixes are converted to plain xpub/tpub version bytes so the
+/// key can be parsed, and the script type they encode is returned alongside.
+/// The prefix must match `network`: mainnet keys on mainnet, testnet keys
+/// (tpub/upub/vpub) on testnet, signet and regtest.
+pub fn parse_extended_key(xpub_str: &str, network: Network) -> Result<(Xpub, ScriptType)> {
+    // Determine network from xpub prefix
+    if xpub_str.starts_with("Ypub") || xpub_str.starts_with("Zpub")
+        || xpub_str.starts_with("Upub") || xpub_str.starts_with("Vpub")
+    {
+        return Err(anyhow!(
+            "Multisig extended keys ({}) are not supported; use a single-key xpub or descriptor",
+            xpub_str.get(0..4).unwrap_or("")
+        ));
+    }
+
+    let prefix_network = detect_network(xpub_str)?;
+    let script_type = detect_script_type(xpub_str);
+
+    if (prefix_network == Network::Bitcoin) != (network == Network::Bitcoin) {
+        return Err(anyhow!(
+            "Extended key prefix '{}' does not match configured network {}",
+            xpub_str.get(0..4).unwrap_or(""),
+            network
+        ));
+    }
+
+    let mut data = base58::decode_check(xpub_str)
+        .map_err(|e| anyhow!("Invalid extended public key encoding: {}", e))?;
+
+    if data.len() != 78 {
+        return Err(anyhow!("Invalid extended public key length: {}", data.len()));
+    }
+
+    let version = match prefix_network {
+        Network::Bitcoin => XPUB_VERSION,
+        _ => TPUB_VERSION,
+    };
+    data[0..4].copy_from_slice(&version);
+
+    // Parse the xpub using bitcoin crate
+    let xpub = Xpub::decode(&data)
+        .context("Failed to parse extended public key")?;
+
+    Ok((xpub, script_type))
+}
+
+/// Parse a plain extended key or a single-key output descriptor
+fn parse_key_source(input: &str, network: Network) -> Result<KeySource> {
+    let input = input.trim();
+
+    if !input.contains('(') {
+        let (xpub, script_type) = parse_extended_key(input, network)?;
+        return Ok(KeySource {
+            xpub,
+            script_type,
+            chains: [vec![ChildNumber::from(0)], vec![ChildNumber::from(1)]],
+        });
+    }
+
+    // Drop the optional "#checksum"
+    let desc = input.split('#').next().unwrap_or("");
+
+    let (script_type, key_expr) = [
+        ("sh(wpkh(", "))", ScriptType::P2shP2wpkh),
+        ("wpkh(", ")", ScriptType::P2wpkh),
+        ("pkh(", ")", ScriptType::P2pkh),
+        ("tr(", ")", ScriptType::P2tr),
+    ]
+    .iter()
+    .find_map(|(open, close, script_type)| {
+        desc.strip_prefix(open)
+            .and_then(|rest| rest.strip_suffix(close))
+            .map(|key| (*script_type, key))
+    })
+    .ok_or_else(|| anyhow!("Unsupported descriptor (expected pkh, sh(wpkh), wpkh or tr)"))?;
+
+    // Skip the key origin "[fingerprint/path]"
+    let key_expr = match key_expr.strip_prefix('[') {
+        Some(rest) => rest
+            .split_once(']')
+            .map(|(_, key)| key)
+            .ok_or_else(|| anyhow!("Unterminated key origin in descriptor"))?,
+        None => key_expr,
+    };
+
+    let (key, suffix) = key_expr.split_once('/').unwrap_or((key_expr, ""));
+    let (xpub, _) = parse_extended_key(key, network)?;
+
+    Ok(KeySource {
+        xpub,
+        script_type,
+        chains: parse_descriptor_path(suffix)?,
+    })
+}
+
+/// Turn a descriptor key path like `0/*` or `<0;1>/*` into the receive and
+/// change chain prefixes (without the final wildcard index)
+fn parse_descriptor_path(suffix: &str) -> Result<[Vec<ChildNumber>; 2]> {
+    let parts: Vec<&str> = suffix.split('/').collect();
+
+    let Some((&"*", fixed)) = parts.split_last() else {
+        return Err(anyhow!("Descriptor key must end in an unhardened /* wildcard"));
+    };
+
+    let normal = |p: &str| -> Result<ChildNumber> {
+        let idx: u32 = p
+            .parse()
+            .map_err(|_| anyhow!("Unsupported descriptor path step '{}'", p))?;
+        Ok(ChildNumber::from_normal_idx(idx)?)
+    };
+
+    let mut receive = Vec::new();
+    let mut change = Vec::new();
+    let mut multipath = false;
+
+    for part in fixed {
+        if let Some(inner) = part.strip_prefix('<').and_then(|p| p.strip_suffix('>')) {
+            let (a, b) = inner
+                .split_once(';')
+                .ok_or_else(|| anyhow!("Multipath step must be <a;b>"))?;
+            if multipath {
+                return Err(anyhow!("Only one multipath step is supported"));
             }
+            multipath = true;
+            receive.push(normal(a)?);
+            change.push(normal(b)?);
+        } else {
+            let child = normal(part)?;
+            receive.push(child);
+            change.push(child);
         }
     }
 
-    info!("Derived {} addresses from xpub", addresses.len());
+    // Conventional ".../0/*" receive descriptor: change lives at ".../1/*"
+    if !multipath && receive.last() == Some(&ChildNumber::from(0)) {
+        if let Some(last) = change.last_mut() {
+            *last = ChildNumber::from(1);
+        }
+    }
 
-    Ok(addresses)
+    Ok([receive, change])
 }
 
 /// Detect Bitcoin network from xpub prefix
 fn detect_network(xpub_str: &str) -> Result<Network> {
     let prefix = xpub_str.get(0..4).unwrap_or("");
-    
+
     match prefix {
-        "xpub" => Ok(Network::Bitcoin),
-        "tpub" => Ok(Network::Testnet),
-        "ypub" | "zpub" => {
-            // ypub/zpub can be mainnet or testnet, default to mainnet
-            // In a real implementation, you might want to check more carefully
-            Ok(Network::Bitcoin)
-        }
+        "xpub" | "ypub" | "zpub" => Ok(Network::Bitcoin),
+        "tpub" | "upub" | "vpub" => Ok(Network::Testnet),
         _ => {
             // Default to mainnet, but warn
             warn!("Unknown xpub prefix '{}', defaulting to mainnet", prefix);
@@ -90,45 +503,293 @@ fn detect_network(xpub_str: &str) -> Result<Network> {
     }
 }
 
+/// Detect the address type encoded by an xpub prefix
+fn detect_script_type(xpub_str: &str) -> ScriptType {
+    match xpub_str.get(0..4).unwrap_or("") {
+        "ypub" | "upub" => ScriptType::P2shP2wpkh,
+        "zpub" | "vpub" => ScriptType::P2wpkh,
+        _ => ScriptType::P2pkh,
+    }
+}
+
 /// Derive a single address from xpub and derivation path
 fn derive_address_from_path(
     xpub: &Xpub,
     path: &DerivationPath,
+    script_type: ScriptType,
     network: Network,
     secp: &Secp256k1<bitcoin::secp256k1::All>,
-) -> Result<String> {
+) -> Result<Address> {
     // Derive the public key at this path
     let child_xpub = xpub.derive_pub(secp, path)
         .context("Failed to derive child key")?;
 
     // Get the public key - in bitcoin 0.32, Xpub.public_key is a field
     let secp_pubkey = child_xpub.public_key;
-    
-    // Convert secp256k1::PublicKey to bitcoin::PublicKey
-    // bitcoin::PublicKey::new() takes secp256k1::PublicKey
-    let bitcoin_pubkey = bitcoin::PublicKey::new(secp_pubkey);
 
-    // Convert to Bitcoin address (P2PKH for legacy, P2WPKH for SegWit)
-    // For now, use P2PKH - in production you'd detect address type from xpub prefix
-    let address = bitcoin::Address::p2pkh(&bitcoin_pubkey, network);
-    
-    Ok(address.to_string())
+    let address = match script_type {
+        ScriptType::P2pkh => Address::p2pkh(bitcoin::PublicKey::new(secp_pubkey), network),
+        ScriptType::P2shP2wpkh => Address::p2shwpkh(&CompressedPublicKey(secp_pubkey), network),
+        ScriptType::P2wpkh => Address::p2wpkh(&CompressedPublicKey(secp_pubkey), network),
+        ScriptType::P2tr => Address::p2tr(secp, XOnlyPublicKey::from(secp_pubkey), None, network),
+    };
+
+    Ok(address)
 }
 
-/// Check if a string looks like an extended public key
+/// Check if a string looks like an extended public key or key descriptor
 pub fn is_xpub(query: &str) -> bool {
-    query.starts_with("xpub")
+    ["pkh(", "wpkh(", "sh(wpkh(", "tr("]
+        .iter()
+        .any(|p| query.starts_with(p))
+        || query.starts_with("xpub")
         || query.starts_with("ypub")
         || query.starts_with("zpub")
         || query.starts_with("tpub")
+        || query.starts_with("upub")
+        || query.starts_with("vpub")
 }
 
 /// Check if a string looks like a Bitcoin address
 pub fn is_bitcoin_address(query: &str) -> bool {
-    // Basic check - starts with 1, 3, bc1, or tb1
+    // Basic check - starts with 1, 3, or a bech32/bech32m (incl. Taproot)
+    // prefix in either case
+    let lower = query.to_ascii_lowercase();
     query.starts_with('1')
         || query.starts_with('3')
-        || query.starts_with("bc1")
-        || query.starts_with("tb1")
+        || lower.starts_with("bc1")
+        || lower.starts_with("tb1")
+        || lower.starts_with("bcrt1")
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::electrs::mock::{scripthash, MockElectrum, Reply};
+    use serde_json::json;
+
+    /// BIP84 test vector account: "abandon ... about", m/84'/0'/0'
+    const ZPUB: &str = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+    const RECEIVE: [&str; 3] = [
+        "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu",
+        "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g",
+        "bc1qp59yckz4ae5c4efgw2s5wfyvrz0ala7rgvuz8z",
+    ];
+    const CHANGE: &str = "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el";
+    /// The same account as a multipath descriptor
+    const DESCRIPTOR: &str = "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*)";
+    const TXID: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+    /// BIP44 / BIP49 test vector accounts of the same mnemonic
+    const XPUB_44: &str = "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj";
+    const YPUB_49: &str = "ypub6Ww3ibxVfGzLrAH1PNcjyAWenMTbbAosGNB6VvmSEgytSER9azLDWCxoJwW7Ke7icmizBMXrzBx9979FfaHxHcrArf3zbeJJJUZPf663zsP";
+
+    // BIP86 test vectors, account 0 of the same mnemonic
+    const TR_DESCRIPTOR: &str = "tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*)";
+    const TR_RECEIVE: [&str; 2] = [
+        "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr",
+        "bc1p4qhjn9zdvkux4e44uhx8tc55attvtyu358kutcqkudyccelu0was9fqzwh",
+    ];
+    const TR_CHANGE: &str = "bc1p3qkhfews2uk44qtvauqyr2ttdsw7svhkl9nkm9s9c3x4ax5h60wqwruhk7";
+
+    /// `key` re-encoded with plain xpub version bytes, as descriptors use
+    fn as_xpub(key: &str) -> String {
+        let mut data = base58::decode_check(key).unwrap();
+        data[0..4].copy_from_slice(&XPUB_VERSION);
+        base58::encode_check(&data)
+    }
+
+    fn receive_and_change(key: &str) -> (String, String) {
+        let receive = derive_address(key, Network::Bitcoin, false, 0).unwrap().to_string();
+        let change = derive_address(key, Network::Bitcoin, true, 0).unwrap().to_string();
+        (receive, change)
+    }
+
+    /// Electrum server where only the `used` addresses have history
+    fn history_server(used: &[&str]) -> MockElectrum {
+        let used: Vec<String> = used.iter().map(|a| scripthash(a)).collect();
+
+        MockElectrum::start(move |method, params| match method {
+            "blockchain.scripthash.get_history" => {
+                let is_used = params[0].as_str().is_some_and(|sh| used.iter().any(|u| u == sh));
+                Reply::Result(if is_used {
+                    json!([{ "tx_hash": TXID, "height": 800_000 }])
+                } else {
+                    json!([])
+                })
+            }
+            _ => Reply::Error("unsupported"),
+        })
+    }
+
+    #[test]
+    fn zpub_derives_bip84_vectors() {
+        assert_eq!(derive_chain_addresses(ZPUB, Network::Bitcoin, 0, 0, 3).unwrap(), RECEIVE);
+        assert_eq!(derive_chain_addresses(ZPUB, Network::Bitcoin, 1, 0, 1).unwrap(), [CHANGE]);
+        assert_eq!(derive_chain_addresses(ZPUB, Network::Bitcoin, 0, 2, 1).unwrap(), [RECEIVE[2]]);
+    }
+
+    #[test]
+    fn descriptor_paths_split_receive_and_change() {
+        let n = ChildNumber::from;
+        let cases: [(&str, [Vec<ChildNumber>; 2]); 5] = [
+            ("0/*", [vec![n(0)], vec![n(1)]]),
+            ("<0;1>/*", [vec![n(0)], vec![n(1)]]),
+            ("2/<3;7>/*", [vec![n(2), n(3)], vec![n(2), n(7)]]),
+            ("1/*", [vec![n(1)], vec![n(1)]]),
+            ("*", [vec![], vec![]]),
+        ];
+
+        for (suffix, expected) in cases {
+            assert_eq!(parse_descriptor_path(suffix).unwrap(), expected, "{}", suffix);
+        }
+    }
+
+    #[test]
+    fn descriptor_paths_reject_unsupported_steps() {
+        for suffix in ["0/5", "0/*'", "0'/*", "0h/*", "<0;1>/<2;3>/*", "<0>/*", "x/*"] {
+            assert!(parse_descriptor_path(suffix).is_err(), "{}", suffix);
+        }
+    }
+
+    #[test]
+    fn descriptors_derive_the_same_addresses_as_their_keys() {
+        let wpkh = (RECEIVE[0].to_string(), CHANGE.to_string());
+        let cases = [
+            (ZPUB.to_string(), wpkh.clone()),
+            (DESCRIPTOR.to_string(), wpkh.clone()),
+            (format!("wpkh([73c5da0a/84h/0h/0h]{}/0/*)", as_xpub(ZPUB)), wpkh.clone()),
+            (format!("wpkh({}/0/*)#checksum", as_xpub(ZPUB)), wpkh),
+            (format!("pkh([73c5da0a/44'/0'/0']{}/0/*)", XPUB_44), receive_and_change(XPUB_44)),
+            (format!("sh(wpkh({}/<0;1>/*))", as_xpub(YPUB_49)), receive_and_change(YPUB_49)),
+        ];
+
+        for (key, expected) in cases {
+            assert_eq!(receive_and_change(&key), expected, "{}", key);
+        }
+    }
+
+    #[test]
+    fn keys_derive_bip44_and_bip49_vectors() {
+        assert_eq!(receive_and_change(XPUB_44).0, "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA");
+        assert_eq!(receive_and_change(YPUB_49).0, "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf");
+    }
+
+    #[test]
+    fn tr_derives_bip86_vectors() {
+        for (index, expected) in TR_RECEIVE.iter().enumerate() {
+            let address = derive_address(TR_DESCRIPTOR, Network::Bitcoin, false, index as u32).unwrap();
+            assert_eq!(address.to_string(), *expected);
+        }
+        assert_eq!(derive_chain_addresses(TR_DESCRIPTOR, Network::Bitcoin, 0, 0, 2).unwrap(), TR_RECEIVE);
+
+        let multipath = TR_DESCRIPTOR.replace("/0/*)", "/<0;1>/*)");
+        assert_eq!(receive_and_change(&multipath), (TR_RECEIVE[0].to_string(), TR_CHANGE.to_string()));
+        assert_eq!(receive_and_change(&TR_DESCRIPTOR.replace("/0/*)", "/1/*)")).0, TR_CHANGE);
+
+        let info = xpub_info(TR_DESCRIPTOR, Network::Bitcoin).unwrap();
+        assert_eq!(info.script_type, ScriptType::P2tr);
+        assert_eq!(info.master_fingerprint.as_deref(), Some("73c5da0a"));
+        assert_eq!(info.derivation_path.as_deref(), Some("m/86'/0'/0'"));
+
+        // The same seed's BIP84 account still derives its own vector
+        assert_eq!(derive_address(ZPUB, Network::Bitcoin, false, 0).unwrap().to_string(), RECEIVE[0]);
+    }
+
+    #[test]
+    fn tr_multipath_matches_separate_chains() {
+        let xpub = as_xpub(ZPUB);
+        let multipath = receive_and_change(&format!("tr({}/<0;1>/*)", xpub));
+
+        assert_eq!(multipath, receive_and_change(&format!("tr({}/0/*)", xpub)));
+        assert_eq!(multipath.1, receive_and_change(&format!("tr({}/1/*)", xpub)).0);
+        assert!(multipath.0.starts_with("bc1p") && multipath.1.starts_with("bc1p"));
+        assert_ne!(multipath.0, multipath.1);
+    }
+
+    #[test]
+    fn xpub_info_of_a_plain_account_key() {
+        let info = xpub_info(ZPUB, Network::Bitcoin).unwrap();
+
+        assert_eq!(info.script_type, ScriptType::P2wpkh);
+        assert_eq!(info.depth, 3);
+        assert_eq!(info.master_fingerprint, None);
+        assert_eq!(info.derivation_path.as_deref(), Some("m/84'/0'/0'"));
+        assert!(!info.path_from_descriptor);
+        assert_eq!(info.network, Network::Bitcoin);
+        assert!(info.network_matches);
+
+        assert!(!xpub_info(ZPUB, Network::Testnet).unwrap().network_matches);
+        assert_eq!(xpub_info(XPUB_44, Network::Bitcoin).unwrap().derivation_path.as_deref(), Some("m/44'/0'/0'"));
+    }
+
+    #[test]
+    fn xpub_info_of_a_descriptor_uses_its_origin() {
+        let info = xpub_info(DESCRIPTOR, Network::Bitcoin).unwrap();
+        let plain = xpub_info(ZPUB, Network::Bitcoin).unwrap();
+
+        assert_eq!(info.script_type, ScriptType::P2wpkh);
+        assert_eq!(info.master_fingerprint.as_deref(), Some("73c5da0a"));
+        assert_eq!(info.derivation_path.as_deref(), Some("m/84'/0'/0'"));
+        assert!(info.path_from_descriptor);
+        assert_eq!(info.fingerprint, plain.fingerprint);
+        assert_eq!(info.parent_fingerprint, plain.parent_fingerprint);
+
+        let sh = xpub_info(&format!("sh(wpkh([73C5DA0A/49h/0h/0h]{}/0/*))", as_xpub(YPUB_49)), Network::Bitcoin).unwrap();
+        assert_eq!(sh.script_type, ScriptType::P2shP2wpkh);
+        assert_eq!(sh.master_fingerprint.as_deref(), Some("73c5da0a"));
+        assert_eq!(sh.derivation_path.as_deref(), Some("m/49'/0'/0'"));
+    }
+
+    #[tokio::test]
+    async fn discovery_bridges_short_gaps_per_chain() {
+        let electrs = history_server(&[RECEIVE[0], RECEIVE[2], CHANGE]).client().unwrap();
+
+        let found = discover_addresses(&electrs, ZPUB, 0, 0, 2).await.unwrap();
+
+        assert_eq!(found.receive.used_addresses, [RECEIVE[0], RECEIVE[2]]);
+        assert_eq!(found.receive.next_index, 3);
+        assert_eq!(found.change.used_addresses, [CHANGE]);
+        assert_eq!(found.change.next_index, 1);
+    }
+
+    #[tokio::test]
+    async fn discovery_finds_funds_only_on_change() {
+        for key in [ZPUB, DESCRIPTOR] {
+            let electrs = history_server(&[CHANGE]).client().unwrap();
+
+            let found = discover_addresses(&electrs, key, 0, 0, 3).await.unwrap();
+
+            assert!(found.receive.used_addresses.is_empty(), "{}", key);
+            assert_eq!(found.receive.next_index, 0);
+            assert_eq!(found.change.used_addresses, [CHANGE], "{}", key);
+            assert_eq!(found.change.next_index, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn discovery_stops_at_the_gap_limit() {
+        let electrs = history_server(&[RECEIVE[0], RECEIVE[2]]).client().unwrap();
+
+        let found = discover_chain(&electrs, ZPUB, 0, 0, 1).await.unwrap();
+
+        assert_eq!(found.used_addresses, [RECEIVE[0]]);
+        assert_eq!(found.next_index, 1);
+    }
+
+    #[tokio::test]
+    async fn discovery_resumes_from_start_index() {
+        let electrs = history_server(&[RECEIVE[0]]).client().unwrap();
+
+        let found = discover_chain(&electrs, ZPUB, 0, 1, 2).await.unwrap();
+
+        assert!(found.used_addresses.is_empty());
+        assert_eq!(found.next_index, 1);
+    }
+
+    #[test]
+    fn invalid_xpub_is_coded() {
+        let err = derive_chain_addresses("xpubnotakey", Network::Bitcoin, 0, 0, 1).unwrap_err();
+        assert_eq!(ErrorCode::of(&err), ErrorCode::InvalidXpub);
+    }
+}